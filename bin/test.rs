@@ -5,11 +5,19 @@ use std::io::Write;
 use std::path::Path;
 use std::process::{exit, Command, ExitStatus, Output, Stdio};
 
-use libmc;
+// Programs reading input have their stdin in a `.in` file next to the program
+fn stdin_for(path: &Path) -> Stdio {
+    match fs::File::open(path.with_extension("in")) {
+        Ok(file) => Stdio::from(file),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Stdio::null(),
+        Err(err) => panic!("Unable to open input of {:?}: {:?}", path, err),
+    }
+}
 
 fn run_ocaml(file_path: &str) -> String {
     let ret: Output = Command::new("ocaml")
         .arg(file_path)
+        .stdin(stdin_for(Path::new(file_path)))
         .stderr(Stdio::null())
         .stdout(Stdio::piped())
         .spawn()
@@ -29,7 +37,12 @@ enum McError {
     RunError { exit_code: ExitStatus, stderr: String, stdout: String },
 }
 
-fn run_mc(file_path_str: &str) -> Result<String, McError> {
+struct McOutput {
+    stdout: String,
+    stderr: String,
+}
+
+fn run_mc(file_path_str: &str) -> Result<McOutput, McError> {
     let file_path = Path::new(file_path_str);
     let file_stem = file_path.file_stem().unwrap();
     let file_stem_str = file_stem.to_str().unwrap();
@@ -40,7 +53,8 @@ fn run_mc(file_path_str: &str) -> Result<String, McError> {
         return Err(McError::CompileError);
     }
 
-    let Output { status, stdout, stderr } = Command::new(&format!("_test/{}", file_stem_str))
+    let Output { status, stdout, stderr } = Command::new(format!("_test/{}", file_stem_str))
+        .stdin(stdin_for(file_path))
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
@@ -48,13 +62,14 @@ fn run_mc(file_path_str: &str) -> Result<String, McError> {
         .wait_with_output()
         .unwrap();
 
-    if !status.success() || !stderr.is_empty() {
-        let stdout = String::from_utf8(stdout).unwrap();
-        let stderr = String::from_utf8(stderr).unwrap();
+    let stdout = String::from_utf8(stdout).unwrap();
+    let stderr = String::from_utf8(stderr).unwrap();
+
+    if !status.success() {
         return Err(McError::RunError { exit_code: status, stdout, stderr });
     }
 
-    Ok(String::from_utf8(stdout).unwrap())
+    Ok(McOutput { stdout, stderr })
 }
 
 enum TestResult {
@@ -62,18 +77,43 @@ enum TestResult {
     Fail(String),
 }
 
+// Programs using functions not in OCaml have the expected output in a `.out` file next to the
+// program
+fn expected_output(path: &Path) -> String {
+    match fs::read_to_string(path.with_extension("out")) {
+        Ok(out) => out,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => run_ocaml(path.to_str().unwrap()),
+        Err(err) => panic!("Unable to read expected output of {:?}: {:?}", path, err),
+    }
+}
+
+// Programs printing to stderr have the expected stderr in a `.err` file next to the program. Other
+// programs should not print anything to stderr.
+fn expected_stderr(path: &Path) -> String {
+    match fs::read_to_string(path.with_extension("err")) {
+        Ok(err) => err,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => panic!("Unable to read expected stderr of {:?}: {:?}", path, err),
+    }
+}
+
 fn run_test(path: &Path) -> TestResult {
     let path_str = path.to_str().unwrap();
-    let ocaml_out = run_ocaml(path_str);
+    let expected_out = expected_output(path);
+    let expected_err = expected_stderr(path);
     match run_mc(path_str) {
-        Ok(mc_out) => {
-            if mc_out == ocaml_out {
+        Ok(McOutput { stdout, stderr }) => {
+            if stdout == expected_out && stderr == expected_err {
                 TestResult::Pass
             } else {
                 use std::fmt::Write;
                 let mut s = String::new();
-                writeln!(&mut s, "Expected: {:?}", ocaml_out).unwrap();
-                writeln!(&mut s, "Found:    {:?}", mc_out).unwrap();
+                writeln!(&mut s, "Expected: {:?}", expected_out).unwrap();
+                writeln!(&mut s, "Found:    {:?}", stdout).unwrap();
+                if stderr != expected_err {
+                    writeln!(&mut s, "Expected stderr: {:?}", expected_err).unwrap();
+                    writeln!(&mut s, "Found stderr:    {:?}", stderr).unwrap();
+                }
                 TestResult::Fail(s)
             }
         }
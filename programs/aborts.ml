@@ -0,0 +1,29 @@
+(* `failwith`, `assert` and `exit` that don't abort, and `exit 0` *)
+let rec safe_div x y = if y = 0 then failwith "division by zero" else x / y
+
+let rec sqrt_pos x = if x < 0.0 then failwith "negative" else sqrt x
+
+let rec find_index a n x i =
+  if i >= n then failwith "not found"
+  else if a.(i) = x then i
+  else find_index a n x (i + 1)
+
+let rec fact n =
+  assert (n >= 0);
+  if n = 0 then 1 else n * fact (n - 1)
+
+let () =
+  print_int (safe_div 10 3);
+  print_newline ();
+  print_float (sqrt_pos 16.0);
+  print_newline ();
+  let a = Array.make 5 0 in
+  a.(3) <- 7;
+  print_int (find_index a 5 7 0);
+  print_newline ();
+  print_int (fact 10);
+  print_newline ();
+  let x = if fact 3 = 6 then 1.5 else assert false in
+  print_float x;
+  print_newline ();
+  exit 0
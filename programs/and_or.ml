@@ -0,0 +1,23 @@
+let rec print_bool b = print_int (if b then 1 else 0) in
+let rec in_range x lo hi = lo <= x && x < hi in
+print_bool (in_range 5 0 10);
+print_bool (in_range 10 0 10);
+print_bool (in_range (-1) 0 10);
+print_newline ();
+let rec outside x lo hi = x < lo || hi <= x in
+print_bool (outside 5 0 10);
+print_bool (outside 10 0 10);
+print_newline ();
+(* Short-circuit: the array access is not evaluated *)
+let arr = Array.make 3 7 in
+let i = 5 in
+print_bool (i < 3 && arr.(i) = 7);
+print_bool (i >= 3 || arr.(i) = 7);
+print_newline ();
+(* && binds tighter than || *)
+print_bool (true || false && false);
+print_bool (not false && true);
+print_newline ();
+let rec count n = if n > 0 && (n mod 2 = 0 || n mod 3 = 0) then 1 + count (n - 1) else 0 in
+print_int (count 4);
+print_newline ()
@@ -0,0 +1,16 @@
+let rec dot (xs : float array) (ys : float array) (n : int) : float =
+  if n = 0 then 0.0 else xs.(n - 1) *. ys.(n - 1) +. dot xs ys (n - 1)
+in
+let (a : float array) = Array.make 3 1.5 in
+let b : float array = Array.make 3 2.0 in
+print_int (truncate (dot a b 3));
+print_newline ();
+let rec apply (f : int -> int) (x : int) : int = f x in
+print_int (apply (fun (x : int) -> x * 2) 21);
+print_newline ();
+let (p : int * bool) = (1, true) in
+let (i, b) = p in
+print_int (if b then i else 0);
+print_newline ();
+print_int ((3 : int) + 4);
+print_newline ()
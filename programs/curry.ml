@@ -0,0 +1,62 @@
+let rec add x y = x + y
+
+let rec fadd x y = x +. y
+
+let rec compose f g = fun x -> g (f x)
+
+let rec apply_twice f x = f (f x)
+
+let rec mix a x b = truncate (a *. float_of_int x) + b
+
+let rec adder n = fun x -> fun y -> n + x + y
+
+(* Function types don't depend on the number of arguments the functions take *)
+let rec ap f = (f 1) 2
+
+let rec fap f = f 1.5 2.0
+
+let rec fold f acc l = match l with [] -> acc | x :: l -> fold f (f acc x) l
+
+;;
+
+let inc = add 1 in
+print_int (inc 41);
+print_newline ();
+let half = fadd 0.5 in
+print_int (truncate (half 1.5 *. 10.0));
+print_newline ();
+let dbl_inc = compose inc (fun x -> x * 2) in
+print_int (dbl_inc 5);
+print_newline ();
+print_int (apply_twice (add 10) 1);
+print_newline ();
+let m = mix 2.5 in
+let m2 = m 4 in
+print_int (m2 1 + m 2 3);
+print_newline ();
+print_int (compose inc inc 1);
+print_newline ();
+print_int (adder 1 2 3);
+print_newline ();
+let a = adder 100 in
+print_int (a 20 3);
+print_newline ();
+let print_sum = compose (add 3) print_int in
+print_sum 4;
+print_newline ();
+print_int (ap add);
+print_newline ();
+print_int (truncate (fap fadd *. 2.0));
+print_newline ();
+print_int (fold (fun a -> fun x -> a + x) 0 [1; 2; 3]);
+print_newline ();
+print_int (fold (fun a x -> a * x) 1 [4; 5; 6]);
+print_newline ();
+print_int ((fun f -> f 1 2) (fun x -> fun y -> x + y));
+print_newline ();
+let (mul : int -> int -> int) = fun x -> fun y -> x * y in
+print_int (mul 6 7);
+print_newline ();
+List.iter (fun f -> print_int (f 10 3); print_newline ()) [add; mul; (fun x y -> x - y); adder 1];
+print_int (ap (adder 5) + ap (mix 2.0));
+print_newline ()
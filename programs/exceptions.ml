@@ -0,0 +1,113 @@
+(* Exceptions: early exit from recursion, exceptions with fields, re-raising from handlers and
+   exceptions not handled by an inner `try` *)
+exception Not_found
+exception Found of int
+exception Error of string * int
+
+let rec find a n x i =
+  if i >= n then raise Not_found
+  else if a.(i) = x then raise (Found i)
+  else find a n x (i + 1)
+
+let rec index a n x =
+  try find a n x 0 with
+  | Found i -> i
+  | Not_found -> -1
+
+let rec check x = if x < 0 then raise (Error ("negative", x)) else float_of_int x
+
+let rec safe_check x =
+  try check x with Error (msg, code) ->
+    print_string msg;
+    print_newline ();
+    float_of_int code
+
+let rec depth n = if n = 0 then raise (Found 0) else 1 + depth (n - 1)
+
+let () =
+  let a = Array.make 10 0 in
+  a.(7) <- 42;
+  print_int (index a 10 42);
+  print_newline ();
+  print_int (index a 10 5);
+  print_newline ();
+  print_float (safe_check 3);
+  print_newline ();
+  print_float (safe_check (-2));
+  print_newline ();
+  (* Not handled by the inner `try` *)
+  let r = try (try raise Not_found with Found i -> i) with Not_found -> 100 in
+  print_int r;
+  print_newline ();
+  (* Raised again by the handler *)
+  let r =
+    try (try depth 1000 with Found _ -> raise (Error ("again", 1))) with
+    | Error (_, code) -> code
+    | _ -> 0
+  in
+  print_int r;
+  print_newline ();
+  (* No exception raised *)
+  print_int (try 1 + 2 with _ -> 0);
+  print_newline ();
+  (* Handlers can be in loops *)
+  let n = ref 0 in
+  for i = 0 to 9 do
+    n := !n + (try if i mod 2 = 0 then raise (Found i) else 0 with Found j -> j)
+  done;
+  print_int !n;
+  print_newline ()
+;;
+
+(* Shadows `Found` *)
+exception Found of string
+
+;;
+
+print_string (try raise (Found "shadowed") with Found s -> s);
+print_newline ();
+print_int (index (Array.make 3 1) 3 1);
+print_newline ()
+;;
+
+(* Exceptions raised by the runtime system *)
+let rec zero u = 0
+
+let rec first s = if String.length s = 0 then assert false else String.get s 0
+
+let rec describe e =
+  match e with
+  | Failure msg -> print_string "Failure "; print_endline msg
+  | Invalid_argument msg -> print_string "Invalid_argument "; print_endline msg
+  | Division_by_zero -> print_endline "Division_by_zero"
+  | Match_failure (_, line, col) ->
+    print_string "Match_failure ";
+    print_int line;
+    print_char ' ';
+    print_int col;
+    print_newline ()
+  | Assert_failure (_, line, col) ->
+    print_string "Assert_failure ";
+    print_int line;
+    print_char ' ';
+    print_int col;
+    print_newline ()
+  | _ -> print_endline "other"
+
+let rec run f = try f (); print_endline "no exception" with e -> describe e
+
+let () =
+  print_int (try failwith "boom" with _ -> 3);
+  print_newline ();
+  run (fun u -> failwith "boom");
+  run (fun u -> print_int (1 / zero u));
+  run (fun u -> print_int (1 mod zero u));
+  run (fun u -> print_char (String.get "" 0));
+  run (fun u -> print_char (Char.chr 300));
+  run (fun u -> print_char (first ""));
+  run (fun u -> match zero u with 1 -> ());
+  run (fun u -> print_char (first "x"));
+  print_int (try 1 / zero () with Division_by_zero -> 4);
+  print_newline ();
+  print_string (try failwith "message" with Failure msg -> msg);
+  print_newline ()
@@ -0,0 +1,22 @@
+let rec apply f x = f x in
+let rec twice f = fun x -> f (f x) in
+let k = 10 in
+let scale = 1.5 in
+print_int (apply (fun x -> x + k) 5);
+print_newline ();
+print_int ((twice (fun x -> x * 3)) 2);
+print_newline ();
+print_int ((fun x y -> x - y) 10 3);
+print_newline ();
+let fscale = fun x -> x *. scale in
+print_int (truncate (fscale 4.0));
+print_newline ();
+let arr = Array.make 3 0 in
+let set = fun i v -> arr.(i) <- v in
+set 0 4;
+set 2 6;
+print_int (arr.(0) + arr.(1) + arr.(2));
+print_newline ();
+let f = if k > 5 then fun x -> x + 1 else fun x -> x - 1 in
+print_int (f 100);
+print_newline ()
@@ -0,0 +1,46 @@
+(* Top-level arrays shared by functions, like `globals.ml` of the MinCaml raytracer *)
+let n_objects = Array.make 1 0
+let objects = Array.make 10 (0, 0.0)
+let screen = Array.make 3 0.0
+
+let rec add_object id size =
+  objects.(n_objects.(0)) <- (id, size);
+  n_objects.(0) <- n_objects.(0) + 1
+
+let rec total_size i =
+  if i < n_objects.(0) then
+    let (_, size) = objects.(i) in
+    size +. total_size (i + 1)
+  else 0.0
+
+let rec move_screen dx dy dz =
+  screen.(0) <- screen.(0) +. dx;
+  screen.(1) <- screen.(1) +. dy;
+  screen.(2) <- screen.(2) +. dz
+
+let () =
+  add_object 1 1.5;
+  add_object 2 2.5;
+  add_object 3 0.25;
+  print_int n_objects.(0);
+  print_newline ();
+  print_float (total_size 0);
+  print_newline ();
+  move_screen 1.0 2.0 3.0;
+  move_screen 0.5 0.5 0.5;
+  print_float (screen.(0) +. screen.(1) +. screen.(2));
+  print_newline ()
+
+(* `a` is a local, `counts` is a global *)
+let counts =
+  let a = Array.make 2 0 in
+  a.(1) <- 5;
+  a
+
+let rec bump k = counts.(0) <- counts.(0) + k
+
+let () =
+  bump 3;
+  bump 4;
+  print_int (counts.(0) + counts.(1));
+  print_newline ()
@@ -0,0 +1,16 @@
+let rec pr x = print_int x; print_newline () in
+let rec fact n = if n <= 1 then 1 else n * fact (n - 1) in
+pr (fact 20);
+pr (6 * 7 + 1);
+pr (1 + 6 * 7);
+pr (100 / 7 / 2);
+pr (100 / (7 * 2));
+pr (100 mod 7);
+pr (- 7 / 2);
+pr (- 7 mod 2);
+pr (7 mod - 2);
+pr (6 * 7 mod 5);
+let minus_one = 0 - 1 in
+pr (7 / minus_one);
+pr (7 mod minus_one);
+pr (- 7 / minus_one)
@@ -0,0 +1,4 @@
+(* Reading a number from stdin (see `io-invalid.in`) when the input is not a number *)
+let () =
+  print_int (try read_int () with Failure msg -> print_string msg; 0);
+  print_newline ()
@@ -0,0 +1,26 @@
+(* Reading from stdin (see `io.in`) and printing to stderr (see `io.err`) *)
+let rec sum n = if n = 0 then 0 else read_int () + sum (n - 1)
+
+let () =
+  let n = read_int () in
+  print_int (sum n);
+  print_newline ();
+  let x = read_float () in
+  let y = read_float () in
+  print_float (x +. y);
+  print_newline ();
+  (* At the end of the input *)
+  print_int (try read_int () with End_of_file -> -1);
+  print_newline ();
+  print_float (try read_float () with End_of_file -> -1.0);
+  print_newline ();
+  prerr_int 42;
+  prerr_newline ();
+  prerr_int (-7);
+  prerr_char ' ';
+  prerr_float 2.5;
+  prerr_char ' ';
+  prerr_float 0.1;
+  prerr_newline ();
+  prerr_string "to ";
+  prerr_endline "stderr"
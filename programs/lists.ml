@@ -0,0 +1,36 @@
+let rec print_list l =
+  match l with
+  | [] -> print_newline ()
+  | [x] -> print_int x; print_newline ()
+  | x :: xs -> print_int x; print_string "; "; print_list xs
+
+let rec map f l = match l with [] -> [] | x :: xs -> f x :: map f xs
+
+let rec sum l = match l with [] -> 0.0 | x :: xs -> x +. sum xs
+
+let rec range i j = if i > j then [] else i :: range (i + 1) j
+
+let () =
+  let l = [1; 2; 3] in
+  print_list l;
+  print_list (List.rev l);
+  print_list (0 :: l);
+  print_list [];
+  print_int (List.length l);
+  print_newline ();
+  print_int (List.length []);
+  print_newline ();
+  print_list (map (fun x -> x * x) (range 1 10));
+  let fs = map float_of_int l in
+  print_int (truncate (sum fs *. 10.0));
+  print_newline ();
+  List.iter (fun x -> print_int (truncate (x *. 2.0)); print_newline ()) (List.rev fs);
+  List.iter print_string ["a"; "b"; "c"];
+  print_newline ();
+  let pairs = [(1, "one"); (2, "two")] in
+  List.iter (fun p -> let (n, s) = p in print_int n; print_string s) pairs;
+  print_newline ();
+  (match [[1; 2]; []] with
+   | (x :: _) :: [] :: [] -> print_int x
+   | _ -> print_int 0);
+  print_newline ()
@@ -0,0 +1,67 @@
+let sum = ref 0 in
+for i = 1 to 100 do
+  sum := !sum + i
+done;
+print_int !sum;
+print_newline ();
+for i = 5 downto 1 do
+  print_int i
+done;
+print_newline ();
+(* Empty ranges don't run the body *)
+for i = 1 to 0 do
+  print_int i
+done;
+for i = 0 downto 1 do
+  print_int i
+done;
+(* Deep loops don't use the stack *)
+let n = ref 0 in
+while !n < 10000000 do
+  n := !n + 1
+done;
+print_int !n;
+print_newline ();
+let arr = Array.make 10 0 in
+for i = 0 to 9 do
+  arr.(i) <- i * i
+done;
+let i = ref 0 in
+let total = ref 0 in
+while (if !i < 10 then arr.(!i) < 50 else false) do
+  total := !total + arr.(!i);
+  i := !i + 1
+done;
+print_int !total;
+print_newline ();
+let fs = Array.make 3 (fun x -> x) in
+for i = 0 to 2 do
+  fs.(i) <- (fun x -> x + i)
+done;
+print_int (fs.(0) 10 + fs.(1) 10 + fs.(2) 10);
+print_newline ();
+for i = 1 to 3 do
+  for j = 1 to i do
+    print_int j
+  done;
+  print_string " "
+done;
+print_newline ();
+let x = 1.5 in
+let f = ref 0.0 in
+for i = 1 to 4 do
+  f := !f +. x
+done;
+print_int (truncate !f);
+print_newline ();
+(* Loops ending at the largest or smallest OCaml int don't step past the end *)
+let big = 4611686018427387903 in
+let n = ref 0 in
+for i = big - 2 to big do
+  n := !n + 1
+done;
+for i = 0 - big downto 0 - big - 1 do
+  n := !n + 1
+done;
+print_int !n;
+print_newline ()
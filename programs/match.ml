@@ -0,0 +1,38 @@
+let rec fib n =
+  match n with
+  | 0 -> 0
+  | 1 -> 1
+  | n -> fib (n - 1) + fib (n - 2) in
+let rec classify p =
+  match p with
+  | (0, 0) -> 0
+  | (x, 0) when x > 0 -> 1
+  | (_, 0) -> 2
+  | (0, _) -> 3
+  | (x, y) when x = y -> 4
+  | _ -> 5 in
+let rec sum3 t =
+  match t with
+  | (a, (b, c)) -> a + b + c in
+let rec norm_sign f =
+  match f >= 0.0, f with
+  | true, x -> x
+  | false, x -> -. x in
+let rec unit_arm u = match u with () -> 7 in
+print_int (fib 10);
+print_newline ();
+print_int (classify (0, 0));
+print_int (classify (5, 0));
+print_int (classify (-5, 0));
+print_int (classify (0, 3));
+print_int (classify (2, 2));
+print_int (classify (2, 3));
+print_newline ();
+print_int (sum3 (1, (20, 300)));
+print_newline ();
+print_int (truncate (norm_sign (-2.5) +. norm_sign 1.5));
+print_newline ();
+print_int (unit_arm ());
+print_newline ();
+print_int (match -3 with -3 -> 1 | _ -> 0);
+print_newline ()
@@ -0,0 +1,15 @@
+let rec print_scaled x = print_int (truncate (x *. 1000.0)); print_newline () in
+print_scaled (tan 0.5);
+print_scaled (atan 1.0);
+print_scaled (exp 1.0);
+print_scaled (log 10.0);
+print_scaled (floor 2.5);
+print_scaled (floor (-2.5));
+print_scaled (ceil 2.5);
+print_scaled (ceil (-2.5));
+print_scaled (Float.round 2.5);
+print_scaled (Float.round (-2.5));
+print_scaled (Float.trunc (-2.7));
+print_scaled (Float.exp 0.0 +. Float.log 1.0);
+print_int (int_of_float (Float.round 3.7) + int_of_float (Float.floor 3.7) + int_of_float (Float.ceil 3.2));
+print_newline ()
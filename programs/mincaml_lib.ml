@@ -0,0 +1,25 @@
+(* Functions of the original MinCaml library. These are not in OCaml, so the expected output is in
+   mincaml_lib.out. *)
+let rec print_bool b = print_int (if b then 1 else 0) in
+print_bool (fless 1.0 2.0);
+print_bool (fless 2.0 1.0);
+print_bool (fequal 0.5 0.5);
+print_bool (fequal 0.5 0.25);
+print_newline ();
+print_bool (fispos 1.5);
+print_bool (fispos 0.0);
+print_bool (fisneg (-1.5));
+print_bool (fisneg 0.0);
+print_bool (fiszero 0.0);
+print_bool (fiszero (-0.5));
+print_newline ();
+print_bool (xor true false);
+print_bool (xor true true);
+print_bool (xor false false);
+print_newline ();
+print_int (truncate (fsqr 3.0 +. fhalf 5.0 +. fneg 0.5));
+print_newline ();
+print_int (truncate (fabs (-2.5) *. 2.0));
+print_newline ();
+List.iter (fun f -> print_bool (f 1.0 2.0)) [fless; fequal];
+print_newline ()
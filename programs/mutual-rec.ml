@@ -0,0 +1,25 @@
+let rec even n = if n = 0 then true else odd (n - 1)
+and odd n = if n = 0 then false else even (n - 1)
+
+let rec pr_bool b = if b then print_int 1 else print_int 0; print_newline ()
+
+let () = pr_bool (even 10); pr_bool (odd 10); pr_bool (even 7)
+
+(* A state machine counting 'a b' pairs, with captured variables of different types *)
+let () =
+  let input = Array.make 10 0 in
+  input.(1) <- 1; input.(2) <- 0; input.(3) <- 1; input.(7) <- 1;
+  let weight = 2.5 in
+  let rec state_a i acc =
+    if i = 10 then acc else
+    if input.(i) = 0 then state_b (i + 1) acc else state_a (i + 1) acc
+  and state_b i acc =
+    if i = 10 then acc else
+    if input.(i) = 1 then state_a (i + 1) (acc +. weight) else state_b (i + 1) acc
+  in
+  print_int (truncate (state_a 0 0.0)); print_newline ()
+
+let () =
+  let x = 1.5 in
+  let rec f y = truncate (x +. y) in
+  print_int (f 2.0); print_newline ()
@@ -0,0 +1,59 @@
+type shape = Circle of float | Square of int
+
+let rec id x = x
+
+let rec compose f g = fun x -> g (f x)
+
+let rec fst p = let (a, _) = p in a
+
+let rec snd p = let (_, b) = p in b
+
+let rec swap p = (snd p, fst p)
+
+let rec map_array f n a =
+  let b = Array.make n (f a.(0)) in
+  for i = 1 to n - 1 do
+    b.(i) <- f a.(i)
+  done;
+  b
+
+let rec eq x y = x = y
+
+let rec area s =
+  match s with
+  | Circle r -> truncate (3.0 *. r *. r)
+  | Square n -> n * n
+
+;;
+
+print_int (id 1);
+print_newline ();
+print_int (truncate (id 2.5 *. 2.0));
+print_newline ();
+if id true then print_string "true\n" else print_string "false\n";
+if eq "ab" ("a" ^ "b") then print_string "true\n" else print_string "false\n";
+if eq 1 2 then print_string "true\n" else print_string "false\n";
+let p = swap (1.5, 3) in
+print_int (fst p + truncate (snd p *. 2.0));
+print_newline ();
+let to_int = compose float_of_int (fun x -> truncate (x *. 10.0)) in
+print_int (to_int 4);
+print_newline ();
+let to_float = compose (fun x -> x + 1) float_of_int in
+let square = compose to_float (fun x -> x *. x) in
+print_int (truncate (square 3));
+print_newline ();
+let shapes = Array.make 3 (Circle 1.0) in
+shapes.(1) <- Square 3;
+shapes.(2) <- Circle 2.0;
+let areas = map_array area 3 shapes in
+for i = 0 to 2 do
+  print_int areas.(i);
+  print_newline ()
+done;
+let ints = Array.make 3 1 in
+ints.(1) <- 2;
+ints.(2) <- 3;
+let halves = map_array (fun x -> float_of_int x /. 2.0) 3 ints in
+print_int (truncate (halves.(0) +. halves.(1) +. halves.(2)));
+print_newline ()
@@ -0,0 +1,20 @@
+let rec p x = print_float x; print_newline () in
+p 1.0;
+p (-2.0);
+p 0.1;
+p 3.14159265358979;
+p (1.0 /. 3.0);
+p 100000000000000000000.0;
+p 0.00000015;
+p 123456789012345.0;
+p 0.0;
+p (-0.0);
+p (1.0 /. 0.0);
+p (-1.0 /. 0.0);
+print_char 'a';
+print_char '\n';
+print_endline "done";
+print_float 2.5;
+print_string " ";
+print_float 100.0;
+print_newline ()
@@ -0,0 +1,8 @@
+let a = Array.make 3 (Float.of_int 2) in
+let b = Array.make 2 0 in
+b.(1) <- Float.to_int (Float.sqrt 16.0);
+print_int (Float.to_int (a.(0) +. a.(2)) + b.(1));
+print_newline ();
+let f = Float.abs in
+print_int (truncate (f (-3.5) +. Float.cos 0.0 +. Float.sin 0.0));
+print_newline ()
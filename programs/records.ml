@@ -0,0 +1,40 @@
+type vec = { x : float; y : float; z : float }
+
+type particle = { mutable pos : vec; vel : vec; mutable steps : int }
+
+let rec add a b = { x = a.x +. b.x; y = a.y +. b.y; z = a.z +. b.z }
+
+let rec scale k v = { z = k *. v.z; y = k *. v.y; x = k *. v.x }
+
+let rec dot a b = a.x *. b.x +. a.y *. b.y +. a.z *. b.z
+
+let rec step p dt =
+  p.pos <- add p.pos (scale dt p.vel);
+  p.steps <- p.steps + 1
+
+;;
+
+let p = { pos = { x = 0.0; y = 1.0; z = 2.0 }; vel = { x = 1.0; y = 0.5; z = -1.0 }; steps = 0 } in
+for i = 1 to 4 do
+  step p 0.5
+done;
+print_int p.steps;
+print_newline ();
+print_int (truncate (p.pos.x *. 10.0));
+print_newline ();
+print_int (truncate (p.pos.y *. 10.0));
+print_newline ();
+print_int (truncate (p.pos.z *. 10.0));
+print_newline ();
+print_int (truncate (dot p.pos p.vel *. 100.0));
+print_newline ()
+;;
+
+(* Shadows `steps` and `x` *)
+type stats = { steps : float; x : int }
+
+;;
+
+let s = { steps = 1.5; x = 2 } in
+print_int (truncate (s.steps *. 2.0) + s.x);
+print_newline ()
@@ -0,0 +1,25 @@
+let sum = ref 0 in
+let rec loop i = if i > 10 then () else (sum := !sum + i; loop (i + 1)) in
+loop 1;
+print_int !sum;
+print_newline ();
+let (f : float ref) = ref 1.5 in
+f := !f *. 2.0;
+print_int (truncate !f);
+print_newline ();
+let counter = ref 0 in
+let rec next u = counter := !counter + 1; !counter in
+let a = next () in
+let b = next () in
+print_int (a * 10 + b);
+print_newline ();
+let cells = Array.make 2 (ref 0) in
+cells.(1) <- ref 5;
+cells.(0) := 3;
+print_int (!(cells.(0)) + !(cells.(1)));
+print_newline ();
+let p = ref (1, 2) in
+p := (3, 4);
+let (x, y) = !p in
+print_int (x + y);
+print_newline ()
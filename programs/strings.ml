@@ -0,0 +1,19 @@
+let hello = "Hello" in
+let s = hello ^ ", " ^ "world!\n" in
+print_string s;
+print_int (String.length s);
+print_newline ();
+print_int (Char.code (String.get s 4));
+print_newline ();
+let rec count_char s c i =
+  if i = String.length s then 0
+  else if String.get s i = c then 1 + count_char s c (i + 1)
+  else count_char s c (i + 1)
+in
+print_int (count_char s 'l' 0);
+print_newline ();
+print_string (if hello = "Hel" ^ "lo" then "equal\n" else "not equal\n");
+print_string (if "abc" < "abd" then "less\n" else "not less\n");
+print_string (if "ab" < "a" then "less\n" else "not less\n");
+print_int (Char.code (Char.chr 65));
+print_string "\tTab\\Quote\"\n"
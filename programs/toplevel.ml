@@ -0,0 +1,15 @@
+(* Top-level definitions without `in` *)
+
+let rec fib n = if n <= 1 then n else fib (n - 1) + fib (n - 2)
+
+let x = fib 20
+let (a, b) = (x, x + 1)
+
+let () = print_int a; print_newline ()
+let _ = print_int b; print_newline ();;
+
+print_int (a * 2); print_newline ();;
+
+let rec pr n = print_int n; print_newline ()
+let y = let z = 10 in z * z;;
+let () = pr y
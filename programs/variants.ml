@@ -0,0 +1,71 @@
+type expr =
+  | Num of int
+  | Flt of float
+  | Add of expr * expr
+  | Mul of expr * expr
+  | Neg of expr
+  | Zero
+
+type shape = Circle of float | Rect of float * float | Dot
+
+let rec eval e =
+  match e with
+  | Num n -> n
+  | Flt f -> truncate f
+  | Add (a, b) -> eval a + eval b
+  | Mul (a, b) -> eval a * eval b
+  | Neg (Neg a) -> eval a
+  | Neg a -> 0 - eval a
+  | Zero -> 0
+
+let rec size e =
+  match e with
+  | Add (a, b) -> 1 + size a + size b
+  | Mul (a, b) -> 1 + size a + size b
+  | Neg a -> 1 + size a
+  | _ -> 1
+
+let rec area s =
+  match s with
+  | Circle r -> 3.0 *. r *. r
+  | Rect (w, h) -> w *. h
+  | Dot -> 0.0
+
+let rec is_small s =
+  match s with
+  | Rect (w, _) when w < 1.0 -> true
+  | Rect _ -> false
+  | _ -> true
+
+let e = Add (Num 3, Mul (Neg (Neg (Num 4)), Add (Flt 2.5, Zero)))
+
+;;
+
+print_int (eval e);
+print_newline ();
+print_int (eval (Neg e));
+print_newline ();
+print_int (size e);
+print_newline ();
+let shapes = Array.make 3 Dot in
+shapes.(0) <- Circle 2.0;
+shapes.(1) <- Rect (3.0, 0.5);
+for i = 0 to 2 do
+  print_int (truncate (area shapes.(i)));
+  print_string (if is_small shapes.(i) then " small" else " big");
+  print_newline ()
+done;
+print_int (match shapes.(2) with Dot -> 1 | _ -> 0);
+print_newline ()
+;;
+
+(* Shadows `Num` and `Dot` *)
+type token = Num of float | Dot
+
+;;
+
+let t = Num 1.5 in
+print_int (match t with Num f -> truncate (f *. 2.0) | Dot -> 0);
+print_newline ();
+print_int (eval (Add (e, e)));
+print_newline ()
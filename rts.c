@@ -1,11 +1,162 @@
 #include <inttypes.h>
 #include <math.h>
+#include <setjmp.h>
 #include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
 
 typedef struct FunctionClosure_ {
     void *function;
 } FunctionClosure;
 
+// Strings are allocated by generated code as the length followed by the bytes. There's always a 0
+// after the bytes, so `bytes` is also a C string (unless the string has 0 bytes in it).
+typedef struct String_ {
+    int64_t len;
+    char bytes[];
+} String;
+
+void mc_exit(int64_t code) {
+    fflush(stdout);
+    exit(code);
+}
+
+// Exceptions. `try` saves the context in a `jmp_buf` on the C stack, `raise` jumps to the innermost
+// one. Frames of the generated code between the two are discarded.
+
+// Innermost `try`, NULL if there isn't one
+static jmp_buf *mc_handler = NULL;
+
+// Exception being raised
+static int64_t mc_exn;
+
+// Names of the exception constructors, defined by generated code. Constructors without fields are
+// the integers `0 .. mc_exn_n_consts`, constructors with fields are blocks starting with the tag.
+extern const int64_t mc_exn_n_consts;
+extern const char *mc_exn_names[];
+
+// Exceptions raised by the runtime system, declared first in the `exn` type in this order by
+// `Ctx::add_builtin_exns`
+enum { TAG_MATCH_FAILURE, TAG_ASSERT_FAILURE, TAG_FAILURE, TAG_INVALID_ARGUMENT };
+enum { EXN_DIVISION_BY_ZERO, EXN_END_OF_FILE };
+
+// Report an uncaught exception, like OCaml. Fields of the exceptions of the runtime system are
+// printed, fields of other exceptions are not.
+static void report_exn(int64_t exn) {
+    if (exn < mc_exn_n_consts) {
+        fprintf(stderr, "Fatal error: exception %s\n", mc_exn_names[exn]);
+        return;
+    }
+    int64_t *block = (int64_t *)exn;
+    const char *name = mc_exn_names[mc_exn_n_consts + block[0]];
+    switch (block[0]) {
+    case TAG_MATCH_FAILURE:
+    case TAG_ASSERT_FAILURE:
+        fprintf(stderr, "Fatal error: exception %s(\"%s\", %" PRId64 ", %" PRId64 ")\n", name,
+                ((String *)block[1])->bytes, block[2], block[3]);
+        break;
+    case TAG_FAILURE:
+    case TAG_INVALID_ARGUMENT:
+        fprintf(stderr, "Fatal error: exception %s(\"%s\")\n", name, ((String *)block[1])->bytes);
+        break;
+    default:
+        fprintf(stderr, "Fatal error: exception %s\n", name);
+    }
+}
+
+// `raise exn`. Uncaught exceptions exit with code 2, like in OCaml. Unlike OCaml we also print the
+// file and the line of the `raise` when `file` is not NULL.
+void mc_raise(int64_t exn, const char *file, int64_t line) {
+    if (mc_handler == NULL) {
+        fflush(stdout);
+        report_exn(exn);
+        if (file != NULL) {
+            fprintf(stderr, "Raised at file \"%s\", line %" PRId64 "\n", file, line);
+        }
+        exit(2);
+    }
+    mc_exn = exn;
+    longjmp(*mc_handler, 1);
+}
+
+static String *alloc_string(const char *bytes) {
+    int64_t len = strlen(bytes);
+    String *s = malloc(sizeof(String) + len + 1);
+    s->len = len;
+    memcpy(s->bytes, bytes, len + 1);
+    return s;
+}
+
+// Raise an exception of the runtime system with a string field, or a string field and a location
+static void raise_with_msg(int64_t tag, String *msg, const char *file, int64_t line) {
+    int64_t *exn = malloc(2 * sizeof(int64_t));
+    exn[0] = tag;
+    exn[1] = (int64_t)msg;
+    mc_raise((int64_t)exn, file, line);
+}
+
+static void raise_with_loc(int64_t tag, const char *file, int64_t line, int64_t col) {
+    int64_t *exn = malloc(4 * sizeof(int64_t));
+    exn[0] = tag;
+    exn[1] = (int64_t)alloc_string(file);
+    exn[2] = line;
+    exn[3] = col;
+    mc_raise((int64_t)exn, NULL, 0);
+}
+
+// Called by generated code on integer division or modulo by zero
+void mc_div_by_zero() {
+    mc_raise(EXN_DIVISION_BY_ZERO, NULL, 0);
+}
+
+static void invalid_argument(const char *msg) {
+    raise_with_msg(TAG_INVALID_ARGUMENT, alloc_string(msg), NULL, 0);
+}
+
+// Called by generated code when no arm of a `match` matches, with the location of the `match`
+void mc_match_failure(const char *file, int64_t line, int64_t col) {
+    raise_with_loc(TAG_MATCH_FAILURE, file, line, col);
+}
+
+// `failwith msg`. OCaml doesn't print the location without backtraces, we print the file and the
+// line of the `failwith` expression when the exception is not caught.
+void mc_failwith(const char *file, int64_t line, String *msg) {
+    raise_with_msg(TAG_FAILURE, msg, file, line);
+}
+
+// Called by generated code when an `assert` fails
+void mc_assert_failure(const char *file, int64_t line, int64_t col) {
+    raise_with_loc(TAG_ASSERT_FAILURE, file, line, col);
+}
+
+// `try`, with closures of the body and the handler. The body is called with unit, the handler
+// with the exception raised by the body.
+int64_t mc_try(FunctionClosure *body, FunctionClosure *handler) {
+    jmp_buf *prev = mc_handler;
+    jmp_buf buf;
+    if (setjmp(buf) == 0) {
+        mc_handler = &buf;
+        int64_t ret = ((int64_t(*)(FunctionClosure *, int64_t))body->function)(body, 0);
+        mc_handler = prev;
+        return ret;
+    }
+    mc_handler = prev;
+    return ((int64_t(*)(FunctionClosure *, int64_t))handler->function)(handler, mc_exn);
+}
+
+double mc_try_float(FunctionClosure *body, FunctionClosure *handler) {
+    jmp_buf *prev = mc_handler;
+    jmp_buf buf;
+    if (setjmp(buf) == 0) {
+        mc_handler = &buf;
+        double ret = ((double (*)(FunctionClosure *, int64_t))body->function)(body, 0);
+        mc_handler = prev;
+        return ret;
+    }
+    mc_handler = prev;
+    return ((double (*)(FunctionClosure *, int64_t))handler->function)(handler, mc_exn);
+}
+
 // int return type because we don't support not returning! Unit is 0.
 int64_t mc_print_int_f(FunctionClosure *self, int64_t i) {
     printf("%" PRId64, i);
@@ -21,6 +172,97 @@ int64_t mc_print_newline_f(FunctionClosure *self, int64_t i) {
 
 FunctionClosure mc_print_newline = { .function = &mc_print_newline_f };
 
+// Like OCaml's `string_of_float`: 12 significant digits, with a `.` at the end when the number
+// would otherwise look like an integer
+static void output_float(FILE *out, double d) {
+    char buf[32];
+    snprintf(buf, sizeof(buf), "%.12g", d);
+    fputs(buf, out);
+    for (char *c = buf; *c != 0; c++) {
+        if (*c != '-' && (*c < '0' || *c > '9')) {
+            return;
+        }
+    }
+    fputc('.', out);
+}
+
+int64_t mc_print_float_f(FunctionClosure *self, double d) {
+    output_float(stdout, d);
+    return 0;
+}
+
+FunctionClosure mc_print_float = { .function = &mc_print_float_f };
+
+// Also `print_byte`. Chars are represented as their codes, so the only difference is the type.
+int64_t mc_print_char_f(FunctionClosure *self, int64_t c) {
+    putchar((unsigned char)c);
+    return 0;
+}
+
+FunctionClosure mc_print_char = { .function = &mc_print_char_f };
+
+int64_t mc_prerr_int_f(FunctionClosure *self, int64_t i) {
+    fprintf(stderr, "%" PRId64, i);
+    return 0;
+}
+
+FunctionClosure mc_prerr_int = { .function = &mc_prerr_int_f };
+
+int64_t mc_prerr_newline_f(FunctionClosure *self, int64_t i) {
+    fputc('\n', stderr);
+    return 0;
+}
+
+FunctionClosure mc_prerr_newline = { .function = &mc_prerr_newline_f };
+
+int64_t mc_prerr_float_f(FunctionClosure *self, double d) {
+    output_float(stderr, d);
+    return 0;
+}
+
+FunctionClosure mc_prerr_float = { .function = &mc_prerr_float_f };
+
+// Also `prerr_byte`
+int64_t mc_prerr_char_f(FunctionClosure *self, int64_t c) {
+    fputc((unsigned char)c, stderr);
+    return 0;
+}
+
+FunctionClosure mc_prerr_char = { .function = &mc_prerr_char_f };
+
+// Raise OCaml's exception when `scanf` fails to read a value
+static void check_read(int ret, const char *fun) {
+    if (ret == 1) {
+        return;
+    }
+    if (ret == EOF) {
+        mc_raise(EXN_END_OF_FILE, NULL, 0);
+    }
+    raise_with_msg(TAG_FAILURE, alloc_string(fun), NULL, 0);
+}
+
+// Unlike OCaml's `read_int` and `read_float`, which read a line, these skip whitespace and read
+// one number, like the original MinCaml library. This allows reading multiple numbers in a line.
+// Like OCaml, standard output is flushed before reading.
+
+int64_t mc_read_int_f(FunctionClosure *self, int64_t unit) {
+    int64_t i;
+    fflush(stdout);
+    check_read(scanf(" %" SCNd64, &i), "int_of_string");
+    return i;
+}
+
+FunctionClosure mc_read_int = { .function = &mc_read_int_f };
+
+double mc_read_float_f(FunctionClosure *self, int64_t unit) {
+    double d;
+    fflush(stdout);
+    check_read(scanf(" %lf", &d), "float_of_string");
+    return d;
+}
+
+FunctionClosure mc_read_float = { .function = &mc_read_float_f };
+
 double mc_float_of_int_f(FunctionClosure *self, int64_t i) {
     return (double)i;
 }
@@ -59,3 +301,126 @@ double mc_cos_f(FunctionClosure *self, double d) {
 }
 
 FunctionClosure mc_cos = { .function = &mc_cos_f };
+
+// Functions of the original MinCaml library that are not in libm, declared with `external` in
+// `src/prelude/stdlib.ml`. Booleans are 0 and 1.
+
+double mc_fneg(double d) {
+    return -d;
+}
+
+double mc_fsqr(double d) {
+    return d * d;
+}
+
+double mc_fhalf(double d) {
+    return d / 2.0;
+}
+
+int64_t mc_fless(double d1, double d2) {
+    return d1 < d2;
+}
+
+int64_t mc_fequal(double d1, double d2) {
+    return d1 == d2;
+}
+
+int64_t mc_fispos(double d) {
+    return d > 0.0;
+}
+
+int64_t mc_fisneg(double d) {
+    return d < 0.0;
+}
+
+int64_t mc_fiszero(double d) {
+    return d == 0.0;
+}
+
+int64_t mc_xor(int64_t b1, int64_t b2) {
+    return b1 != b2;
+}
+
+int64_t mc_print_string_f(FunctionClosure *self, String *s) {
+    fwrite(s->bytes, 1, s->len, stdout);
+    return 0;
+}
+
+FunctionClosure mc_print_string = { .function = &mc_print_string_f };
+
+int64_t mc_print_endline_f(FunctionClosure *self, String *s) {
+    fwrite(s->bytes, 1, s->len, stdout);
+    putchar('\n');
+    return 0;
+}
+
+FunctionClosure mc_print_endline = { .function = &mc_print_endline_f };
+
+int64_t mc_prerr_string_f(FunctionClosure *self, String *s) {
+    fwrite(s->bytes, 1, s->len, stderr);
+    return 0;
+}
+
+FunctionClosure mc_prerr_string = { .function = &mc_prerr_string_f };
+
+int64_t mc_prerr_endline_f(FunctionClosure *self, String *s) {
+    fwrite(s->bytes, 1, s->len, stderr);
+    fputc('\n', stderr);
+    return 0;
+}
+
+FunctionClosure mc_prerr_endline = { .function = &mc_prerr_endline_f };
+
+String *mc_string_concat_f(FunctionClosure *self, String *s1, String *s2) {
+    int64_t len = s1->len + s2->len;
+    String *s = malloc(sizeof(String) + len + 1);
+    s->len = len;
+    memcpy(s->bytes, s1->bytes, s1->len);
+    memcpy(s->bytes + s1->len, s2->bytes, s2->len);
+    s->bytes[len] = 0;
+    return s;
+}
+
+FunctionClosure mc_string_concat = { .function = &mc_string_concat_f };
+
+int64_t mc_string_length_f(FunctionClosure *self, String *s) {
+    return s->len;
+}
+
+FunctionClosure mc_string_length = { .function = &mc_string_length_f };
+
+int64_t mc_string_get_f(FunctionClosure *self, String *s, int64_t i) {
+    if (i < 0 || i >= s->len) {
+        invalid_argument("index out of bounds");
+    }
+    return (unsigned char)s->bytes[i];
+}
+
+FunctionClosure mc_string_get = { .function = &mc_string_get_f };
+
+int64_t mc_string_compare_f(FunctionClosure *self, String *s1, String *s2) {
+    int64_t len = s1->len < s2->len ? s1->len : s2->len;
+    int ret = memcmp(s1->bytes, s2->bytes, len);
+    if (ret != 0) {
+        return ret < 0 ? -1 : 1;
+    }
+    return s1->len < s2->len ? -1 : (s1->len > s2->len ? 1 : 0);
+}
+
+FunctionClosure mc_string_compare = { .function = &mc_string_compare_f };
+
+// Chars are represented as their codes
+int64_t mc_char_code_f(FunctionClosure *self, int64_t c) {
+    return c;
+}
+
+FunctionClosure mc_char_code = { .function = &mc_char_code_f };
+
+int64_t mc_char_chr_f(FunctionClosure *self, int64_t i) {
+    if (i < 0 || i > 255) {
+        invalid_argument("Char.chr");
+    }
+    return i;
+}
+
+FunctionClosure mc_char_chr = { .function = &mc_char_chr_f };
@@ -1,37 +1,85 @@
 use crate::ast;
 use crate::common::*;
 use crate::ctx::{Ctx, TypeId, VarId};
-use crate::type_check::Type;
+use crate::exhaustiveness::check_match;
+use crate::span::Span;
+use crate::type_check::{CtorRep, Type, EXN_TYPE};
 use crate::var::CompilerPhase;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Expr {
     Unit,
     Int(i64),
     Float(f64),
+    // String allocation
+    String(Vec<u8>),
     IBinOp(BinOp<IntBinOp>),
     FBinOp(BinOp<FloatBinOp>),
     Neg(VarId),
     FNeg(VarId),
-    If(VarId, VarId, Cmp, Box<Expr>, Box<Expr>),
-    Let { id: VarId, ty_id: TypeId, rhs: Box<Expr>, body: Box<Expr> },
+    If(Cond, Box<Expr>, Box<Expr>),
+    Let { id: VarId, rhs: Box<Expr>, body: Box<Expr> },
     Var(VarId),
-    LetRec { name: VarId, ty_id: TypeId, args: Vec<VarId>, rhs: Box<Expr>, body: Box<Expr> },
+    // Group of mutually recursive functions
+    LetRec { defs: Vec<FunDef>, body: Box<Expr> },
+    // Application to all arguments of the function. Functions defined with `let rec` and builtins
+    // take the number of arguments in their definitions, other functions take one argument.
     App(VarId, Vec<VarId>),
+    // Application of a function defined with `let rec` or a builtin to some of its arguments,
+    // possibly none. Allocates a closure that takes the rest of the arguments one at a time.
+    PartialApp(VarId, Vec<VarId>),
     // Tuple allocation
     Tuple(Vec<VarId>),
     // Tuple field read
     TupleGet(VarId, usize),
+    // Tuple field write. Evaluates to unit.
+    TuplePut(VarId, usize, VarId),
     // Array allocation
     ArrayAlloc { len: VarId, elem: VarId },
     // Array field read
     ArrayGet(VarId, VarId),
     // Array field write
     ArrayPut(VarId, VarId, VarId),
+    // Raise `Match_failure`, when no arm of a `match` matches. The span is the location of the
+    // `match`. Evaluates to unit.
+    MatchFailure(Span),
+    // `failwith`, `exit`, `raise`, or a failing `assert`. The span is the location of the
+    // expression, to be reported by the runtime system. Evaluates to unit.
+    Abort(Abort, Span),
+    // `try`, with closures for the body and the handler. The body is called with unit. When it
+    // raises an exception, the handler is called with the exception.
+    Try(VarId, VarId),
+    // `for` loop. `start` and `end` are evaluated once, before the loop. Evaluates to unit.
+    For { var: VarId, start: VarId, dir: ForDir, end: VarId, body: Box<Expr> },
+    // `while` loop. The condition is evaluated in every iteration. Evaluates to unit.
+    While { cond: Cond, body: Box<Expr> },
+}
+
+// Condition of an `if` or `while`. Conditions are compiled to branches to the `then` and `else`
+// branches of the `if` (the body and the continuation of the `while`), so `&&`, `||` and `not` in conditions don't materialize booleans.
+#[derive(Debug, Clone)]
+pub enum Cond {
+    // Compare two variables
+    Cmp(VarId, VarId, Cmp),
+    // Constant condition, e.g. the `false` of a desugared `&&`
+    Bool(bool),
+    // `if <cond> then <cond> else <cond>`
+    If(Box<Cond>, Box<Cond>, Box<Cond>),
+    // Bind a variable before testing the condition. The RHS is only evaluated when the condition
+    // is tested.
+    Let { id: VarId, rhs: Box<Expr>, body: Box<Cond> },
+}
+
+#[derive(Debug, Clone)]
+pub struct FunDef {
+    pub name: VarId,
+    pub ty_id: TypeId,
+    pub args: Vec<VarId>,
+    pub rhs: Expr,
 }
 
 enum TmpLet {
-    TmpLet { id: VarId, ty_id: TypeId, rhs: Box<Expr> },
+    TmpLet { id: VarId, rhs: Box<Expr> },
     NoNeed,
 }
 
@@ -39,7 +87,14 @@ impl TmpLet {
     fn finish(self, body: Expr) -> Expr {
         match self {
             TmpLet::NoNeed => body,
-            TmpLet::TmpLet { id, ty_id, rhs } => Expr::Let { id, ty_id, rhs, body: Box::new(body) },
+            TmpLet::TmpLet { id, rhs } => Expr::Let { id, rhs, body: Box::new(body) },
+        }
+    }
+
+    fn finish_cond(self, body: Cond) -> Cond {
+        match self {
+            TmpLet::NoNeed => body,
+            TmpLet::TmpLet { id, rhs } => Cond::Let { id, rhs, body: Box::new(body) },
         }
     }
 }
@@ -50,7 +105,218 @@ fn mk_let(ctx: &mut Ctx, e: Expr, ty_id: TypeId) -> (TmpLet, VarId) {
         _ => {
             let id = ctx.fresh_generated_var(CompilerPhase::ANormal);
             ctx.set_var_type(id, ty_id);
-            (TmpLet::TmpLet { id, ty_id, rhs: Box::new(e) }, id)
+            (TmpLet::TmpLet { id, rhs: Box::new(e) }, id)
+        }
+    }
+}
+
+// Value of an expression after an abort, to make the types work
+fn dummy_value(ctx: &Ctx, ty_id: TypeId) -> Expr {
+    match &*ctx.get_type(ty_id) {
+        Type::Unit => Expr::Unit,
+        Type::Float => Expr::Float(0.0),
+        _ => Expr::Int(0),
+    }
+}
+
+// `assert e` becomes `if e then () else assert false`. Other aborts evaluate to a value of type
+// `ty_id` after aborting.
+fn anormal_abort(
+    ctx: &mut Ctx, kind: ast::AbortKind, arg: ast::Expr, span: Span, ty_id: TypeId,
+) -> Expr {
+    let (tmp, abort) = match kind {
+        ast::AbortKind::Assert => {
+            if !matches!(arg.kind, ast::ExprKind::Bool(false)) {
+                let assert_false = ast::Expr {
+                    kind: ast::ExprKind::Abort(
+                        ast::AbortKind::Assert,
+                        Box::new(ast::Expr { kind: ast::ExprKind::Bool(false), span }),
+                    ),
+                    span,
+                };
+                return anormal(
+                    ctx,
+                    ast::Expr {
+                        kind: ast::ExprKind::If(
+                            Box::new(arg),
+                            Box::new(ast::Expr { kind: ast::ExprKind::Unit, span }),
+                            Box::new(assert_false),
+                        ),
+                        span,
+                    },
+                );
+            }
+            (TmpLet::NoNeed, Abort::AssertFailure)
+        }
+        ast::AbortKind::Failwith | ast::AbortKind::Exit | ast::AbortKind::Raise => {
+            let (arg, arg_ty_id) = anormal_(ctx, arg);
+            let (tmp, var) = mk_let(ctx, arg, arg_ty_id);
+            let abort = match kind {
+                ast::AbortKind::Failwith => Abort::Failwith(var),
+                ast::AbortKind::Exit => Abort::Exit(var),
+                _ => Abort::Raise(var),
+            };
+            (tmp, abort)
+        }
+    };
+
+    let abort_var = ctx.fresh_generated_var(CompilerPhase::ANormal);
+    let unit = ctx.unit_type_id();
+    ctx.set_var_type(abort_var, unit);
+    tmp.finish(Expr::Let {
+        id: abort_var,
+        rhs: Box::new(Expr::Abort(abort, span)),
+        body: Box::new(dummy_value(ctx, ty_id)),
+    })
+}
+
+// `try body with arms` becomes `Try(fun () -> body, fun exn -> match exn with arms)`. When the
+// arms are not exhaustive the handler raises the exceptions not matched by them again.
+fn anormal_try(
+    ctx: &mut Ctx, body: ast::Expr, mut arms: Vec<ast::Arm<VarId>>, span: Span,
+) -> (Expr, TypeId) {
+    let unit = ctx.unit_type_id();
+    let unit_var = ctx.fresh_generated_var(CompilerPhase::ANormal);
+    ctx.set_var_type(unit_var, unit);
+    let body_fun = ast::Expr {
+        kind: ast::ExprKind::Fun {
+            args: vec![unit_var],
+            arg_tys: vec![None],
+            body: Box::new(body),
+        },
+        span,
+    };
+    let (body_fun, body_fun_ty_id) = anormal_(ctx, body_fun);
+    let ret_ty = match &*ctx.get_type(body_fun_ty_id) {
+        Type::Fun { ret, .. } => (**ret).clone(),
+        other => panic!("Non-function type in Try: {:?}", other),
+    };
+    let ret_ty_id = ctx.intern_type(ret_ty);
+
+    let exn_ty_id = ctx.intern_type(Type::Variant(EXN_TYPE.into()));
+    let exn_var = ctx.fresh_generated_var(CompilerPhase::ANormal);
+    ctx.set_var_type(exn_var, exn_ty_id);
+    let arm_pats: Vec<(&ast::Pat<VarId>, bool)> = arms
+        .iter()
+        .map(|arm| (&arm.pat, arm.guard.is_some()))
+        .collect();
+    if check_match(ctx, &arm_pats).missing.is_some() {
+        let raise_var = ctx.fresh_generated_var(CompilerPhase::ANormal);
+        ctx.set_var_type(raise_var, ret_ty_id);
+        let raise = ast::ExprKind::Abort(
+            ast::AbortKind::Raise,
+            Box::new(ast::Expr { kind: ast::ExprKind::Var(exn_var), span }),
+        );
+        arms.push(ast::Arm {
+            pat: ast::Pat { kind: ast::PatKind::Wildcard, span },
+            guard: None,
+            rhs: ast::Expr {
+                kind: ast::ExprKind::Let {
+                    bndr: raise_var,
+                    rhs: Box::new(ast::Expr { kind: raise, span }),
+                    body: Box::new(ast::Expr { kind: ast::ExprKind::Var(raise_var), span }),
+                },
+                span,
+            },
+        });
+    }
+    let handler_body = ast::Expr {
+        kind: ast::ExprKind::Match {
+            scrut: Box::new(ast::Expr { kind: ast::ExprKind::Var(exn_var), span }),
+            arms,
+        },
+        span,
+    };
+    let handler_fun = ast::Expr {
+        kind: ast::ExprKind::Fun {
+            args: vec![exn_var],
+            arg_tys: vec![None],
+            body: Box::new(handler_body),
+        },
+        span,
+    };
+    let (handler_fun, handler_fun_ty_id) = anormal_(ctx, handler_fun);
+
+    let (body_tmp, body_var) = mk_let(ctx, body_fun, body_fun_ty_id);
+    let (handler_tmp, handler_var) = mk_let(ctx, handler_fun, handler_fun_ty_id);
+    (
+        body_tmp.finish(handler_tmp.finish(Expr::Try(body_var, handler_var))),
+        ret_ty_id,
+    )
+}
+
+fn finish_all(tmps: Vec<TmpLet>, body: Expr) -> Expr {
+    tmps.into_iter()
+        .rev()
+        .fold(body, |acc, tmp| tmp.finish(acc))
+}
+
+// Operands of a comparison. Strings are compared by their contents, so `s1 <cmp> s2` becomes
+// `String.compare s1 s2 <cmp> 0`.
+fn cmp_args(ctx: &mut Ctx, e1: ast::Expr, e2: ast::Expr) -> (Vec<TmpLet>, VarId, VarId) {
+    let (e1, e1_ty) = anormal_(ctx, e1);
+    let (tmp1, var1) = mk_let(ctx, e1, e1_ty);
+    let (e2, e2_ty) = anormal_(ctx, e2);
+    // assert_eq!(e1_ty, e2_ty);
+    let (tmp2, var2) = mk_let(ctx, e2, e2_ty);
+
+    if let Type::String = &*ctx.get_type(e1_ty) {
+        let int = ctx.int_type_id();
+        let compare = ctx.builtin_var("String.compare");
+        let (ord_tmp, ord_var) = mk_let(ctx, Expr::App(compare, vec![var1, var2]), int);
+        let (zero_tmp, zero_var) = mk_let(ctx, Expr::Int(0), int);
+        (vec![tmp1, tmp2, ord_tmp, zero_tmp], ord_var, zero_var)
+    } else {
+        (vec![tmp1, tmp2], var1, var2)
+    }
+}
+
+// Condition of an `if`. Comparisons are tested directly, and `&&`, `||` and boolean `if`s are
+// conditions with the conditions of the operands. Other expressions are compared with `true`.
+fn anormal_cond(ctx: &mut Ctx, expr: ast::Expr) -> Cond {
+    let span = expr.span;
+    match expr.kind {
+        ast::ExprKind::Bool(b) => Cond::Bool(b),
+
+        ast::ExprKind::Not(e) => Cond::If(
+            Box::new(anormal_cond(ctx, *e)),
+            Box::new(Cond::Bool(false)),
+            Box::new(Cond::Bool(true)),
+        ),
+
+        ast::ExprKind::Cmp(e1, cmp, e2) => {
+            let (tmps, var1, var2) = cmp_args(ctx, *e1, *e2);
+            tmps.into_iter()
+                .rev()
+                .fold(Cond::Cmp(var1, var2, cmp), |acc, tmp| tmp.finish_cond(acc))
+        }
+
+        ast::ExprKind::And(e1, e2) => Cond::If(
+            Box::new(anormal_cond(ctx, *e1)),
+            Box::new(anormal_cond(ctx, *e2)),
+            Box::new(Cond::Bool(false)),
+        ),
+
+        ast::ExprKind::Or(e1, e2) => Cond::If(
+            Box::new(anormal_cond(ctx, *e1)),
+            Box::new(Cond::Bool(true)),
+            Box::new(anormal_cond(ctx, *e2)),
+        ),
+
+        ast::ExprKind::If(cond, then_, else_) => Cond::If(
+            Box::new(anormal_cond(ctx, *cond)),
+            Box::new(anormal_cond(ctx, *then_)),
+            Box::new(anormal_cond(ctx, *else_)),
+        ),
+
+        ast::ExprKind::Annot { expr, .. } => anormal_cond(ctx, *expr),
+
+        kind => {
+            let int = ctx.int_type_id();
+            let e = anormal(ctx, ast::Expr { kind, span });
+            let (tmp, var) = mk_let(ctx, e, int);
+            let (true_tmp, true_var) = mk_let(ctx, Expr::Int(1), int);
+            tmp.finish_cond(true_tmp.finish_cond(Cond::Cmp(var, true_var, Cmp::Equal)))
         }
     }
 }
@@ -64,34 +330,49 @@ pub fn anormal_(ctx: &mut Ctx, expr: ast::Expr) -> (Expr, TypeId) {
     let float = ctx.float_type_id();
     let unit = ctx.unit_type_id();
 
-    match expr {
-        ast::Expr::Unit => (Expr::Unit, unit),
-        ast::Expr::Bool(b) => (Expr::Int(if b { 1 } else { 0 }), int),
-        ast::Expr::Int(i) => (Expr::Int(i), int),
-        ast::Expr::Float(f) => (Expr::Float(f), float),
+    let span = expr.span;
+    match expr.kind {
+        ast::ExprKind::Unit => (Expr::Unit, unit),
+        ast::ExprKind::Bool(b) => (Expr::Int(if b { 1 } else { 0 }), int),
+        ast::ExprKind::Int(i) => (Expr::Int(i), int),
+        ast::ExprKind::Float(f) => (Expr::Float(f), float),
+        ast::ExprKind::String(bytes) => (Expr::String(bytes), ctx.intern_type(Type::String)),
+        ast::ExprKind::Char(c) => (Expr::Int(i64::from(c)), ctx.intern_type(Type::Char)),
 
-        ast::Expr::Not(e) => anormal_(
+        ast::ExprKind::Not(e) => anormal_(
             ctx,
-            ast::Expr::If(
-                e,
-                Box::new(ast::Expr::Bool(false)),
-                Box::new(ast::Expr::Bool(true)),
-            ),
+            ast::Expr {
+                kind: ast::ExprKind::If(
+                    e,
+                    Box::new(ast::Expr { kind: ast::ExprKind::Bool(false), span }),
+                    Box::new(ast::Expr { kind: ast::ExprKind::Bool(true), span }),
+                ),
+                span,
+            },
         ),
 
-        ast::Expr::Neg(e) => {
+        // `e1 && e2` is `if e1 then e2 else false`, `e1 || e2` is `if e1 then true else e2`
+        kind @ (ast::ExprKind::And(_, _) | ast::ExprKind::Or(_, _)) => {
+            let cond = anormal_cond(ctx, ast::Expr { kind, span });
+            (
+                Expr::If(cond, Box::new(Expr::Int(1)), Box::new(Expr::Int(0))),
+                int,
+            )
+        }
+
+        ast::ExprKind::Neg(e) => {
             let e = anormal(ctx, *e);
             let (tmp, var) = mk_let(ctx, e, int);
             (tmp.finish(Expr::Neg(var)), int)
         }
 
-        ast::Expr::FNeg(e) => {
+        ast::ExprKind::FNeg(e) => {
             let e = anormal(ctx, *e);
             let (tmp, var) = mk_let(ctx, e, float);
             (tmp.finish(Expr::FNeg(var)), float)
         }
 
-        ast::Expr::IntBinOp(e1, op, e2) => {
+        ast::ExprKind::IntBinOp(e1, op, e2) => {
             let e1 = anormal(ctx, *e1);
             let (tmp1, arg1) = mk_let(ctx, e1, int);
             let e2 = anormal(ctx, *e2);
@@ -100,7 +381,7 @@ pub fn anormal_(ctx: &mut Ctx, expr: ast::Expr) -> (Expr, TypeId) {
             (e, int)
         }
 
-        ast::Expr::FloatBinOp(e1, op, e2) => {
+        ast::ExprKind::FloatBinOp(e1, op, e2) => {
             let e1 = anormal(ctx, *e1);
             let (tmp1, arg1) = mk_let(ctx, e1, float);
             let e2 = anormal(ctx, *e2);
@@ -109,98 +390,123 @@ pub fn anormal_(ctx: &mut Ctx, expr: ast::Expr) -> (Expr, TypeId) {
             (e, float)
         }
 
-        ast::Expr::Cmp(e1, cmp, e2) => {
-            let (e1, e1_ty) = anormal_(ctx, *e1);
-            let (tmp1, var1) = mk_let(ctx, e1, e1_ty);
-            let (e2, e2_ty) = anormal_(ctx, *e2);
-            // assert_eq!(e1_ty, e2_ty);
-            let (tmp2, var2) = mk_let(ctx, e2, e2_ty);
-            let e = tmp1.finish(tmp2.finish(Expr::If(
-                var1,
-                var2,
-                cmp,
-                Box::new(Expr::Int(1)),
-                Box::new(Expr::Int(0)),
-            )));
+        ast::ExprKind::Cmp(e1, cmp, e2) => {
+            let (tmps, var1, var2) = cmp_args(ctx, *e1, *e2);
+            let e = finish_all(
+                tmps,
+                Expr::If(
+                    Cond::Cmp(var1, var2, cmp),
+                    Box::new(Expr::Int(1)),
+                    Box::new(Expr::Int(0)),
+                ),
+            );
             (e, int)
         }
 
-        ast::Expr::If(box ast::Expr::Cmp(e1, cmp, e2), then_, else_) => {
-            let (e1, e1_ty) = anormal_(ctx, *e1);
-            let (tmp1, var1) = mk_let(ctx, e1, e1_ty);
-            let (e2, e2_ty) = anormal_(ctx, *e2);
-            // assert_eq!(e1_ty, e2_ty);
-            let (tmp2, var2) = mk_let(ctx, e2, e2_ty);
+        ast::ExprKind::If(cond, then_, else_) => {
+            let cond = anormal_cond(ctx, *cond);
             let (then_, ty) = anormal_(ctx, *then_);
             let else_ = anormal(ctx, *else_);
-            let e = tmp1.finish(tmp2.finish(Expr::If(
-                var1,
-                var2,
-                cmp,
-                Box::new(then_),
-                Box::new(else_),
-            )));
-            (e, ty)
-        }
-
-        ast::Expr::If(cond, then_, else_) => {
-            let cond = anormal(ctx, *cond);
-            let (cond_tmp, cond_var) = mk_let(ctx, cond, int);
-            let true_ = anormal(ctx, ast::Expr::Bool(true));
-            let (true_tmp, true_var) = mk_let(ctx, true_, int);
-            let (then_, ty) = anormal_(ctx, *then_);
-            let else_ = anormal(ctx, *else_);
-            let e = cond_tmp.finish(true_tmp.finish(Expr::If(
-                cond_var,
-                true_var,
-                Cmp::Equal,
-                Box::new(then_),
-                Box::new(else_),
-            )));
-            (e, ty)
+            (Expr::If(cond, Box::new(then_), Box::new(else_)), ty)
         }
 
-        ast::Expr::Let { bndr, rhs, body } => {
-            let (rhs, rhs_ty) = anormal_(ctx, *rhs);
+        // Bound to a variable by the type checker, see `ExprKind::Abort` in `type_check`
+        ast::ExprKind::Let {
+            bndr,
+            rhs: box ast::Expr { kind: ast::ExprKind::Abort(kind, arg), span },
+            body,
+        } => {
+            let rhs = anormal_abort(ctx, kind, *arg, span, ctx.var_type_id(bndr));
             let (body, body_ty) = anormal_(ctx, *body);
-            let e = Expr::Let { id: bndr, ty_id: rhs_ty, rhs: Box::new(rhs), body: Box::new(body) };
+            let e = Expr::Let { id: bndr, rhs: Box::new(rhs), body: Box::new(body) };
             (e, body_ty)
         }
 
-        ast::Expr::Var(var) => (Expr::Var(var), ctx.var_type_id(var)),
+        ast::ExprKind::Abort(kind, arg) => (anormal_abort(ctx, kind, *arg, span, unit), unit),
+
+        ast::ExprKind::Let { bndr, rhs, body } => {
+            let rhs = anormal(ctx, *rhs);
+            let (body, body_ty) = anormal_(ctx, *body);
+            let e = Expr::Let { id: bndr, rhs: Box::new(rhs), body: Box::new(body) };
+            (e, body_ty)
+        }
+
+        // As values, functions taking multiple arguments are closures taking one argument at a time
+        ast::ExprKind::Var(var) => {
+            let ty_id = ctx.var_type_id(var);
+            match ctx.fun_arity(var) {
+                Some(arity) if arity > 1 => (Expr::PartialApp(var, vec![]), ty_id),
+                _ => (Expr::Var(var), ty_id),
+            }
+        }
+
+        ast::ExprKind::LetRec { defs, body } => {
+            for def in &defs {
+                ctx.add_known_fun(def.bndr);
+            }
+            let defs = defs
+                .into_iter()
+                .map(|ast::FunDef { bndr, args, rhs, .. }| {
+                    let mut arg_tys: Vec<Type> = Vec::with_capacity(args.len());
+                    for arg in &args {
+                        arg_tys.push((*ctx.var_type(*arg)).clone());
+                    }
 
-        ast::Expr::LetRec { bndr, args, rhs, body } => {
+                    let (rhs, rhs_ty_id) = anormal_(ctx, rhs);
+                    let rhs_ty = (*ctx.get_type(rhs_ty_id)).clone();
+                    let fun_ty = Type::Fun { args: arg_tys, ret: Box::new(rhs_ty) };
+
+                    FunDef { name: bndr, ty_id: ctx.intern_type(fun_ty), args, rhs }
+                })
+                .collect();
+
+            let (body, body_ty) = anormal_(ctx, *body);
+
+            (Expr::LetRec { defs, body: Box::new(body) }, body_ty)
+        }
+
+        // Anonymous functions are named and closure converted as a `let rec` with one function
+        ast::ExprKind::Fun { args, body, .. } => {
             let mut arg_tys: Vec<Type> = Vec::with_capacity(args.len());
             for arg in &args {
-                arg_tys.push((&*ctx.var_type(*arg)).clone());
+                arg_tys.push((*ctx.var_type(*arg)).clone());
             }
 
-            let (rhs, rhs_ty_id) = anormal_(ctx, *rhs);
-            let rhs_ty = (&*ctx.get_type(rhs_ty_id)).clone();
-            let fun_ty = Type::Fun { args: arg_tys, ret: Box::new(rhs_ty) };
-            let (body, body_ty) = anormal_(ctx, *body);
+            let (rhs, rhs_ty_id) = anormal_(ctx, *body);
+            let rhs_ty = (*ctx.get_type(rhs_ty_id)).clone();
+            let ty_id = ctx.intern_type(Type::Fun { args: arg_tys, ret: Box::new(rhs_ty) });
 
-            let e = Expr::LetRec {
-                name: bndr,
-                ty_id: ctx.intern_type(fun_ty),
-                args,
-                rhs: Box::new(rhs),
-                body: Box::new(body),
-            };
+            let name = ctx.fresh_generated_var(CompilerPhase::ANormal);
+            ctx.set_var_type(name, ty_id);
+            ctx.add_known_fun(name);
 
-            (e, body_ty)
+            let value = if args.len() > 1 {
+                Expr::PartialApp(name, vec![])
+            } else {
+                Expr::Var(name)
+            };
+            let def = FunDef { name, ty_id, args, rhs };
+            (
+                Expr::LetRec { defs: vec![def], body: Box::new(value) },
+                ty_id,
+            )
         }
 
-        ast::Expr::App { fun, args } => {
-            let (fun, fun_ty_id) = anormal_(ctx, *fun);
-            let fun_ty = (&*ctx.get_type(fun_ty_id)).clone();
-            let ret_ty: Type = match &fun_ty {
-                Type::Fun { args: _, ret } => (**ret).clone(),
-                other => panic!("Non-function in function position: {:?} : {:?}", fun, other),
+        // Functions defined with `let rec` and builtins are called with the arguments they take,
+        // other functions are applied to one argument at a time
+        ast::ExprKind::App { fun, args } => {
+            let known_fun = match &fun.kind {
+                ast::ExprKind::Var(var) => ctx.fun_arity(*var).map(|arity| (*var, arity)),
+                _ => None,
+            };
+            let (fun_tmp, fun_id, arity) = match known_fun {
+                Some((var, arity)) => (TmpLet::NoNeed, var, arity),
+                None => {
+                    let (fun, fun_ty_id) = anormal_(ctx, *fun);
+                    let (fun_tmp, fun_id) = mk_let(ctx, fun, fun_ty_id);
+                    (fun_tmp, fun_id, 1)
+                }
             };
-            let ret_ty_id = ctx.intern_type(ret_ty);
-
-            let (fun_tmp, fun_id) = mk_let(ctx, fun, fun_ty_id);
 
             let mut arg_ids: Vec<VarId> = Vec::with_capacity(args.len());
             let mut arg_tmps: Vec<TmpLet> = Vec::with_capacity(args.len());
@@ -211,7 +517,35 @@ pub fn anormal_(ctx: &mut Ctx, expr: ast::Expr) -> (Expr, TypeId) {
                 arg_tmps.push(arg_tmp);
             }
 
-            let fun_call = fun_tmp.finish(Expr::App(fun_id, arg_ids));
+            // Apply the function to the arguments it takes, and the return value to the rest of
+            // the arguments
+            let mut call_tmps: Vec<TmpLet> = vec![];
+            let mut fun_id = fun_id;
+            let mut arity = arity;
+            let mut arg_ids = &arg_ids[..];
+            let (fun_call, ret_ty_id) = loop {
+                if arg_ids.len() < arity {
+                    let closure_ty = ctx.var_type(fun_id).apply(arg_ids.len());
+                    break (
+                        Expr::PartialApp(fun_id, arg_ids.to_vec()),
+                        ctx.intern_type(closure_ty),
+                    );
+                }
+                let (call_args, rest_args) = arg_ids.split_at(arity);
+                let call = Expr::App(fun_id, call_args.to_vec());
+                let ret_ty = ctx.var_type(fun_id).apply(arity);
+                let ret_ty_id = ctx.intern_type(ret_ty);
+                if rest_args.is_empty() {
+                    break (call, ret_ty_id);
+                }
+                let (call_tmp, ret_id) = mk_let(ctx, call, ret_ty_id);
+                call_tmps.push(call_tmp);
+                fun_id = ret_id;
+                arity = 1;
+                arg_ids = rest_args;
+            };
+
+            let fun_call = fun_tmp.finish(finish_all(call_tmps, fun_call));
 
             let e = arg_tmps
                 .into_iter()
@@ -221,14 +555,85 @@ pub fn anormal_(ctx: &mut Ctx, expr: ast::Expr) -> (Expr, TypeId) {
             (e, ret_ty_id)
         }
 
-        ast::Expr::Tuple(args) => {
+        ast::ExprKind::Match { scrut, arms } => {
+            let (scrut, scrut_ty_id) = anormal_(ctx, *scrut);
+            let (scrut_tmp, scrut_var) = mk_let(ctx, scrut, scrut_ty_id);
+
+            let arm_pats: Vec<(&ast::Pat<VarId>, bool)> = arms
+                .iter()
+                .map(|arm| (&arm.pat, arm.guard.is_some()))
+                .collect();
+            let exhaustive = check_match(ctx, &arm_pats).missing.is_none();
+
+            let mut ret_ty = unit;
+            let arms: Vec<(ast::Pat<VarId>, Option<Expr>, Expr)> = arms
+                .into_iter()
+                .map(|ast::Arm { pat, guard, rhs }| {
+                    let guard = guard.map(|guard| anormal(ctx, guard));
+                    let (rhs, rhs_ty) = anormal_(ctx, rhs);
+                    ret_ty = rhs_ty;
+                    (pat, guard, rhs)
+                })
+                .collect();
+
+            // Each arm falls through to the next one, the last one aborts the program. When the
+            // match is exhaustive and the last arm doesn't have a guard the last arm doesn't need
+            // to test the pattern.
+            let mut rest: Option<Expr> = if exhaustive && arms.last().unwrap().1.is_none() {
+                None
+            } else {
+                let failure_var = ctx.fresh_generated_var(CompilerPhase::ANormal);
+                ctx.set_var_type(failure_var, unit);
+                Some(Expr::Let {
+                    id: failure_var,
+                    rhs: Box::new(Expr::MatchFailure(span)),
+                    body: Box::new(dummy_value(ctx, ret_ty)),
+                })
+            };
+
+            for (pat, guard, rhs) in arms.into_iter().rev() {
+                rest = Some(match_arm(
+                    ctx,
+                    scrut_var,
+                    scrut_ty_id,
+                    &pat,
+                    guard,
+                    rhs,
+                    rest,
+                ));
+            }
+
+            (scrut_tmp.finish(rest.unwrap()), ret_ty)
+        }
+
+        ast::ExprKind::Try { body, arms } => anormal_try(ctx, *body, arms, span),
+
+        // The empty list is 0. The type checker binds `[]` to a variable of its type, so the type
+        // here is not used.
+        ast::ExprKind::Nil => (Expr::Int(0), int),
+
+        // Cons cells are tuples of the head and the tail
+        ast::ExprKind::Cons(hd, tl) => {
+            let (hd, hd_ty_id) = anormal_(ctx, *hd);
+            let list_ty = Type::List(Box::new((*ctx.get_type(hd_ty_id)).clone()));
+            let list_ty_id = ctx.intern_type(list_ty);
+            let (hd_tmp, hd_var) = mk_let(ctx, hd, hd_ty_id);
+            let tl = anormal(ctx, *tl);
+            let (tl_tmp, tl_var) = mk_let(ctx, tl, list_ty_id);
+            (
+                finish_all(vec![hd_tmp, tl_tmp], Expr::Tuple(vec![hd_var, tl_var])),
+                list_ty_id,
+            )
+        }
+
+        ast::ExprKind::Tuple(args) => {
             let mut arg_ids: Vec<VarId> = Vec::with_capacity(args.len());
             let mut arg_tmps: Vec<TmpLet> = Vec::with_capacity(args.len());
             let mut arg_tys: Vec<Type> = Vec::with_capacity(args.len());
 
             for arg in args {
                 let (arg, arg_ty_id) = anormal_(ctx, arg);
-                let arg_ty = (&*ctx.get_type(arg_ty_id)).clone();
+                let arg_ty = (*ctx.get_type(arg_ty_id)).clone();
                 let (arg_tmp, arg_id) = mk_let(ctx, arg, arg_ty_id);
                 arg_ids.push(arg_id);
                 arg_tmps.push(arg_tmp);
@@ -245,7 +650,7 @@ pub fn anormal_(ctx: &mut Ctx, expr: ast::Expr) -> (Expr, TypeId) {
             (e, ctx.intern_type(Type::Tuple(arg_tys)))
         }
 
-        ast::Expr::LetTuple { bndrs, rhs, body } => {
+        ast::ExprKind::LetTuple { bndrs, rhs, body } => {
             let (body, body_ty) = anormal_(ctx, *body);
 
             let (rhs, rhs_ty) = anormal_(ctx, *rhs);
@@ -255,24 +660,20 @@ pub fn anormal_(ctx: &mut Ctx, expr: ast::Expr) -> (Expr, TypeId) {
                 .into_iter()
                 .enumerate()
                 .rev()
-                .fold(body, |expr, (bndr_idx, bndr)| {
-                    let ty_id = ctx.var_type_id(bndr);
-                    Expr::Let {
-                        id: bndr,
-                        ty_id,
-                        rhs: Box::new(Expr::TupleGet(rhs_id, bndr_idx)),
-                        body: Box::new(expr),
-                    }
+                .fold(body, |expr, (bndr_idx, bndr)| Expr::Let {
+                    id: bndr,
+                    rhs: Box::new(Expr::TupleGet(rhs_id, bndr_idx)),
+                    body: Box::new(expr),
                 });
 
             (rhs_tmp.finish(e), body_ty)
         }
 
-        ast::Expr::Array { len, elem } => {
+        ast::ExprKind::Array { len, elem } => {
             let (len, len_ty_id) = anormal_(ctx, *len);
             assert_eq!(len_ty_id, int);
             let (elem, elem_ty_id) = anormal_(ctx, *elem);
-            let elem_ty = (&*ctx.get_type(elem_ty_id)).clone();
+            let elem_ty = (*ctx.get_type(elem_ty_id)).clone();
             let (len_tmp, len_id) = mk_let(ctx, len, len_ty_id);
             let (elem_tmp, elem_id) = mk_let(ctx, elem, elem_ty_id);
 
@@ -282,7 +683,7 @@ pub fn anormal_(ctx: &mut Ctx, expr: ast::Expr) -> (Expr, TypeId) {
             )
         }
 
-        ast::Expr::Get(e1, e2) => {
+        ast::ExprKind::Get(e1, e2) => {
             let (e1, e1_ty_id) = anormal_(ctx, *e1);
             let elem_ty = match &*ctx.get_type(e1_ty_id) {
                 Type::Array(elem) => (**elem).clone(),
@@ -298,7 +699,7 @@ pub fn anormal_(ctx: &mut Ctx, expr: ast::Expr) -> (Expr, TypeId) {
             (e, ctx.intern_type(elem_ty))
         }
 
-        ast::Expr::Put(e1, e2, e3) => {
+        ast::ExprKind::Put(e1, e2, e3) => {
             let (e1, e1_ty_id) = anormal_(ctx, *e1);
             // assert!(e1_ty.is_array());
             let (e2, e2_ty_id) = anormal_(ctx, *e2);
@@ -314,5 +715,324 @@ pub fn anormal_(ctx: &mut Ctx, expr: ast::Expr) -> (Expr, TypeId) {
 
             (e, unit)
         }
+
+        ast::ExprKind::Annot { expr, .. } => anormal_(ctx, *expr),
+
+        // References are one-field tuples
+        ast::ExprKind::Ref(e) => {
+            let (e, e_ty_id) = anormal_(ctx, *e);
+            let e_ty = (*ctx.get_type(e_ty_id)).clone();
+            let (tmp, var) = mk_let(ctx, e, e_ty_id);
+            (
+                tmp.finish(Expr::Tuple(vec![var])),
+                ctx.intern_type(Type::Ref(Box::new(e_ty))),
+            )
+        }
+
+        ast::ExprKind::Deref(e) => {
+            let (e, e_ty_id) = anormal_(ctx, *e);
+            let elem_ty = match &*ctx.get_type(e_ty_id) {
+                Type::Ref(elem) => (**elem).clone(),
+                other => panic!("Non-ref type in Deref: {:?}", other),
+            };
+            let (tmp, var) = mk_let(ctx, e, e_ty_id);
+            (tmp.finish(Expr::TupleGet(var, 0)), ctx.intern_type(elem_ty))
+        }
+
+        ast::ExprKind::Assign(e1, e2) => {
+            let (e1, e1_ty_id) = anormal_(ctx, *e1);
+            let (e2, e2_ty_id) = anormal_(ctx, *e2);
+            let (e1_tmp, e1_id) = mk_let(ctx, e1, e1_ty_id);
+            let (e2_tmp, e2_id) = mk_let(ctx, e2, e2_ty_id);
+            (
+                e1_tmp.finish(e2_tmp.finish(Expr::TuplePut(e1_id, 0, e2_id))),
+                unit,
+            )
+        }
+
+        ast::ExprKind::For { var, start, dir, end, body } => {
+            let start = anormal(ctx, *start);
+            let (start_tmp, start_var) = mk_let(ctx, start, int);
+            let end = anormal(ctx, *end);
+            let (end_tmp, end_var) = mk_let(ctx, end, int);
+            let body = anormal(ctx, *body);
+            let e = Expr::For { var, start: start_var, dir, end: end_var, body: Box::new(body) };
+            (start_tmp.finish(end_tmp.finish(e)), unit)
+        }
+
+        ast::ExprKind::While { cond, body } => {
+            let cond = anormal_cond(ctx, *cond);
+            let body = anormal(ctx, *body);
+            (Expr::While { cond, body: Box::new(body) }, unit)
+        }
+
+        ast::ExprKind::TypeDecl { body, .. }
+        | ast::ExprKind::External { body, .. }
+        | ast::ExprKind::Exception { body, .. } => anormal_(ctx, *body),
+
+        // Records are tuples of the fields in declaration order. Fields are evaluated in the order
+        // they're written.
+        ast::ExprKind::Record(fields) => {
+            let (decl, _) = ctx.get_field(&fields[0].field).unwrap();
+            let mut tmps: Vec<TmpLet> = Vec::with_capacity(fields.len());
+            let mut field_vars: Vec<(usize, VarId)> = Vec::with_capacity(fields.len());
+            for ast::FieldExpr { field, expr, .. } in fields {
+                let (_, field_idx) = ctx.get_field(&field).unwrap();
+                let (expr, expr_ty_id) = anormal_(ctx, expr);
+                let (tmp, var) = mk_let(ctx, expr, expr_ty_id);
+                tmps.push(tmp);
+                field_vars.push((field_idx, var));
+            }
+            field_vars.sort_by_key(|(field_idx, _)| *field_idx);
+            let tuple = Expr::Tuple(field_vars.into_iter().map(|(_, var)| var).collect());
+            (
+                finish_all(tmps, tuple),
+                ctx.intern_type(Type::Record(decl.name.clone())),
+            )
+        }
+
+        ast::ExprKind::GetField { expr, field } => {
+            let (decl, field_idx) = ctx.get_field(&field).unwrap();
+            let (expr, expr_ty_id) = anormal_(ctx, *expr);
+            let (tmp, var) = mk_let(ctx, expr, expr_ty_id);
+            (
+                tmp.finish(Expr::TupleGet(var, field_idx)),
+                ctx.intern_type(decl.fields[field_idx].ty.clone()),
+            )
+        }
+
+        ast::ExprKind::SetField { expr, field, value } => {
+            let (_, field_idx) = ctx.get_field(&field).unwrap();
+            let (expr, expr_ty_id) = anormal_(ctx, *expr);
+            let (value, value_ty_id) = anormal_(ctx, *value);
+            let (expr_tmp, expr_var) = mk_let(ctx, expr, expr_ty_id);
+            let (value_tmp, value_var) = mk_let(ctx, value, value_ty_id);
+            (
+                expr_tmp.finish(value_tmp.finish(Expr::TuplePut(expr_var, field_idx, value_var))),
+                unit,
+            )
+        }
+
+        // Constant constructors are integers, constructors with fields are tuples of the tag and
+        // the fields
+        ast::ExprKind::Ctor { ctor, args } => {
+            let (decl, ctor_idx) = ctx.get_ctor(&ctor).unwrap();
+            let ty_id = ctx.intern_type(Type::Variant(decl.name.clone()));
+            match decl.ctor_rep(ctor_idx) {
+                CtorRep::Const(value) => (Expr::Int(value), ty_id),
+                CtorRep::Block(tag) => {
+                    let mut tmps: Vec<TmpLet> = Vec::with_capacity(args.len() + 1);
+                    let mut field_vars: Vec<VarId> = Vec::with_capacity(args.len() + 1);
+                    let (tag_tmp, tag_var) = mk_let(ctx, Expr::Int(tag), int);
+                    tmps.push(tag_tmp);
+                    field_vars.push(tag_var);
+                    for arg in args {
+                        let (arg, arg_ty_id) = anormal_(ctx, arg);
+                        let (arg_tmp, arg_var) = mk_let(ctx, arg, arg_ty_id);
+                        tmps.push(arg_tmp);
+                        field_vars.push(arg_var);
+                    }
+                    (finish_all(tmps, Expr::Tuple(field_vars)), ty_id)
+                }
+            }
+        }
+    }
+}
+
+// A step of matching a value against a pattern
+enum MatchStep {
+    // Bind a pattern variable, or a part of the value to match against a nested pattern. Binds
+    // are pure, but a field of a variant can only be read after the tests before it.
+    Bind(VarId, Expr),
+    // Compare a variable with a constant
+    Test(VarId, Cmp, i64),
+}
+
+fn finish_binds(steps: &[MatchStep], body: Expr) -> Expr {
+    steps.iter().rev().fold(body, |body, step| match step {
+        MatchStep::Bind(id, rhs) => {
+            Expr::Let { id: *id, rhs: Box::new(rhs.clone()), body: Box::new(body) }
+        }
+        MatchStep::Test(_, _, _) => body,
+    })
+}
+
+// Compile a `match` arm. `rest` is the code for the arms below, `None` if the arm always matches.
+//
+// When the arm has a guard or multiple tests, the binds, the tests and the guard are combined into
+// one flag, so that `rest` is not duplicated. Pattern variables are then bound again for the RHS:
+//
+//     let matched =
+//       if scrut >= 1 then
+//         let view = scrut in
+//         let x = view.1 in
+//         if x = 1 then <guard> else 0
+//       else 0
+//     in
+//     if matched = 1 then (let view = scrut in let x = view.1 in <rhs>) else <rest>
+fn match_arm(
+    ctx: &mut Ctx, scrut: VarId, scrut_ty_id: TypeId, pat: &ast::Pat<VarId>, guard: Option<Expr>,
+    rhs: Expr, rest: Option<Expr>,
+) -> Expr {
+    let int = ctx.int_type_id();
+    let mut steps: Vec<MatchStep> = vec![];
+    match_pat(ctx, scrut, scrut_ty_id, pat, &mut steps);
+
+    let tests: Vec<usize> = steps
+        .iter()
+        .enumerate()
+        .filter(|(_, step)| matches!(step, MatchStep::Test(_, _, _)))
+        .map(|(step_idx, _)| step_idx)
+        .collect();
+
+    let rest = match rest {
+        None => return finish_binds(&steps, rhs),
+        Some(rest) => rest,
+    };
+
+    match (guard, tests.as_slice()) {
+        (None, []) => finish_binds(&steps, rhs),
+        (None, [test_idx]) => {
+            let (var, cmp, value) = match &steps[*test_idx] {
+                MatchStep::Test(var, cmp, value) => (*var, *cmp, *value),
+                MatchStep::Bind(_, _) => unreachable!(),
+            };
+            let rhs = finish_binds(&steps[test_idx + 1..], rhs);
+            let (value_tmp, value_var) = mk_let(ctx, Expr::Int(value), int);
+            let test = value_tmp.finish(Expr::If(
+                Cond::Cmp(var, value_var, cmp),
+                Box::new(rhs),
+                Box::new(rest),
+            ));
+            finish_binds(&steps[..*test_idx], test)
+        }
+        (guard, _) => {
+            let mut flag = guard.unwrap_or(Expr::Int(1));
+            for step in steps.iter().rev() {
+                flag = match step {
+                    MatchStep::Bind(id, rhs) => {
+                        Expr::Let { id: *id, rhs: Box::new(rhs.clone()), body: Box::new(flag) }
+                    }
+                    MatchStep::Test(var, cmp, value) => {
+                        let (value_tmp, value_var) = mk_let(ctx, Expr::Int(*value), int);
+                        value_tmp.finish(Expr::If(
+                            Cond::Cmp(*var, value_var, *cmp),
+                            Box::new(flag),
+                            Box::new(Expr::Int(0)),
+                        ))
+                    }
+                };
+            }
+            let (flag_tmp, flag_var) = mk_let(ctx, flag, int);
+            let (true_tmp, true_var) = mk_let(ctx, Expr::Int(1), int);
+            flag_tmp.finish(true_tmp.finish(Expr::If(
+                Cond::Cmp(flag_var, true_var, Cmp::Equal),
+                Box::new(finish_binds(&steps, rhs)),
+                Box::new(rest),
+            )))
+        }
+    }
+}
+
+// Collect the steps needed to match `var` against a pattern
+fn match_pat(
+    ctx: &mut Ctx, var: VarId, ty_id: TypeId, pat: &ast::Pat<VarId>, steps: &mut Vec<MatchStep>,
+) {
+    match &pat.kind {
+        ast::PatKind::Wildcard | ast::PatKind::Unit => {}
+        ast::PatKind::Var(bndr) => steps.push(MatchStep::Bind(*bndr, Expr::Var(var))),
+        ast::PatKind::Bool(b) => steps.push(MatchStep::Test(var, Cmp::Equal, i64::from(*b))),
+        ast::PatKind::Int(i) => steps.push(MatchStep::Test(var, Cmp::Equal, *i)),
+        ast::PatKind::Tuple(pats) => {
+            let field_tys = match &*ctx.get_type(ty_id) {
+                Type::Tuple(field_tys) => field_tys.clone(),
+                other => panic!("Non-tuple type in tuple pattern: {:?}", other),
+            };
+            match_fields(ctx, var, &field_tys, 0, pats, steps);
+        }
+        ast::PatKind::Nil => steps.push(MatchStep::Test(var, Cmp::Equal, 0)),
+        ast::PatKind::Cons(hd, tl) => {
+            steps.push(MatchStep::Test(var, Cmp::NotEqual, 0));
+
+            // View the cell as a tuple of the head and the tail
+            let list_ty = (*ctx.get_type(ty_id)).clone();
+            let elem_ty = match &list_ty {
+                Type::List(elem_ty) => (**elem_ty).clone(),
+                other => panic!("Non-list type in list pattern: {:?}", other),
+            };
+            let view_tys = vec![elem_ty, list_ty];
+            let view_ty_id = ctx.intern_type(Type::Tuple(view_tys.clone()));
+            let view_var = ctx.fresh_generated_var(CompilerPhase::ANormal);
+            ctx.set_var_type(view_var, view_ty_id);
+            steps.push(MatchStep::Bind(view_var, Expr::Var(var)));
+
+            let fields = [(**hd).clone(), (**tl).clone()];
+            match_fields(ctx, view_var, &view_tys, 0, &fields, steps);
+        }
+        ast::PatKind::Ctor { ctor, args } => {
+            let (decl, ctor_idx) = ctx.get_ctor(ctor).unwrap();
+            match decl.ctor_rep(ctor_idx) {
+                CtorRep::Const(value) => {
+                    if decl.ctors.len() > 1 {
+                        steps.push(MatchStep::Test(var, Cmp::Equal, value));
+                    }
+                }
+                CtorRep::Block(tag) => {
+                    // Pointers are never small integers, so a value of a type with constant
+                    // constructors is a block when it's not one of the constants
+                    let n_consts = decl.n_consts();
+                    if n_consts > 0 {
+                        steps.push(MatchStep::Test(
+                            var,
+                            Cmp::GreaterThanOrEqual,
+                            n_consts as i64,
+                        ));
+                    }
+
+                    // View the value as a tuple of the tag and the fields
+                    let fields = &decl.ctors[ctor_idx].fields;
+                    let mut view_tys: Vec<Type> = Vec::with_capacity(fields.len() + 1);
+                    view_tys.push(Type::Int);
+                    view_tys.extend(fields.iter().cloned());
+                    let view_ty_id = ctx.intern_type(Type::Tuple(view_tys.clone()));
+                    let view_var = ctx.fresh_generated_var(CompilerPhase::ANormal);
+                    ctx.set_var_type(view_var, view_ty_id);
+                    steps.push(MatchStep::Bind(view_var, Expr::Var(var)));
+
+                    if decl.n_blocks() > 1 {
+                        let int = ctx.int_type_id();
+                        let tag_var = ctx.fresh_generated_var(CompilerPhase::ANormal);
+                        ctx.set_var_type(tag_var, int);
+                        steps.push(MatchStep::Bind(tag_var, Expr::TupleGet(view_var, 0)));
+                        steps.push(MatchStep::Test(tag_var, Cmp::Equal, tag));
+                    }
+
+                    match_fields(ctx, view_var, &view_tys[1..], 1, args, steps);
+                }
+            }
+        }
+    }
+}
+
+// Match fields of a tuple, starting at the given field index, against patterns
+fn match_fields(
+    ctx: &mut Ctx, var: VarId, field_tys: &[Type], first_field_idx: usize,
+    pats: &[ast::Pat<VarId>], steps: &mut Vec<MatchStep>,
+) {
+    for (pat_idx, (pat, field_ty)) in pats.iter().zip(field_tys).enumerate() {
+        let field_idx = first_field_idx + pat_idx;
+        match &pat.kind {
+            ast::PatKind::Wildcard | ast::PatKind::Unit => {}
+            ast::PatKind::Var(bndr) => {
+                steps.push(MatchStep::Bind(*bndr, Expr::TupleGet(var, field_idx)))
+            }
+            _ => {
+                let field_ty_id = ctx.intern_type(field_ty.clone());
+                let field_var = ctx.fresh_generated_var(CompilerPhase::ANormal);
+                ctx.set_var_type(field_var, field_ty_id);
+                steps.push(MatchStep::Bind(field_var, Expr::TupleGet(var, field_idx)));
+                match_pat(ctx, field_var, field_ty_id, pat, steps);
+            }
+        }
     }
 }
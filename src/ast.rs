@@ -1,5 +1,6 @@
 use crate::common::*;
 use crate::ctx::{Ctx, VarId};
+use crate::span::Span;
 use crate::var::CompilerPhase;
 
 // Expressions after interning
@@ -8,14 +9,23 @@ pub type Expr = Expr_<VarId>;
 // Expressions after parsing
 pub type ParsedExpr = Expr_<String>;
 
-#[derive(Debug, PartialEq)]
-pub enum Expr_<I> {
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr_<I> {
+    pub kind: ExprKind<I>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind<I> {
     // ()
     Unit,
     // true, false
     Bool(bool),
     Int(i64),
     Float(f64),
+    // String literal, after escapes
+    String(Vec<u8>),
+    Char(u8),
     // not <expr>
     Not(Box<Expr_<I>>),
     // - <expr>
@@ -28,18 +38,32 @@ pub enum Expr_<I> {
     FloatBinOp(Box<Expr_<I>>, FloatBinOp, Box<Expr_<I>>),
     // Comparison, e.g. <expr> <= <expr>
     Cmp(Box<Expr_<I>>, Cmp, Box<Expr_<I>>),
+    // <expr> && <expr>
+    And(Box<Expr_<I>>, Box<Expr_<I>>),
+    // <expr> || <expr>
+    Or(Box<Expr_<I>>, Box<Expr_<I>>),
     // if <expr> then <expr> else <expr>
     If(Box<Expr_<I>>, Box<Expr_<I>>, Box<Expr_<I>>),
     // let <ident> = <expr> in <expr>
     Let { bndr: I, rhs: Box<Expr_<I>>, body: Box<Expr_<I>> },
     // <ident>
     Var(I),
-    // let rec <ident> <ident>+ = <expr> in <expr>
-    LetRec { bndr: I, args: Vec<I>, rhs: Box<Expr_<I>>, body: Box<Expr_<I>> },
+    // let rec <fun_def> (and <fun_def>)* in <expr>
+    LetRec { defs: Vec<FunDef<I>>, body: Box<Expr_<I>> },
+    // fun <binder>+ -> <expr>
+    Fun { args: Vec<I>, arg_tys: Vec<Option<TypeExpr>>, body: Box<Expr_<I>> },
     // <expr> <expr>+
     App { fun: Box<Expr_<I>>, args: Vec<Expr_<I>> },
+    // match <expr> with (| <pat> (when <expr>)? -> <expr>)+
+    Match { scrut: Box<Expr_<I>>, arms: Vec<Arm<I>> },
+    // try <expr> with (| <pat> (when <expr>)? -> <expr>)+
+    Try { body: Box<Expr_<I>>, arms: Vec<Arm<I>> },
     // <expr> (, <expr>)+
     Tuple(Vec<Expr_<I>>),
+    // []
+    Nil,
+    // <expr> :: <expr>. List literals `[<expr>; ...]` are desugared to `::`s.
+    Cons(Box<Expr_<I>>, Box<Expr_<I>>),
     // let ( <ident> (, <ident>)+ ) = <expr> in <expr>
     LetTuple { bndrs: Vec<I>, rhs: Box<Expr_<I>>, body: Box<Expr_<I>> },
     // Array.create <expr> <expr>
@@ -48,87 +72,391 @@ pub enum Expr_<I> {
     Get(Box<Expr_<I>>, Box<Expr_<I>>),
     // <expr> . ( <expr> ) <- <expr>
     Put(Box<Expr_<I>>, Box<Expr_<I>>, Box<Expr_<I>>),
+    // ( <expr> : <type> )
+    Annot { expr: Box<Expr_<I>>, ty: TypeExpr },
+    // ref <expr>
+    Ref(Box<Expr_<I>>),
+    // ! <expr>
+    Deref(Box<Expr_<I>>),
+    // failwith <expr>, exit <expr> or assert <expr>
+    Abort(AbortKind, Box<Expr_<I>>),
+    // <expr> := <expr>
+    Assign(Box<Expr_<I>>, Box<Expr_<I>>),
+    // for <ident> = <expr> (to | downto) <expr> do <expr> done
+    For { var: I, start: Box<Expr_<I>>, dir: ForDir, end: Box<Expr_<I>>, body: Box<Expr_<I>> },
+    // while <expr> do <expr> done
+    While { cond: Box<Expr_<I>>, body: Box<Expr_<I>> },
+    // type <type_decl> (and <type_decl>)* <expr>. Only at the top level, the body is the rest of
+    // the program.
+    TypeDecl { decls: Vec<TypeDecl>, body: Box<Expr_<I>> },
+    // external <ident> : <type> = <string> <expr>. Only at the top level, the body is the rest of
+    // the program.
+    External { bndr: I, ty: TypeExpr, symbol: String, body: Box<Expr_<I>> },
+    // exception <ctor_decl> <expr>. Only at the top level, the body is the rest of the program.
+    Exception { decl: CtorDecl, body: Box<Expr_<I>> },
+    // <ctor> or <ctor> <expr>. Arguments of a constructor with multiple fields are written as a
+    // tuple.
+    Ctor { ctor: String, args: Vec<Expr_<I>> },
+    // { <ident> = <expr> (; <ident> = <expr>)* }
+    Record(Vec<FieldExpr<I>>),
+    // <expr> . <ident>
+    GetField { expr: Box<Expr_<I>>, field: String },
+    // <expr> . <ident> <- <expr>
+    SetField { expr: Box<Expr_<I>>, field: String, value: Box<Expr_<I>> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortKind {
+    // failwith <string>
+    Failwith,
+    // exit <int>
+    Exit,
+    // assert <bool>. Aborts when the argument is false.
+    Assert,
+    // raise <exn>. Aborts when there's no handler for the exception.
+    Raise,
+}
+
+// <ident> = <expr> in a record expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldExpr<I> {
+    pub field: String,
+    // Span of the field name
+    pub span: Span,
+    pub expr: Expr_<I>,
+}
+
+// <ident> <binder>+ (: <type>)? = <expr>, where a binder is `<ident>` or `(<ident> : <type>)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunDef<I> {
+    pub bndr: I,
+    pub args: Vec<I>,
+    // Annotations of the arguments, one for each argument
+    pub arg_tys: Vec<Option<TypeExpr>>,
+    // Annotation of the return type
+    pub ret_ty: Option<TypeExpr>,
+    pub rhs: Expr_<I>,
+}
+
+// <ident> = <ctor_decl> (| <ctor_decl>)* or <ident> = { <field_decl> (; <field_decl>)* }
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeDecl {
+    pub name: String,
+    pub kind: TypeDeclKind,
+    // Span of the name
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeDeclKind {
+    Variant(Vec<CtorDecl>),
+    Record(Vec<FieldDecl>),
+}
+
+// <ctor> (of <type> (* <type>)*)?
+#[derive(Debug, Clone, PartialEq)]
+pub struct CtorDecl {
+    pub name: String,
+    pub fields: Vec<TypeExpr>,
+    // Span of the name
+    pub span: Span,
+}
+
+// mutable? <ident> : <type>
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDecl {
+    pub name: String,
+    pub mutable: bool,
+    pub ty: TypeExpr,
+    // Span of the name
+    pub span: Span,
+}
+
+// Types in annotations
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeExpr {
+    pub kind: TypeExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeExprKind {
+    // int, float, ...
+    Named(String),
+    // <type> array
+    Array(Box<TypeExpr>),
+    // <type> list
+    List(Box<TypeExpr>),
+    // <type> ref
+    Ref(Box<TypeExpr>),
+    // <type> (* <type>)+
+    Tuple(Vec<TypeExpr>),
+    // <type> (-> <type>)+. A parenthesized return type is a function returning a function.
+    Fun { args: Vec<TypeExpr>, ret: Box<TypeExpr> },
+}
+
+// <pat> (when <expr>)? -> <expr>
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arm<I> {
+    pub pat: Pat<I>,
+    pub guard: Option<Expr_<I>>,
+    pub rhs: Expr_<I>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pat<I> {
+    pub kind: PatKind<I>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatKind<I> {
+    // _
+    Wildcard,
+    // <ident>
+    Var(I),
+    // ()
+    Unit,
+    // true, false
+    Bool(bool),
+    Int(i64),
+    // <pat> (, <pat>)+
+    Tuple(Vec<Pat<I>>),
+    // []
+    Nil,
+    // <pat> :: <pat>. List patterns `[<pat>; ...]` are desugared to `::`s.
+    Cons(Box<Pat<I>>, Box<Pat<I>>),
+    // <ctor> or <ctor> <pat>. Like in expressions, multiple fields are written as a tuple.
+    Ctor { ctor: String, args: Vec<Pat<I>> },
 }
 
 impl ParsedExpr {
     pub fn intern(self, ctx: &mut Ctx) -> Expr {
-        match self {
-            ParsedExpr::Unit => Expr::Unit,
+        let Expr_ { kind, span } = self;
+        let kind = match kind {
+            ExprKind::Unit => ExprKind::Unit,
 
-            ParsedExpr::Bool(b) => Expr::Bool(b),
+            ExprKind::Bool(b) => ExprKind::Bool(b),
 
-            ParsedExpr::Int(i) => Expr::Int(i),
+            ExprKind::Int(i) => ExprKind::Int(i),
 
-            ParsedExpr::Float(f) => Expr::Float(f),
+            ExprKind::Float(f) => ExprKind::Float(f),
 
-            ParsedExpr::Not(e) => Expr::Not(Box::new(e.intern(ctx))),
+            ExprKind::String(bytes) => ExprKind::String(bytes),
 
-            ParsedExpr::Neg(e) => Expr::Neg(Box::new(e.intern(ctx))),
+            ExprKind::Char(c) => ExprKind::Char(c),
 
-            ParsedExpr::IntBinOp(e1, op, e2) => {
-                Expr::IntBinOp(Box::new(e1.intern(ctx)), op, Box::new(e2.intern(ctx)))
+            ExprKind::Not(e) => ExprKind::Not(Box::new(e.intern(ctx))),
+
+            ExprKind::Neg(e) => ExprKind::Neg(Box::new(e.intern(ctx))),
+
+            ExprKind::IntBinOp(e1, op, e2) => {
+                ExprKind::IntBinOp(Box::new(e1.intern(ctx)), op, Box::new(e2.intern(ctx)))
+            }
+
+            ExprKind::FNeg(e) => ExprKind::FNeg(Box::new(e.intern(ctx))),
+
+            ExprKind::FloatBinOp(e1, op, e2) => {
+                ExprKind::FloatBinOp(Box::new(e1.intern(ctx)), op, Box::new(e2.intern(ctx)))
             }
 
-            ParsedExpr::FNeg(e) => Expr::FNeg(Box::new(e.intern(ctx))),
+            ExprKind::Cmp(e1, op, e2) => {
+                ExprKind::Cmp(Box::new(e1.intern(ctx)), op, Box::new(e2.intern(ctx)))
+            }
 
-            ParsedExpr::FloatBinOp(e1, op, e2) => {
-                Expr::FloatBinOp(Box::new(e1.intern(ctx)), op, Box::new(e2.intern(ctx)))
+            ExprKind::And(e1, e2) => {
+                ExprKind::And(Box::new(e1.intern(ctx)), Box::new(e2.intern(ctx)))
             }
 
-            ParsedExpr::Cmp(e1, op, e2) => {
-                Expr::Cmp(Box::new(e1.intern(ctx)), op, Box::new(e2.intern(ctx)))
+            ExprKind::Or(e1, e2) => {
+                ExprKind::Or(Box::new(e1.intern(ctx)), Box::new(e2.intern(ctx)))
             }
 
-            ParsedExpr::If(e1, e2, e3) => Expr::If(
+            ExprKind::If(e1, e2, e3) => ExprKind::If(
                 Box::new(e1.intern(ctx)),
                 Box::new(e2.intern(ctx)),
                 Box::new(e3.intern(ctx)),
             ),
 
-            ParsedExpr::Let { bndr, rhs, body } => Expr::Let {
+            ExprKind::Let { bndr, rhs, body } => ExprKind::Let {
                 bndr: intern(&bndr, ctx),
                 rhs: Box::new(rhs.intern(ctx)),
                 body: Box::new(body.intern(ctx)),
             },
 
-            ParsedExpr::Var(var) => Expr::Var(intern(&var, ctx)),
+            ExprKind::Var(var) => ExprKind::Var(intern(&var, ctx)),
 
-            ParsedExpr::LetRec { bndr, args, rhs, body } => Expr::LetRec {
-                bndr: intern(&bndr, ctx),
+            ExprKind::LetRec { defs, body } => ExprKind::LetRec {
+                defs: defs
+                    .into_iter()
+                    .map(|FunDef { bndr, args, arg_tys, ret_ty, rhs }| FunDef {
+                        bndr: intern(&bndr, ctx),
+                        args: args.into_iter().map(|arg| intern(&arg, ctx)).collect(),
+                        arg_tys,
+                        ret_ty,
+                        rhs: rhs.intern(ctx),
+                    })
+                    .collect(),
+                body: Box::new(body.intern(ctx)),
+            },
+
+            ExprKind::Fun { args, arg_tys, body } => ExprKind::Fun {
                 args: args.into_iter().map(|arg| intern(&arg, ctx)).collect(),
-                rhs: Box::new(rhs.intern(ctx)),
+                arg_tys,
                 body: Box::new(body.intern(ctx)),
             },
 
-            ParsedExpr::App { fun, args } => Expr::App {
+            ExprKind::App { fun, args } => ExprKind::App {
                 fun: Box::new(fun.intern(ctx)),
                 args: args.into_iter().map(|arg| arg.intern(ctx)).collect(),
             },
 
-            ParsedExpr::Tuple(args) => {
-                Expr::Tuple(args.into_iter().map(|arg| arg.intern(ctx)).collect())
+            ExprKind::Match { scrut, arms } => ExprKind::Match {
+                scrut: Box::new(scrut.intern(ctx)),
+                arms: arms
+                    .into_iter()
+                    .map(|Arm { pat, guard, rhs }| Arm {
+                        pat: pat.intern(ctx),
+                        guard: guard.map(|guard| guard.intern(ctx)),
+                        rhs: rhs.intern(ctx),
+                    })
+                    .collect(),
+            },
+
+            ExprKind::Try { body, arms } => ExprKind::Try {
+                body: Box::new(body.intern(ctx)),
+                arms: arms
+                    .into_iter()
+                    .map(|Arm { pat, guard, rhs }| Arm {
+                        pat: pat.intern(ctx),
+                        guard: guard.map(|guard| guard.intern(ctx)),
+                        rhs: rhs.intern(ctx),
+                    })
+                    .collect(),
+            },
+
+            ExprKind::Tuple(args) => {
+                ExprKind::Tuple(args.into_iter().map(|arg| arg.intern(ctx)).collect())
             }
 
-            ParsedExpr::LetTuple { bndrs, rhs, body } => Expr::LetTuple {
+            ExprKind::Nil => ExprKind::Nil,
+
+            ExprKind::Cons(hd, tl) => {
+                ExprKind::Cons(Box::new(hd.intern(ctx)), Box::new(tl.intern(ctx)))
+            }
+
+            ExprKind::LetTuple { bndrs, rhs, body } => ExprKind::LetTuple {
                 bndrs: bndrs.into_iter().map(|bndr| intern(&bndr, ctx)).collect(),
                 rhs: Box::new(rhs.intern(ctx)),
                 body: Box::new(body.intern(ctx)),
             },
 
-            ParsedExpr::Array { len, elem } => {
-                Expr::Array { len: Box::new(len.intern(ctx)), elem: Box::new(elem.intern(ctx)) }
+            ExprKind::Array { len, elem } => {
+                ExprKind::Array { len: Box::new(len.intern(ctx)), elem: Box::new(elem.intern(ctx)) }
             }
 
-            ParsedExpr::Get(e1, e2) => {
-                Expr::Get(Box::new(e1.intern(ctx)), Box::new(e2.intern(ctx)))
+            ExprKind::Get(e1, e2) => {
+                ExprKind::Get(Box::new(e1.intern(ctx)), Box::new(e2.intern(ctx)))
             }
 
-            ParsedExpr::Put(e1, e2, e3) => Expr::Put(
+            ExprKind::Put(e1, e2, e3) => ExprKind::Put(
                 Box::new(e1.intern(ctx)),
                 Box::new(e2.intern(ctx)),
                 Box::new(e3.intern(ctx)),
             ),
-        }
+
+            ExprKind::Annot { expr, ty } => {
+                ExprKind::Annot { expr: Box::new(expr.intern(ctx)), ty }
+            }
+
+            ExprKind::Ref(e) => ExprKind::Ref(Box::new(e.intern(ctx))),
+
+            ExprKind::Deref(e) => ExprKind::Deref(Box::new(e.intern(ctx))),
+
+            ExprKind::Abort(kind, e) => ExprKind::Abort(kind, Box::new(e.intern(ctx))),
+
+            ExprKind::Assign(e1, e2) => {
+                ExprKind::Assign(Box::new(e1.intern(ctx)), Box::new(e2.intern(ctx)))
+            }
+
+            ExprKind::For { var, start, dir, end, body } => ExprKind::For {
+                var: intern(&var, ctx),
+                start: Box::new(start.intern(ctx)),
+                dir,
+                end: Box::new(end.intern(ctx)),
+                body: Box::new(body.intern(ctx)),
+            },
+
+            ExprKind::While { cond, body } => ExprKind::While {
+                cond: Box::new(cond.intern(ctx)),
+                body: Box::new(body.intern(ctx)),
+            },
+
+            ExprKind::TypeDecl { decls, body } => {
+                ExprKind::TypeDecl { decls, body: Box::new(body.intern(ctx)) }
+            }
+
+            ExprKind::Exception { decl, body } => {
+                ExprKind::Exception { decl, body: Box::new(body.intern(ctx)) }
+            }
+
+            ExprKind::External { bndr, ty, symbol, body } => ExprKind::External {
+                bndr: intern(&bndr, ctx),
+                ty,
+                symbol,
+                body: Box::new(body.intern(ctx)),
+            },
+
+            ExprKind::Ctor { ctor, args } => {
+                ExprKind::Ctor { ctor, args: args.into_iter().map(|arg| arg.intern(ctx)).collect() }
+            }
+
+            ExprKind::Record(fields) => ExprKind::Record(
+                fields
+                    .into_iter()
+                    .map(|FieldExpr { field, span, expr }| FieldExpr {
+                        field,
+                        span,
+                        expr: expr.intern(ctx),
+                    })
+                    .collect(),
+            ),
+
+            ExprKind::GetField { expr, field } => {
+                ExprKind::GetField { expr: Box::new(expr.intern(ctx)), field }
+            }
+
+            ExprKind::SetField { expr, field, value } => ExprKind::SetField {
+                expr: Box::new(expr.intern(ctx)),
+                field,
+                value: Box::new(value.intern(ctx)),
+            },
+        };
+        Expr { kind, span }
+    }
+}
+
+impl Pat<String> {
+    fn intern(self, ctx: &mut Ctx) -> Pat<VarId> {
+        let Pat { kind, span } = self;
+        let kind = match kind {
+            PatKind::Wildcard => PatKind::Wildcard,
+            PatKind::Var(var) => PatKind::Var(intern(&var, ctx)),
+            PatKind::Unit => PatKind::Unit,
+            PatKind::Bool(b) => PatKind::Bool(b),
+            PatKind::Int(i) => PatKind::Int(i),
+            PatKind::Tuple(pats) => {
+                PatKind::Tuple(pats.into_iter().map(|pat| pat.intern(ctx)).collect())
+            }
+            PatKind::Nil => PatKind::Nil,
+            PatKind::Cons(hd, tl) => {
+                PatKind::Cons(Box::new(hd.intern(ctx)), Box::new(tl.intern(ctx)))
+            }
+            PatKind::Ctor { ctor, args } => {
+                PatKind::Ctor { ctor, args: args.into_iter().map(|pat| pat.intern(ctx)).collect() }
+            }
+        };
+        Pat { kind, span }
     }
 }
 
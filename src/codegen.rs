@@ -4,23 +4,27 @@ use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
 use cranelift_codegen::ir::entities::{Block, FuncRef, SigRef, Value};
 use cranelift_codegen::ir::types::*;
 use cranelift_codegen::ir::MemFlags;
-use cranelift_codegen::ir::{AbiParam, InstBuilder, Signature};
+use cranelift_codegen::ir::{AbiParam, InstBuilder, Signature, TrapCode};
 use cranelift_codegen::isa::CallConv;
 use cranelift_codegen::settings;
 use cranelift_codegen::verifier::verify_function;
 use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
-use cranelift_module::{default_libcall_names, DataId, FuncId, Linkage, Module};
+use cranelift_module::{default_libcall_names, DataContext, DataId, FuncId, Linkage, Module};
 use cranelift_object::{ObjectBackend, ObjectBuilder, ObjectProduct};
 
 use fxhash::{FxHashMap, FxHashSet};
 
 use crate::cg_types::RepType;
-use crate::common::{BinOp, Cmp, FloatBinOp, IntBinOp};
-use crate::ctx::{Ctx, VarId};
+use crate::common::{Abort, BinOp, Cmp, FloatBinOp, IntBinOp};
+use crate::ctx::{Ctx, TypeId, VarId};
+use crate::diagnostics::SourceLines;
 use crate::lower;
-use crate::type_check;
+use crate::span::Span;
+use crate::type_check::{self, CtorRep, EXN_TYPE};
 
-pub fn codegen(ctx: &mut Ctx, funs: &[lower::Fun], main_id: VarId, dump: bool) -> Vec<u8> {
+pub fn codegen(
+    ctx: &mut Ctx, funs: &[lower::Fun], main_id: VarId, file_name: &str, source: &str, dump: bool,
+) -> Vec<u8> {
     // Module and FunctionBuilderContext are used for the whole compilation unit. Each function
     // gets its own FunctionBuilder.
     let codegen_flags: settings::Flags = settings::Flags::new(settings::builder());
@@ -36,9 +40,13 @@ pub fn codegen(ctx: &mut Ctx, funs: &[lower::Fun], main_id: VarId, dump: bool) -
 
     let mut fn_builder_ctx: FunctionBuilderContext = FunctionBuilderContext::new();
 
-    // Declare malloc at module-level and pass the id to code gen to be able to generate malloc
-    // calls.
-    let malloc_id = declare_malloc(&mut module);
+    // Declare malloc and RTS functions at module-level and pass the ids to code gen to be able to
+    // generate calls.
+    let rts_ids = declare_rts_funs(&mut module);
+
+    let source_loc = SourceLoc::new(&mut module, file_name, source);
+
+    define_exn_names(ctx, &mut module);
 
     // Global env is not mutable as we never add anything to it. Declarations in basic blocks are
     // done directly using the FunctionBuilder. When a variable isn't bound in 'env' it assumes
@@ -48,13 +56,29 @@ pub fn codegen(ctx: &mut Ctx, funs: &[lower::Fun], main_id: VarId, dump: bool) -
     // it in an immutable way.
     let (env, main_fun_id) = init_module_env(ctx, &mut module, funs, main_id);
 
+    // Generate closures of externals
+    let externals: Vec<(VarId, TypeId)> = ctx.externals().copied().collect();
+    for (var, ty_id) in externals {
+        let data_id = env.get_data(var).expect("Can't find DataId of external");
+        make_external(
+            ctx,
+            &mut module,
+            &mut fn_builder_ctx,
+            var,
+            ty_id,
+            data_id,
+            dump,
+        );
+    }
+
     // Generate code for functions
     for fun in funs {
         codegen_fun(
             ctx,
             &mut module,
             &env,
-            malloc_id,
+            &rts_ids,
+            &source_loc,
             fun,
             &mut fn_builder_ctx,
             dump,
@@ -88,6 +112,9 @@ enum VarVal {
     // Variable is a reference to a data object (i.e. a closure). Get a reference to it using
     // `declare_data_in_func` and a value of it using `global_value`.
     Data(DataId),
+    // Variable is stored in a data object (i.e. a top-level array). Get the address of the object
+    // using `global_value` and load the value from it.
+    Global(DataId),
 }
 
 impl Env {
@@ -107,6 +134,17 @@ impl Env {
         self.0.insert(var, VarVal::Data(val));
     }
 
+    fn add_global(&mut self, var: VarId, val: DataId) {
+        self.0.insert(var, VarVal::Global(val));
+    }
+
+    fn get_global(&self, var: VarId) -> Option<DataId> {
+        match self.0.get(&var) {
+            Some(VarVal::Global(data_id)) => Some(*data_id),
+            _ => None,
+        }
+    }
+
     fn get_fun(&self, var: VarId) -> Option<FuncId> {
         match self.0.get(&var) {
             Some(VarVal::Fun(fun_id)) => Some(*fun_id),
@@ -114,6 +152,13 @@ impl Env {
         }
     }
 
+    fn get_data(&self, var: VarId) -> Option<DataId> {
+        match self.0.get(&var) {
+            Some(VarVal::Data(data_id)) => Some(*data_id),
+            _ => None,
+        }
+    }
+
     fn use_var(
         &mut self, ctx: &Ctx, module: &Module<ObjectBackend>, builder: &mut FunctionBuilder,
         var: VarId,
@@ -144,6 +189,11 @@ impl Env {
                 // self.0.insert(var, VarVal::Known(val));
                 val
             }
+            Some(VarVal::Global(data_id)) => {
+                let data_ref = module.declare_data_in_func(data_id, builder.func);
+                let addr = builder.ins().global_value(I64, data_ref);
+                builder.ins().load(I64, MemFlags::new(), addr, 0)
+            }
             None => {
                 // Should be a variable declared and defined before.
                 let var = Variable::new(ctx.get_var(var).get_uniq().0.get() as usize);
@@ -153,8 +203,65 @@ impl Env {
     }
 }
 
-fn declare_malloc(module: &mut Module<ObjectBackend>) -> FuncId {
-    module
+// Functions called directly by generated code, rather than via closures
+struct RtsFuns<F> {
+    malloc: F,
+    // Raise `Division_by_zero`
+    div_by_zero: F,
+    // Raise `Match_failure` with the location of a `match` with no matching arm
+    match_failure: F,
+    // `failwith`, raises `Failure` with the message
+    failwith: F,
+    // Raise `Assert_failure` with the location of a failing `assert`
+    assert_failure: F,
+    // `exit`
+    exit: F,
+    // `try`, with a body returning a word or a float. Calls the body closure, and the handler
+    // closure when the body raises an exception.
+    try_word: F,
+    try_float: F,
+    // `raise`, returns to the innermost `try`. Reports the exception and the location and exits
+    // when there isn't one.
+    raise: F,
+}
+
+// Used to report locations of `failwith`s, `assert`s, `raise`s and `match`es
+struct SourceLoc<'a> {
+    // C string of the source file name
+    file_name: DataId,
+    lines: SourceLines<'a>,
+}
+
+impl<'a> SourceLoc<'a> {
+    fn new(module: &mut Module<ObjectBackend>, file_name: &str, source: &'a str) -> Self {
+        let file_name_id = module
+            .declare_data("#file_name", Linkage::Local, false, false, None)
+            .unwrap();
+        let mut data_ctx = DataContext::new();
+        let mut bytes = file_name.as_bytes().to_vec();
+        bytes.push(0);
+        data_ctx.define(bytes.into_boxed_slice());
+        module.define_data(file_name_id, &data_ctx).unwrap();
+
+        SourceLoc { file_name: file_name_id, lines: SourceLines::new(source) }
+    }
+
+    // Values of the file name, line and column (0-based, like in OCaml's `Assert_failure`) of a
+    // span
+    fn codegen_loc(
+        &self, module: &Module<ObjectBackend>, builder: &mut FunctionBuilder, span: Span,
+    ) -> (Value, Value, Value) {
+        let (line, col) = self.lines.line_col(span.start);
+        let file_name_ref = module.declare_data_in_func(self.file_name, builder.func);
+        let file_name = builder.ins().global_value(I64, file_name_ref);
+        let line = builder.ins().iconst(I64, line as i64);
+        let col = builder.ins().iconst(I64, col as i64 - 1);
+        (file_name, line, col)
+    }
+}
+
+fn declare_rts_funs(module: &mut Module<ObjectBackend>) -> RtsFuns<FuncId> {
+    let malloc = module
         .declare_function(
             "malloc",
             Linkage::Import,
@@ -164,7 +271,164 @@ fn declare_malloc(module: &mut Module<ObjectBackend>) -> FuncId {
                 call_conv: CallConv::SystemV,
             },
         )
-        .unwrap()
+        .unwrap();
+
+    let div_by_zero = module
+        .declare_function(
+            "mc_div_by_zero",
+            Linkage::Import,
+            &Signature { params: vec![], returns: vec![], call_conv: CallConv::SystemV },
+        )
+        .unwrap();
+
+    let match_failure = module
+        .declare_function(
+            "mc_match_failure",
+            Linkage::Import,
+            &Signature {
+                params: vec![AbiParam::new(I64), AbiParam::new(I64), AbiParam::new(I64)],
+                returns: vec![],
+                call_conv: CallConv::SystemV,
+            },
+        )
+        .unwrap();
+
+    let failwith = module
+        .declare_function(
+            "mc_failwith",
+            Linkage::Import,
+            &Signature {
+                params: vec![AbiParam::new(I64), AbiParam::new(I64), AbiParam::new(I64)],
+                returns: vec![],
+                call_conv: CallConv::SystemV,
+            },
+        )
+        .unwrap();
+
+    let assert_failure = module
+        .declare_function(
+            "mc_assert_failure",
+            Linkage::Import,
+            &Signature {
+                params: vec![AbiParam::new(I64), AbiParam::new(I64), AbiParam::new(I64)],
+                returns: vec![],
+                call_conv: CallConv::SystemV,
+            },
+        )
+        .unwrap();
+
+    let exit = module
+        .declare_function(
+            "mc_exit",
+            Linkage::Import,
+            &Signature {
+                params: vec![AbiParam::new(I64)],
+                returns: vec![],
+                call_conv: CallConv::SystemV,
+            },
+        )
+        .unwrap();
+
+    let try_word = module
+        .declare_function(
+            "mc_try",
+            Linkage::Import,
+            &Signature {
+                params: vec![AbiParam::new(I64), AbiParam::new(I64)],
+                returns: vec![AbiParam::new(I64)],
+                call_conv: CallConv::SystemV,
+            },
+        )
+        .unwrap();
+
+    let try_float = module
+        .declare_function(
+            "mc_try_float",
+            Linkage::Import,
+            &Signature {
+                params: vec![AbiParam::new(I64), AbiParam::new(I64)],
+                returns: vec![AbiParam::new(F64)],
+                call_conv: CallConv::SystemV,
+            },
+        )
+        .unwrap();
+
+    let raise = module
+        .declare_function(
+            "mc_raise",
+            Linkage::Import,
+            &Signature {
+                params: vec![AbiParam::new(I64), AbiParam::new(I64), AbiParam::new(I64)],
+                returns: vec![],
+                call_conv: CallConv::SystemV,
+            },
+        )
+        .unwrap();
+
+    RtsFuns {
+        malloc,
+        div_by_zero,
+        match_failure,
+        failwith,
+        assert_failure,
+        exit,
+        try_word,
+        try_float,
+        raise,
+    }
+}
+
+// Define the tables the RTS uses to report uncaught exceptions: `mc_exn_n_consts` is the number
+// of exception constructors without fields, and `mc_exn_names` are the names of the constructors,
+// indexed by the value of a constructor without fields, or the number of constructors without
+// fields plus the tag of a constructor with fields.
+fn define_exn_names(ctx: &Ctx, module: &mut Module<ObjectBackend>) {
+    let decl = ctx.get_variant(EXN_TYPE).unwrap();
+    let n_consts = decl.n_consts();
+
+    let n_consts_id = module
+        .declare_data("mc_exn_n_consts", Linkage::Export, false, false, None)
+        .unwrap();
+    let mut data_ctx = DataContext::new();
+    data_ctx.define((n_consts as i64).to_le_bytes().to_vec().into_boxed_slice());
+    module.define_data(n_consts_id, &data_ctx).unwrap();
+
+    let mut name_ids: Vec<DataId> = vec![];
+    for ctor_idx in 0..decl.ctors.len() {
+        let name_id = module
+            .declare_data(
+                &format!("#exn_name_{}", ctor_idx),
+                Linkage::Local,
+                false,
+                false,
+                None,
+            )
+            .unwrap();
+        let mut data_ctx = DataContext::new();
+        let mut bytes = decl.ctors[ctor_idx].name.as_bytes().to_vec();
+        bytes.push(0);
+        data_ctx.define(bytes.into_boxed_slice());
+        module.define_data(name_id, &data_ctx).unwrap();
+        name_ids.push(name_id);
+    }
+
+    let names_id = module
+        .declare_data("mc_exn_names", Linkage::Export, false, false, None)
+        .unwrap();
+    let mut data_ctx = DataContext::new();
+    // At least one word to define the symbol. Not `define_zeroinit`, relocations in
+    // zero-initialized data are ignored.
+    let n_words = decl.ctors.len().max(1);
+    data_ctx.define(vec![0; n_words * usize::from(WORD_SIZE)].into_boxed_slice());
+    for (ctor_idx, name_id) in name_ids.into_iter().enumerate() {
+        let name_idx = match decl.ctor_rep(ctor_idx) {
+            CtorRep::Const(value) => value as usize,
+            CtorRep::Block(tag) => n_consts + tag as usize,
+        };
+        let name_ref = module.declare_data_in_data(name_id, &mut data_ctx);
+        data_ctx.write_data_addr((name_idx * usize::from(WORD_SIZE)) as u32, name_ref, 0);
+    }
+    module.define_data(names_id, &data_ctx).unwrap();
 }
 
 fn init_module_env(
@@ -179,11 +443,39 @@ fn init_module_env(
         let name = var.symbol_name();
 
         let id: DataId = module
-            .declare_data(&*name, Linkage::Import, false, false, None)
+            .declare_data(&name, Linkage::Import, false, false, None)
             .unwrap();
         env.add_data(*builtin_var_id, id);
     }
 
+    // Declare closures of externals, defined in `make_external`
+    for (external_var_id, _ty_id) in ctx.externals() {
+        let var = ctx.get_var(*external_var_id);
+        let name = format!("#external_{}_{}", var.symbol_name(), var.get_uniq());
+
+        let id: DataId = module
+            .declare_data(&name, Linkage::Local, false, false, None)
+            .unwrap();
+        env.add_data(*external_var_id, id);
+    }
+
+    // Define data objects of top-level arrays. Arrays are allocated by main, these only hold the
+    // pointers.
+    for global_var_id in ctx.globals() {
+        let var = ctx.get_var(*global_var_id);
+        let name = format!("#global_{}_{}", var.name(), var.get_uniq());
+
+        let id: DataId = module
+            .declare_data(&name, Linkage::Local, true, false, None)
+            .unwrap();
+
+        let mut data_ctx = DataContext::new();
+        data_ctx.define_zeroinit(usize::from(WORD_SIZE));
+        module.define_data(id, &data_ctx).unwrap();
+
+        env.add_global(*global_var_id, id);
+    }
+
     // Declare functions
     for lower::Fun { name, args, return_type, .. } in funs {
         let params: Vec<AbiParam> = args
@@ -196,7 +488,7 @@ fn init_module_env(
         let sig = Signature { params, returns, call_conv: CallConv::SystemV };
 
         let id: FuncId = module
-            .declare_function(&*ctx.get_var(*name).name(), Linkage::Local, &sig)
+            .declare_function(&ctx.get_var(*name).name(), Linkage::Local, &sig)
             .unwrap();
 
         if *name == main_id {
@@ -213,9 +505,11 @@ fn init_module_env(
     (env, main_fun_id)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn codegen_fun(
-    ctx: &mut Ctx, module: &mut Module<ObjectBackend>, global_env: &Env, malloc_id: FuncId,
-    fun: &lower::Fun, fn_builder_ctx: &mut FunctionBuilderContext, dump: bool,
+    ctx: &mut Ctx, module: &mut Module<ObjectBackend>, global_env: &Env, rts_ids: &RtsFuns<FuncId>,
+    source_loc: &SourceLoc, fun: &lower::Fun, fn_builder_ctx: &mut FunctionBuilderContext,
+    dump: bool,
 ) {
     let lower::Fun { name, args, blocks, return_type } = fun;
 
@@ -238,8 +532,18 @@ fn codegen_fun(
         .get_fun(*name)
         .expect("Can't find FuncId of function");
 
-    // TODO: Only do this for functions that allocate
-    let malloc: FuncRef = module.declare_func_in_func(malloc_id, &mut context.func);
+    // TODO: Only do this for functions that allocate or divide
+    let rts: RtsFuns<FuncRef> = RtsFuns {
+        malloc: module.declare_func_in_func(rts_ids.malloc, &mut context.func),
+        div_by_zero: module.declare_func_in_func(rts_ids.div_by_zero, &mut context.func),
+        match_failure: module.declare_func_in_func(rts_ids.match_failure, &mut context.func),
+        failwith: module.declare_func_in_func(rts_ids.failwith, &mut context.func),
+        assert_failure: module.declare_func_in_func(rts_ids.assert_failure, &mut context.func),
+        exit: module.declare_func_in_func(rts_ids.exit, &mut context.func),
+        try_word: module.declare_func_in_func(rts_ids.try_word, &mut context.func),
+        try_float: module.declare_func_in_func(rts_ids.try_float, &mut context.func),
+        raise: module.declare_func_in_func(rts_ids.raise, &mut context.func),
+    };
 
     let mut builder: FunctionBuilder = FunctionBuilder::new(&mut context.func, fn_builder_ctx);
 
@@ -270,8 +574,8 @@ fn codegen_fun(
             // println!("stmt: {}", s);
 
             match stmt {
-                lower::Stmt::Asgn(lower::Asgn { lhs, rhs }) => {
-                    if !declared.contains(lhs) {
+                lower::Stmt::Asgn(lower::Asgn { lhs, rhs: _ }) => {
+                    if !declared.contains(lhs) && env.get_global(*lhs).is_none() {
                         declared.insert(*lhs);
                         let lhs_cl_var =
                             Variable::new(ctx.get_var(*lhs).get_uniq().0.get() as usize);
@@ -287,7 +591,7 @@ fn codegen_fun(
     for block in blocks.values().filter_map(lower::BlockData::get_block) {
         let lower::Block { idx, comment: _, stmts, exit } = block;
 
-        let mut cl_block = *label_to_block.get(&idx).unwrap();
+        let mut cl_block = *label_to_block.get(idx).unwrap();
         builder.switch_to_block(cl_block);
 
         for stmt in stmts {
@@ -297,16 +601,42 @@ fn codegen_fun(
 
             match stmt {
                 lower::Stmt::Asgn(lower::Asgn { lhs, rhs }) => {
-                    let (block, val) =
-                        codegen_expr(ctx, &module, cl_block, &mut builder, &mut env, malloc, rhs);
+                    let (block, val) = codegen_expr(
+                        ctx,
+                        module,
+                        cl_block,
+                        &mut builder,
+                        &mut env,
+                        &rts,
+                        source_loc,
+                        rhs,
+                    );
                     cl_block = block;
 
-                    let lhs_cl_var = Variable::new(ctx.get_var(*lhs).get_uniq().0.get() as usize);
-                    builder.def_var(lhs_cl_var, val.unwrap());
+                    match env.get_global(*lhs) {
+                        Some(data_id) => {
+                            let data_ref = module.declare_data_in_func(data_id, builder.func);
+                            let addr = builder.ins().global_value(I64, data_ref);
+                            builder.ins().store(MemFlags::new(), val.unwrap(), addr, 0);
+                        }
+                        None => {
+                            let lhs_cl_var =
+                                Variable::new(ctx.get_var(*lhs).get_uniq().0.get() as usize);
+                            builder.def_var(lhs_cl_var, val.unwrap());
+                        }
+                    }
                 }
                 lower::Stmt::Expr(expr) => {
-                    let (block, _) =
-                        codegen_expr(ctx, &module, cl_block, &mut builder, &mut env, malloc, expr);
+                    let (block, _) = codegen_expr(
+                        ctx,
+                        module,
+                        cl_block,
+                        &mut builder,
+                        &mut env,
+                        &rts,
+                        source_loc,
+                        expr,
+                    );
                     cl_block = block;
                 }
             }
@@ -314,13 +644,13 @@ fn codegen_fun(
 
         match exit {
             lower::Exit::Return(var) => {
-                let var = env.use_var(ctx, &module, &mut builder, *var);
+                let var = env.use_var(ctx, module, &mut builder, *var);
                 builder.ins().return_(&[var]);
             }
             lower::Exit::Branch { v1, v2, cond, then_block, else_block } => {
                 let comp_type = ctx.var_rep_type(*v1);
-                let v1 = env.use_var(ctx, &module, &mut builder, *v1);
-                let v2 = env.use_var(ctx, &module, &mut builder, *v2);
+                let v1 = env.use_var(ctx, module, &mut builder, *v1);
+                let v2 = env.use_var(ctx, module, &mut builder, *v2);
 
                 let then_block = *label_to_block.get(then_block).unwrap();
                 let else_block = *label_to_block.get(else_block).unwrap();
@@ -384,9 +714,10 @@ fn codegen_fun(
     module.clear_context(&mut context);
 }
 
+#[allow(clippy::too_many_arguments)]
 fn codegen_expr(
     ctx: &mut Ctx, module: &Module<ObjectBackend>, block: Block, builder: &mut FunctionBuilder,
-    env: &mut Env, malloc: FuncRef, rhs: &lower::Expr,
+    env: &mut Env, rts: &RtsFuns<FuncRef>, source_loc: &SourceLoc, rhs: &lower::Expr,
 ) -> (Block, Option<Value>) {
     match rhs {
         lower::Expr::Atom(lower::Atom::Unit) => (block, Some(builder.ins().iconst(I64, 0))),
@@ -399,13 +730,40 @@ fn codegen_expr(
         lower::Expr::IBinOp(BinOp { op, arg1, arg2 }) => {
             let arg1 = env.use_var(ctx, module, builder, *arg1);
             let arg2 = env.use_var(ctx, module, builder, *arg2);
-            let val = match op {
-                IntBinOp::Add => builder.ins().iadd(arg1, arg2),
-                IntBinOp::Sub => builder.ins().isub(arg1, arg2),
-                // IntBinOp::Mul => builder.ins().imul(arg1, arg2),
-                // IntBinOp::Div => builder.ins().sdiv(arg1, arg2),
-            };
-            (block, Some(val))
+            match op {
+                IntBinOp::Add => (block, Some(builder.ins().iadd(arg1, arg2))),
+                IntBinOp::Sub => (block, Some(builder.ins().isub(arg1, arg2))),
+                IntBinOp::Mul => (block, Some(builder.ins().imul(arg1, arg2))),
+                IntBinOp::Div | IntBinOp::Mod => {
+                    // Division by zero traps in the CPU, check it and call the RTS to report it
+                    let div_block = builder.create_block();
+                    let zero_block = builder.create_block();
+                    builder.ins().brz(arg2, zero_block, &[]);
+                    builder.ins().jump(div_block, &[]);
+                    builder.seal_block(zero_block);
+                    builder.seal_block(div_block);
+
+                    builder.switch_to_block(zero_block);
+                    builder.ins().call(rts.div_by_zero, &[]);
+                    builder.ins().trap(TrapCode::UnreachableCodeReached);
+
+                    // `min_int / -1` overflows and traps too. Divide by 1 instead when the
+                    // divisor is -1, and negate the result. Wraps to `min_int` as in OCaml.
+                    builder.switch_to_block(div_block);
+                    let divisor_minus_one = builder.ins().icmp_imm(IntCC::Equal, arg2, -1);
+                    let one = builder.ins().iconst(I64, 1);
+                    let divisor = builder.ins().select(divisor_minus_one, one, arg2);
+                    let val = match op {
+                        IntBinOp::Div => {
+                            let quot = builder.ins().sdiv(arg1, divisor);
+                            let neg_quot = builder.ins().ineg(quot);
+                            builder.ins().select(divisor_minus_one, neg_quot, quot)
+                        }
+                        _ => builder.ins().srem(arg1, divisor),
+                    };
+                    (div_block, Some(val))
+                }
+            }
         }
 
         lower::Expr::FBinOp(BinOp { op, arg1, arg2 }) => {
@@ -462,11 +820,47 @@ fn codegen_expr(
             let malloc_arg = builder
                 .ins()
                 .iconst(I64, *len as i64 * i64::from(WORD_SIZE));
-            let malloc_call = builder.ins().call(malloc, &[malloc_arg]);
+            let malloc_call = builder.ins().call(rts.malloc, &[malloc_arg]);
             let tuple = builder.inst_results(malloc_call)[0];
             (block, Some(tuple))
         }
 
+        lower::Expr::String(bytes) => {
+            // Length, then the bytes followed by at least one 0 byte, padded to a whole word so
+            // that we can initialize it a word at a time. The 0 makes the bytes a valid C string.
+            let word_size = usize::from(WORD_SIZE);
+            let n_words = (bytes.len() + word_size) / word_size;
+            let malloc_arg = builder
+                .ins()
+                .iconst(I64, (1 + n_words) as i64 * i64::from(WORD_SIZE));
+            let malloc_call = builder.ins().call(rts.malloc, &[malloc_arg]);
+            let string = builder.inst_results(malloc_call)[0];
+
+            let len = builder.ins().iconst(I64, bytes.len() as i64);
+            builder.ins().store(MemFlags::new(), len, string, 0);
+
+            for word_idx in 0..n_words {
+                let mut word = [0u8; 8];
+                for (byte_idx, byte) in bytes
+                    .iter()
+                    .skip(word_idx * word_size)
+                    .take(word_size)
+                    .enumerate()
+                {
+                    word[byte_idx] = *byte;
+                }
+                let word = builder.ins().iconst(I64, i64::from_le_bytes(word));
+                builder.ins().store(
+                    MemFlags::new(),
+                    word,
+                    string,
+                    ((1 + word_idx) * word_size) as i32,
+                );
+            }
+
+            (block, Some(string))
+        }
+
         lower::Expr::TuplePut(tuple, idx, val) => {
             let tuple = env.use_var(ctx, module, builder, *tuple);
             let arg = env.use_var(ctx, module, builder, *val);
@@ -479,25 +873,11 @@ fn codegen_expr(
             (block, None)
         }
 
-        lower::Expr::TupleGet(tuple, idx) => {
-            let tuple_type = ctx.var_type(*tuple);
-            let elem_type = match &*tuple_type {
-                type_check::Type::Tuple(args) => rep_type_abi(RepType::from(&args[*idx])),
-                type_check::Type::Fun { .. } => {
-                    // NOTE DISGUSTING HACK: This case happens after closure conversion where we
-                    // turn functions into tuples (closures) and in application code when we see
-                    //
-                    //   f x
-                    //
-                    // we instead do
-                    //
-                    //   f.0 x
-                    //
-                    // Note sure how to best implement/fix this, so for now we allow this case.
-                    I64
-                }
-                other => panic!("Non-tuple in tuple position: {:?}", other),
-            };
+        lower::Expr::TupleGet(tuple, idx, elem_type) => {
+            // NB. Type of the tuple may be a function type, as closure conversion turns functions
+            // into tuples (closures). The field type is recorded in the expression for this
+            // reason.
+            let elem_type = rep_type_abi(*elem_type);
 
             let tuple = env.use_var(ctx, module, builder, *tuple);
 
@@ -514,7 +894,7 @@ fn codegen_expr(
             let len_val = env.use_var(ctx, module, builder, *len);
             let word_size = builder.ins().iconst(I64, i64::from(WORD_SIZE));
             let size_val = builder.ins().imul(len_val, word_size);
-            let malloc_call = builder.ins().call(malloc, &[size_val]);
+            let malloc_call = builder.ins().call(rts.malloc, &[size_val]);
             (block, Some(builder.inst_results(malloc_call)[0]))
         }
 
@@ -551,9 +931,152 @@ fn codegen_expr(
             let ret = builder.ins().iconst(I64, 0);
             (block, Some(ret))
         }
+
+        lower::Expr::MatchFailure(span) => {
+            let (file_name, line, col) = source_loc.codegen_loc(module, builder, *span);
+            builder
+                .ins()
+                .call(rts.match_failure, &[file_name, line, col]);
+            (block, None)
+        }
+
+        lower::Expr::Abort(Abort::Failwith(msg), span) => {
+            let (file_name, line, _) = source_loc.codegen_loc(module, builder, *span);
+            let msg = env.use_var(ctx, module, builder, *msg);
+            builder.ins().call(rts.failwith, &[file_name, line, msg]);
+            (block, None)
+        }
+
+        lower::Expr::Abort(Abort::AssertFailure, span) => {
+            let (file_name, line, col) = source_loc.codegen_loc(module, builder, *span);
+            builder
+                .ins()
+                .call(rts.assert_failure, &[file_name, line, col]);
+            (block, None)
+        }
+
+        lower::Expr::Abort(Abort::Exit(code), _) => {
+            let code = env.use_var(ctx, module, builder, *code);
+            builder.ins().call(rts.exit, &[code]);
+            (block, None)
+        }
+
+        lower::Expr::Abort(Abort::Raise(exn), span) => {
+            let (file_name, line, _) = source_loc.codegen_loc(module, builder, *span);
+            let exn = env.use_var(ctx, module, builder, *exn);
+            builder.ins().call(rts.raise, &[exn, file_name, line]);
+            (block, None)
+        }
+
+        lower::Expr::Try(body, handler, ret_type) => {
+            let try_fun = match ret_type {
+                RepType::Word => rts.try_word,
+                RepType::Float => rts.try_float,
+            };
+            let body = env.use_var(ctx, module, builder, *body);
+            let handler = env.use_var(ctx, module, builder, *handler);
+            let call = builder.ins().call(try_fun, &[body, handler]);
+            (block, Some(builder.inst_results(call)[0]))
+        }
     }
 }
 
+// Define the closure of an `external`, and the function of the closure which calls the C function.
+// Unit arguments are not passed to the C function, and the return value of a C function returning
+// unit is not used, so C functions with `void` parameters or return types can be used.
+fn make_external(
+    ctx: &Ctx, module: &mut Module<ObjectBackend>, fun_ctx: &mut FunctionBuilderContext,
+    var: VarId, ty_id: TypeId, data_id: DataId, dump: bool,
+) {
+    let (arg_tys, ret_ty) = match &*ctx.get_type(ty_id) {
+        type_check::Type::Fun { args, ret } => (args.clone(), (**ret).clone()),
+        other => panic!("Non-function external: {:?}", other),
+    };
+    let ret_unit = ret_ty == type_check::Type::Unit;
+
+    let var = ctx.get_var(var);
+    let symbol = var.symbol_name();
+
+    let c_fun_sig = Signature {
+        params: arg_tys
+            .iter()
+            .filter(|ty| **ty != type_check::Type::Unit)
+            .map(|ty| AbiParam::new(rep_type_abi(RepType::from(ty))))
+            .collect(),
+        returns: if ret_unit {
+            vec![]
+        } else {
+            vec![AbiParam::new(rep_type_abi(RepType::from(&ret_ty)))]
+        },
+        call_conv: CallConv::SystemV,
+    };
+    let c_fun_id = module
+        .declare_function(&symbol, Linkage::Import, &c_fun_sig)
+        .unwrap();
+
+    // Closure function: takes the closure and the arguments
+    let mut context = module.make_context();
+    let mut params: Vec<AbiParam> = vec![AbiParam::new(I64)];
+    params.extend(
+        arg_tys
+            .iter()
+            .map(|ty| AbiParam::new(rep_type_abi(RepType::from(ty)))),
+    );
+    context.func.signature = Signature {
+        params,
+        returns: vec![AbiParam::new(rep_type_abi(RepType::from(&ret_ty)))],
+        call_conv: CallConv::SystemV,
+    };
+    let fun_name = format!("#external_fun_{}_{}", symbol, var.get_uniq());
+    let fun_id = module
+        .declare_function(&fun_name, Linkage::Local, &context.func.signature)
+        .unwrap();
+
+    let mut builder: FunctionBuilder = FunctionBuilder::new(&mut context.func, fun_ctx);
+    let block = builder.create_block();
+    builder.switch_to_block(block);
+    builder.append_block_params_for_function_params(block);
+    let c_args: Vec<Value> = builder.block_params(block)[1..]
+        .iter()
+        .zip(&arg_tys)
+        .filter(|(_, ty)| **ty != type_check::Type::Unit)
+        .map(|(arg, _)| *arg)
+        .collect();
+    let c_fun_ref: FuncRef = module.declare_func_in_func(c_fun_id, builder.func);
+    let call = builder.ins().call(c_fun_ref, &c_args);
+    let ret = if ret_unit {
+        builder.ins().iconst(I64, 0)
+    } else {
+        builder.inst_results(call)[0]
+    };
+    builder.ins().return_(&[ret]);
+    builder.seal_block(block);
+    builder.finalize();
+
+    let flags = settings::Flags::new(settings::builder());
+    let res = verify_function(&context.func, &flags);
+
+    if dump {
+        println!("{}", context.func.display(None));
+    }
+    if let Err(errors) = res {
+        println!("{}", errors);
+    }
+
+    module
+        .define_function(fun_id, &mut context, &mut NullTrapSink {})
+        .unwrap();
+    module.clear_context(&mut context);
+
+    // Closure: just the function
+    let mut data_ctx = DataContext::new();
+    // Not `define_zeroinit`, relocations in zero-initialized data are ignored
+    data_ctx.define(vec![0; usize::from(WORD_SIZE)].into_boxed_slice());
+    let fun_ref = module.declare_func_in_data(fun_id, &mut data_ctx);
+    data_ctx.write_function_addr(0, fun_ref);
+    module.define_data(data_id, &data_ctx).unwrap();
+}
+
 fn make_main(
     module: &mut Module<ObjectBackend>, fun_ctx: &mut FunctionBuilderContext, main_id: FuncId,
     dump: bool,
@@ -31,8 +31,29 @@ pub enum FloatBinOp {
 pub enum IntBinOp {
     Add,
     Sub,
-    // Mul,
-    // Div,
+    Mul,
+    Div,
+    Mod,
+}
+
+// How a program is aborted, see `ast::AbortKind`
+#[derive(Debug, Clone, Copy)]
+pub enum Abort {
+    // `failwith` with the message
+    Failwith(VarId),
+    // `assert` of a false value
+    AssertFailure,
+    // `exit` with the exit code
+    Exit(VarId),
+    // `raise` with the exception. Returns to the innermost `try` when there is one.
+    Raise(VarId),
+}
+
+// Direction of a `for` loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForDir {
+    To,
+    Downto,
 }
 
 impl fmt::Display for Cmp {
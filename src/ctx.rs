@@ -1,9 +1,10 @@
 use crate::cg_types::RepType;
 use crate::interner::{InternId, InternTable};
-use crate::type_check::{TyVar, Type};
+use crate::span::Span;
+use crate::type_check::{Instance, RecordDecl, TyVar, Type, VariantCtor, VariantDecl, EXN_TYPE};
 use crate::var::{CompilerPhase, Uniq, Var};
 
-use fxhash::FxHashMap;
+use fxhash::{FxHashMap, FxHashSet};
 use std::num::NonZeroU32;
 use std::rc::Rc;
 
@@ -20,6 +21,31 @@ pub struct Ctx {
     ty_env: FxHashMap<VarId, TypeId>,
     rep_ty_env: FxHashMap<VarId, RepType>,
     builtins: Vec<(VarId, TypeId)>,
+    // Builtins declared with `external` in the program
+    externals: Vec<(VarId, TypeId)>,
+    // Functions defined with `let rec` and anonymous functions. Like builtins these are called
+    // directly with the arguments they take, other function values are closures taking one
+    // argument.
+    known_funs: FxHashSet<VarId>,
+    // Top-level arrays, allocated in module data objects rather than captured by closures
+    globals: Vec<VarId>,
+    // Variant types declared with `type`, and the type and index of each constructor by the unique
+    // name of the constructor. Constructors are shadowed like fields below.
+    variants: FxHashMap<Rc<str>, Rc<VariantDecl>>,
+    ctors: FxHashMap<Rc<str>, (Rc<str>, usize)>,
+    ctor_names: FxHashMap<Rc<str>, Rc<str>>,
+    // Record types declared with `type`, and the type and index of each field by the unique name
+    // of the field. A field is shadowed by fields with the same name in later types, the type
+    // checker replaces the names in the program with the unique names of the fields in scope.
+    records: FxHashMap<Rc<str>, Rc<RecordDecl>>,
+    fields: FxHashMap<Rc<str>, (Rc<str>, usize)>,
+    field_names: FxHashMap<Rc<str>, Rc<str>>,
+    // Type variables of polymorphic binders, and uses of the binders
+    generic_vars: FxHashMap<VarId, Vec<TyVar>>,
+    instances: FxHashMap<VarId, Instance>,
+    // Operand types of comparisons, checked after type checking as the types may not be known at
+    // the comparisons
+    comparisons: Vec<(Type, Span)>,
     // Ids for widely used types
     int_id: TypeId,
     float_id: TypeId,
@@ -39,11 +65,24 @@ impl Default for Ctx {
             ty_env: Default::default(),
             rep_ty_env: Default::default(),
             builtins: vec![],
+            externals: vec![],
+            known_funs: Default::default(),
+            globals: vec![],
+            variants: Default::default(),
+            ctors: Default::default(),
+            ctor_names: Default::default(),
+            records: Default::default(),
+            fields: Default::default(),
+            field_names: Default::default(),
+            generic_vars: Default::default(),
+            instances: Default::default(),
+            comparisons: vec![],
             int_id,
             float_id,
             unit_id,
         };
         ctx.add_builtin_vars();
+        ctx.add_builtin_exns();
         ctx
     }
 }
@@ -156,10 +195,189 @@ impl Ctx {
         self.builtins.iter()
     }
 
+    /// Add a builtin declared with `external`. Unlike other builtins, closures of externals are
+    /// generated by the compiler.
+    pub fn add_external(&mut self, name: &str, symbol_name: &str, ty: Type) -> VarId {
+        let var = self.fresh_builtin_var(name, symbol_name);
+        let ty_id = self.intern_type(ty);
+        self.ty_env.insert(var, ty_id);
+        self.externals.push((var, ty_id));
+        var
+    }
+
+    pub fn externals(&self) -> impl Iterator<Item = &(VarId, TypeId)> {
+        self.externals.iter()
+    }
+
+    pub fn add_known_fun(&mut self, var: VarId) {
+        self.known_funs.insert(var);
+    }
+
+    /// Number of arguments taken by a function defined with `let rec`, an anonymous function or a
+    /// builtin. `None` for other variables.
+    pub fn fun_arity(&self, var: VarId) -> Option<usize> {
+        if !self.known_funs.contains(&var) && !self.is_builtin_var(var) {
+            return None;
+        }
+        match &*self.var_type(var) {
+            Type::Fun { args, .. } => Some(args.len()),
+            _ => None,
+        }
+    }
+
+    pub fn add_global(&mut self, var: VarId) {
+        self.globals.push(var);
+    }
+
+    pub fn globals(&self) -> impl Iterator<Item = &VarId> {
+        self.globals.iter()
+    }
+
+    pub fn is_global_var(&self, var: VarId) -> bool {
+        self.globals.contains(&var)
+    }
+
+    /// Get a builtin by its name. Panics if there isn't one.
+    pub fn builtin_var(&self, name: &str) -> VarId {
+        self.builtins
+            .iter()
+            .map(|(var, _)| *var)
+            .find(|var| &*self.get_var(*var).name() == name)
+            .unwrap_or_else(|| panic!("Unknown builtin: {}", name))
+    }
+
+    /// Add a variant type, replacing the one with the same name if there is one. The constructors
+    /// are brought into scope, so the replaced type shouldn't have constructors.
+    pub fn add_variant(&mut self, decl: VariantDecl) {
+        for (ctor_idx, ctor) in decl.ctors.iter().enumerate() {
+            self.add_ctor_name(&decl.name, ctor_idx, &ctor.name);
+        }
+        self.variants.insert(decl.name.clone(), Rc::new(decl));
+    }
+
+    /// Add a constructor declared with `exception` to the `exn` type
+    pub fn add_exn_ctor(&mut self, ctor: VariantCtor) {
+        let exn = self.get_variant(EXN_TYPE).unwrap();
+        self.add_ctor_name(&exn.name, exn.ctors.len(), &ctor.name);
+        let mut ctors = exn.ctors.clone();
+        ctors.push(ctor);
+        self.variants.insert(
+            exn.name.clone(),
+            Rc::new(VariantDecl { name: exn.name.clone(), ctors }),
+        );
+    }
+
+    fn add_ctor_name(&mut self, ty_name: &Rc<str>, ctor_idx: usize, name: &Rc<str>) {
+        let unique_name = unique_name(&self.ctors, name);
+        self.ctors
+            .insert(unique_name.clone(), (ty_name.clone(), ctor_idx));
+        self.ctor_names.insert(name.clone(), unique_name);
+    }
+
+    pub fn get_variant(&self, name: &str) -> Option<Rc<VariantDecl>> {
+        self.variants.get(name).cloned()
+    }
+
+    /// Get the unique name of the constructor in scope with the given name
+    pub fn resolve_ctor(&self, name: &str) -> Option<Rc<str>> {
+        self.ctor_names.get(name).cloned()
+    }
+
+    /// Get the type of a constructor and the index of the constructor in the type, by the unique
+    /// name of the constructor
+    pub fn get_ctor(&self, name: &str) -> Option<(Rc<VariantDecl>, usize)> {
+        let (ty_name, ctor_idx) = self.ctors.get(name)?;
+        Some((self.variants.get(ty_name).unwrap().clone(), *ctor_idx))
+    }
+
+    /// Add a record type, replacing the one with the same name if there is one. The fields are
+    /// brought into scope, so the replaced type shouldn't have fields.
+    pub fn add_record(&mut self, decl: RecordDecl) {
+        for (field_idx, field) in decl.fields.iter().enumerate() {
+            let unique_name = unique_name(&self.fields, &field.name);
+            self.fields
+                .insert(unique_name.clone(), (decl.name.clone(), field_idx));
+            self.field_names.insert(field.name.clone(), unique_name);
+        }
+        self.records.insert(decl.name.clone(), Rc::new(decl));
+    }
+
+    pub fn get_record(&self, name: &str) -> Option<Rc<RecordDecl>> {
+        self.records.get(name).cloned()
+    }
+
+    /// Get the unique name of the field in scope with the given name
+    pub fn resolve_field(&self, name: &str) -> Option<Rc<str>> {
+        self.field_names.get(name).cloned()
+    }
+
+    /// Get the type of a record field and the index of the field in the record, by the unique
+    /// name of the field
+    pub fn get_field(&self, name: &str) -> Option<(Rc<RecordDecl>, usize)> {
+        let (ty_name, field_idx) = self.fields.get(name)?;
+        Some((self.records.get(ty_name).unwrap().clone(), *field_idx))
+    }
+
+    pub fn set_generic_vars(&mut self, var: VarId, ty_vars: Vec<TyVar>) {
+        self.generic_vars.insert(var, ty_vars);
+    }
+
+    /// Type variables of a polymorphic binder. Empty for monomorphic binders.
+    pub fn generic_vars(&self, var: VarId) -> &[TyVar] {
+        self.generic_vars
+            .get(&var)
+            .map(|vars| &vars[..])
+            .unwrap_or(&[])
+    }
+
+    /// Type variables of all polymorphic binders
+    pub fn all_generic_vars(&self) -> impl Iterator<Item = TyVar> + '_ {
+        self.generic_vars.values().flatten().copied()
+    }
+
+    pub fn add_instance(&mut self, var: VarId, instance: Instance) {
+        self.instances.insert(var, instance);
+    }
+
+    /// Get the polymorphic binder and type arguments of a use of a polymorphic binder
+    pub fn get_instance(&self, var: VarId) -> Option<&Instance> {
+        self.instances.get(&var)
+    }
+
+    pub fn instances_mut(&mut self) -> impl Iterator<Item = &mut Instance> {
+        self.instances.values_mut()
+    }
+
+    pub fn add_comparison(&mut self, ty: Type, span: Span) {
+        self.comparisons.push((ty, span));
+    }
+
+    pub fn take_comparisons(&mut self) -> Vec<(Type, Span)> {
+        std::mem::take(&mut self.comparisons)
+    }
+
     pub fn is_builtin_var(&self, id: VarId) -> bool {
         self.get_var(id).is_builtin()
     }
 
+    // Exceptions raised by the runtime system. The runtime system relies on the order of the
+    // constructors, see `rts.c`.
+    fn add_builtin_exns(&mut self) {
+        let exn = |name: &str, fields: Vec<Type>| VariantCtor { name: name.into(), fields };
+        let loc = || vec![Type::String, Type::Int, Type::Int];
+        self.add_variant(VariantDecl {
+            name: EXN_TYPE.into(),
+            ctors: vec![
+                exn("Match_failure", loc()),
+                exn("Assert_failure", loc()),
+                exn("Failure", vec![Type::String]),
+                exn("Invalid_argument", vec![Type::String]),
+                exn("Division_by_zero", vec![]),
+                exn("End_of_file", vec![]),
+            ],
+        });
+    }
+
     fn add_builtin(&mut self, var: VarId, ty: TypeId) {
         self.ty_env.insert(var, ty);
         self.builtins.push((var, ty));
@@ -192,6 +410,45 @@ impl Ctx {
             self.intern_type(Type::Fun { args: vec![Type::Unit], ret: Box::new(Type::Unit) });
         self.add_builtin(print_newline_var, print_newline_ty);
 
+        let print_float_var = self.fresh_builtin_var("print_float", "mc_print_float");
+        let print_float_ty =
+            self.intern_type(Type::Fun { args: vec![Type::Float], ret: Box::new(Type::Unit) });
+        self.add_builtin(print_float_var, print_float_ty);
+
+        let print_char_var = self.fresh_builtin_var("print_char", "mc_print_char");
+        let print_char_ty =
+            self.intern_type(Type::Fun { args: vec![Type::Char], ret: Box::new(Type::Unit) });
+        self.add_builtin(print_char_var, print_char_ty);
+
+        // Prints the lowest 8 bits of an int. From the original MinCaml library.
+        let print_byte_var = self.fresh_builtin_var("print_byte", "mc_print_char");
+        self.add_builtin(print_byte_var, print_int_ty);
+
+        let prerr_int_var = self.fresh_builtin_var("prerr_int", "mc_prerr_int");
+        self.add_builtin(prerr_int_var, print_int_ty);
+
+        let prerr_newline_var = self.fresh_builtin_var("prerr_newline", "mc_prerr_newline");
+        self.add_builtin(prerr_newline_var, print_newline_ty);
+
+        let prerr_float_var = self.fresh_builtin_var("prerr_float", "mc_prerr_float");
+        self.add_builtin(prerr_float_var, print_float_ty);
+
+        let prerr_char_var = self.fresh_builtin_var("prerr_char", "mc_prerr_char");
+        self.add_builtin(prerr_char_var, print_char_ty);
+
+        let prerr_byte_var = self.fresh_builtin_var("prerr_byte", "mc_prerr_char");
+        self.add_builtin(prerr_byte_var, print_int_ty);
+
+        let read_int_var = self.fresh_builtin_var("read_int", "mc_read_int");
+        let read_int_ty =
+            self.intern_type(Type::Fun { args: vec![Type::Unit], ret: Box::new(Type::Int) });
+        self.add_builtin(read_int_var, read_int_ty);
+
+        let read_float_var = self.fresh_builtin_var("read_float", "mc_read_float");
+        let read_float_ty =
+            self.intern_type(Type::Fun { args: vec![Type::Unit], ret: Box::new(Type::Float) });
+        self.add_builtin(read_float_var, read_float_ty);
+
         let float_of_int_var = self.fresh_builtin_var("float_of_int", "mc_float_of_int");
         let float_of_int_ty =
             self.intern_type(Type::Fun { args: vec![Type::Int], ret: Box::new(Type::Float) });
@@ -214,5 +471,89 @@ impl Ctx {
 
         let cos_var = self.fresh_builtin_var("cos", "mc_cos");
         self.add_builtin(cos_var, float_float);
+
+        // Qualified names. These are resolved like other builtins, with the path as the name.
+        // Variables can't have dots in their names so these can't be shadowed.
+        let float_of_int_var = self.fresh_builtin_var("Float.of_int", "mc_float_of_int");
+        self.add_builtin(float_of_int_var, float_of_int_ty);
+
+        let float_to_int_var = self.fresh_builtin_var("Float.to_int", "mc_int_of_float");
+        self.add_builtin(float_to_int_var, float_int);
+
+        let float_abs_var = self.fresh_builtin_var("Float.abs", "mc_abs_float");
+        self.add_builtin(float_abs_var, float_float);
+
+        let float_sqrt_var = self.fresh_builtin_var("Float.sqrt", "mc_sqrt");
+        self.add_builtin(float_sqrt_var, float_float);
+
+        let float_sin_var = self.fresh_builtin_var("Float.sin", "mc_sin");
+        self.add_builtin(float_sin_var, float_float);
+
+        let float_cos_var = self.fresh_builtin_var("Float.cos", "mc_cos");
+        self.add_builtin(float_cos_var, float_float);
+
+        let print_string_var = self.fresh_builtin_var("print_string", "mc_print_string");
+        let print_string_ty =
+            self.intern_type(Type::Fun { args: vec![Type::String], ret: Box::new(Type::Unit) });
+        self.add_builtin(print_string_var, print_string_ty);
+
+        let print_endline_var = self.fresh_builtin_var("print_endline", "mc_print_endline");
+        self.add_builtin(print_endline_var, print_string_ty);
+
+        let prerr_string_var = self.fresh_builtin_var("prerr_string", "mc_prerr_string");
+        self.add_builtin(prerr_string_var, print_string_ty);
+
+        let prerr_endline_var = self.fresh_builtin_var("prerr_endline", "mc_prerr_endline");
+        self.add_builtin(prerr_endline_var, print_string_ty);
+
+        // `^` operator
+        let string_concat_var = self.fresh_builtin_var("^", "mc_string_concat");
+        let string_concat_ty = self.intern_type(Type::Fun {
+            args: vec![Type::String, Type::String],
+            ret: Box::new(Type::String),
+        });
+        self.add_builtin(string_concat_var, string_concat_ty);
+
+        let string_length_var = self.fresh_builtin_var("String.length", "mc_string_length");
+        let string_length_ty =
+            self.intern_type(Type::Fun { args: vec![Type::String], ret: Box::new(Type::Int) });
+        self.add_builtin(string_length_var, string_length_ty);
+
+        let string_get_var = self.fresh_builtin_var("String.get", "mc_string_get");
+        let string_get_ty = self.intern_type(Type::Fun {
+            args: vec![Type::String, Type::Int],
+            ret: Box::new(Type::Char),
+        });
+        self.add_builtin(string_get_var, string_get_ty);
+
+        // Also used to compile comparison operators on strings
+        let string_compare_var = self.fresh_builtin_var("String.compare", "mc_string_compare");
+        let string_compare_ty = self.intern_type(Type::Fun {
+            args: vec![Type::String, Type::String],
+            ret: Box::new(Type::Int),
+        });
+        self.add_builtin(string_compare_var, string_compare_ty);
+
+        let char_code_var = self.fresh_builtin_var("Char.code", "mc_char_code");
+        let char_code_ty =
+            self.intern_type(Type::Fun { args: vec![Type::Char], ret: Box::new(Type::Int) });
+        self.add_builtin(char_code_var, char_code_ty);
+
+        let char_chr_var = self.fresh_builtin_var("Char.chr", "mc_char_chr");
+        let char_chr_ty =
+            self.intern_type(Type::Fun { args: vec![Type::Int], ret: Box::new(Type::Char) });
+        self.add_builtin(char_chr_var, char_chr_ty);
+    }
+}
+
+/// A name that isn't in the map, for a constructor or field shadowing another one with the same
+/// name. `#` isn't valid in identifiers, so the names don't clash with names in the program.
+fn unique_name(names: &FxHashMap<Rc<str>, (Rc<str>, usize)>, name: &Rc<str>) -> Rc<str> {
+    if !names.contains_key(name) {
+        return name.clone();
     }
+    (1..)
+        .map(|n| -> Rc<str> { format!("{}#{}", name, n).into() })
+        .find(|unique_name| !names.contains_key(unique_name))
+        .unwrap()
 }
@@ -0,0 +1,242 @@
+// Compiler diagnostics: errors and warnings with source locations, rendered similar to rustc:
+//
+//     error: mismatched types
+//      --> test.ml:2:1
+//       |
+//     2 | x +. 2.0
+//       | ^ expected `float`, found `int`
+//
+// Passes convert their errors to `Diagnostic`s, which are rendered against the source file in
+// `lib.rs`.
+
+use crate::span::Span;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => "error".fmt(f),
+            Severity::Warning => "warning".fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Location of the problem, underlined with `^`
+    pub span: Span,
+    /// Label shown next to the primary underline
+    pub label: Option<String>,
+    /// Related locations, underlined with `-`
+    pub secondary: Vec<(Span, String)>,
+    /// Notes shown after the source snippet
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic::new(Severity::Error, message.into(), span)
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, message.into(), span)
+    }
+
+    fn new(severity: Severity, message: String, span: Span) -> Diagnostic {
+        Diagnostic { severity, message, span, label: None, secondary: vec![], notes: vec![] }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Diagnostic {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, label: impl Into<String>) -> Diagnostic {
+        self.secondary.push((span, label.into()));
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn render(&self, file_name: &str, source: &str, w: &mut dyn fmt::Write) -> fmt::Result {
+        let Diagnostic { severity, message, span, label, secondary, notes } = self;
+
+        // Underlines to draw: (span, underline char, label)
+        let mut marks: Vec<(Span, char, Option<&str>)> = Vec::with_capacity(secondary.len() + 1);
+        marks.push((*span, '^', label.as_deref()));
+        for (span, label) in secondary {
+            marks.push((*span, '-', Some(label)));
+        }
+        marks.sort_by_key(|(span, _, _)| span.start);
+
+        let lines = SourceLines::new(source);
+        let max_line = marks
+            .iter()
+            .map(|(span, _, _)| lines.line_col(span.start).0)
+            .max()
+            .unwrap();
+        let gutter = " ".repeat(max_line.to_string().len());
+
+        writeln!(w, "{}: {}", severity, message)?;
+        let (line, col) = lines.line_col(span.start);
+        writeln!(w, "{}--> {}:{}:{}", gutter, file_name, line, col)?;
+        writeln!(w, "{} |", gutter)?;
+
+        let mut last_line: Option<usize> = None;
+        for (span, underline_char, label) in marks {
+            let (line, _) = lines.line_col(span.start);
+            let text = lines.line_text(line);
+            if last_line != Some(line) {
+                writeln!(w, "{:>width$} | {}", line, text, width = gutter.len())?;
+                last_line = Some(line);
+            }
+
+            // Whitespace up to the start column. Tabs are copied so the underline lines up with
+            // the text above.
+            let line_start = lines.line_start(line);
+            let prefix: String = source[line_start..span.start]
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+
+            // Underline until the end of the span or the end of the line, at least one char
+            let underline_end = span.end.min(line_start + text.len());
+            let underline_len = source[span.start..underline_end.max(span.start)]
+                .chars()
+                .count();
+            let underline = underline_char.to_string().repeat(underline_len.max(1));
+
+            write!(w, "{} | {}{}", gutter, prefix, underline)?;
+            match label {
+                Some(label) => writeln!(w, " {}", label)?,
+                None => writeln!(w)?,
+            }
+        }
+
+        if !notes.is_empty() {
+            writeln!(w, "{} |", gutter)?;
+        }
+        for note in notes {
+            writeln!(w, "{} = note: {}", gutter, note)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Maps byte offsets to lines and columns
+pub struct SourceLines<'a> {
+    source: &'a str,
+    // Byte offsets of line starts
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceLines<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        for (idx, c) in source.char_indices() {
+            if c == '\n' {
+                line_starts.push(idx + 1);
+            }
+        }
+        SourceLines { source, line_starts }
+    }
+
+    // 1-based line and column (in chars) of a byte offset
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line_idx = match self.line_starts.binary_search(&offset) {
+            Ok(idx) => idx,
+            Err(idx) => idx - 1,
+        };
+        let col = self.source[self.line_starts[line_idx]..offset]
+            .chars()
+            .count();
+        (line_idx + 1, col + 1)
+    }
+
+    fn line_start(&self, line: usize) -> usize {
+        self.line_starts[line - 1]
+    }
+
+    // Text of a 1-based line, without the newline
+    fn line_text(&self, line: usize) -> &'a str {
+        let start = self.line_starts[line - 1];
+        let end = match self.line_starts.get(line) {
+            Some(next_start) => next_start - 1,
+            None => self.source.len(),
+        };
+        self.source[start..end].trim_end_matches('\r')
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render(diag: &Diagnostic, source: &str) -> String {
+        let mut s = String::new();
+        diag.render("test.ml", source, &mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn render_primary() {
+        let source = "let x = 1 in\nx +. 2.0";
+        let diag = Diagnostic::error("mismatched types", Span::new(13, 14))
+            .with_label("expected `float`, found `int`");
+        assert_eq!(
+            render(&diag, source),
+            "error: mismatched types\n \
+             --> test.ml:2:1\n  \
+             |\n\
+             2 | x +. 2.0\n  \
+             | ^ expected `float`, found `int`\n"
+        );
+    }
+
+    #[test]
+    fn render_secondary_and_notes() {
+        let source = "let x = 1 in\n\tx +. 2.0";
+        let diag = Diagnostic::error("mismatched types", Span::new(14, 15))
+            .with_secondary(Span::new(4, 5), "defined here")
+            .with_note("a note");
+        assert_eq!(
+            render(&diag, source),
+            "error: mismatched types\n \
+             --> test.ml:2:2\n  \
+             |\n\
+             1 | let x = 1 in\n  \
+             |     - defined here\n\
+             2 | \tx +. 2.0\n  \
+             | \t^\n  \
+             |\n  \
+             = note: a note\n"
+        );
+    }
+
+    #[test]
+    fn render_end_of_input() {
+        let source = "let x = 1";
+        let diag = Diagnostic::error("unexpected end of input", Span::new(9, 9));
+        assert_eq!(
+            render(&diag, source),
+            "error: unexpected end of input\n \
+             --> test.ml:1:10\n  \
+             |\n\
+             1 | let x = 1\n  \
+             |          ^\n"
+        );
+    }
+}
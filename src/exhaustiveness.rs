@@ -0,0 +1,447 @@
+// Exhaustiveness and redundancy checking for `match` arms, using the "usefulness" algorithm from
+// Maranget's "Warnings for pattern matching".
+//
+// A pattern vector `q` is useful with respect to a matrix of pattern rows `P` if there's a value
+// matched by `q` but not by any of the rows. An arm is redundant if its pattern is not useful with
+// respect to the patterns of the arms above it, and a match is exhaustive if the wildcard is not
+// useful with respect to all of the arms. Arms with guards may not match, so they're ignored when
+// checking arms below them.
+//
+// Checking only needs the patterns and the declared variant types: all constructors determine
+// their types, and a column with only wildcards doesn't need a type.
+
+use crate::ast::{Pat, PatKind};
+use crate::ctx::Ctx;
+use crate::type_check::VariantDecl;
+
+use std::fmt;
+use std::rc::Rc;
+
+pub struct MatchCheck {
+    /// Indices of arms that can never match
+    pub redundant: Vec<usize>,
+    /// A value not matched by any of the arms, when the match is not exhaustive
+    pub missing: Option<Witness>,
+}
+
+/// Check the arms of a `match`. Each arm is given as its pattern and whether it has a guard.
+pub fn check_match<I>(ctx: &Ctx, arms: &[(&Pat<I>, bool)]) -> MatchCheck {
+    let mut rows: Vec<Row> = Vec::with_capacity(arms.len());
+    let mut redundant: Vec<usize> = vec![];
+
+    for (arm_idx, (pat, guarded)) in arms.iter().enumerate() {
+        let row = vec![Pattern::from_pat(ctx, pat)];
+        if !useful(&rows, &row) {
+            redundant.push(arm_idx);
+        }
+        if !guarded {
+            rows.push(row);
+        }
+    }
+
+    let missing = missing(&rows, 1).map(|mut pats| Witness(pats.pop().unwrap()));
+
+    MatchCheck { redundant, missing }
+}
+
+/// An example value not matched by a `match`. Displayed in OCaml syntax, with `_` for any value.
+pub struct Witness(Pattern);
+
+impl fmt::Display for Witness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// Patterns with variables replaced by wildcards
+#[derive(Debug, Clone)]
+enum Pattern {
+    Wildcard,
+    Ctor(Ctor, Vec<Pattern>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    Unit,
+    Bool(bool),
+    Int(i64),
+    Tuple(usize),
+    // `[]` and `::` of lists
+    Nil,
+    Cons,
+    // Constructor of a variant type, with its index in the type
+    Variant(Rc<VariantDecl>, usize),
+}
+
+impl Ctor {
+    fn arity(&self) -> usize {
+        match self {
+            Ctor::Unit | Ctor::Bool(_) | Ctor::Int(_) | Ctor::Nil => 0,
+            Ctor::Tuple(arity) => *arity,
+            Ctor::Cons => 2,
+            Ctor::Variant(decl, ctor_idx) => decl.ctors[*ctor_idx].fields.len(),
+        }
+    }
+}
+
+type Row = Vec<Pattern>;
+
+impl Pattern {
+    fn from_pat<I>(ctx: &Ctx, pat: &Pat<I>) -> Pattern {
+        match &pat.kind {
+            PatKind::Wildcard | PatKind::Var(_) => Pattern::Wildcard,
+            PatKind::Unit => Pattern::Ctor(Ctor::Unit, vec![]),
+            PatKind::Bool(b) => Pattern::Ctor(Ctor::Bool(*b), vec![]),
+            PatKind::Int(i) => Pattern::Ctor(Ctor::Int(*i), vec![]),
+            PatKind::Tuple(pats) => Pattern::Ctor(
+                Ctor::Tuple(pats.len()),
+                pats.iter().map(|pat| Pattern::from_pat(ctx, pat)).collect(),
+            ),
+            PatKind::Nil => Pattern::Ctor(Ctor::Nil, vec![]),
+            PatKind::Cons(hd, tl) => Pattern::Ctor(
+                Ctor::Cons,
+                vec![Pattern::from_pat(ctx, hd), Pattern::from_pat(ctx, tl)],
+            ),
+            PatKind::Ctor { ctor, args } => {
+                let (decl, ctor_idx) = ctx.get_ctor(ctor).unwrap();
+                Pattern::Ctor(
+                    Ctor::Variant(decl, ctor_idx),
+                    args.iter().map(|pat| Pattern::from_pat(ctx, pat)).collect(),
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Wildcard => f.write_str("_"),
+            Pattern::Ctor(Ctor::Unit, _) => f.write_str("()"),
+            Pattern::Ctor(Ctor::Bool(b), _) => write!(f, "{}", b),
+            Pattern::Ctor(Ctor::Int(i), _) => write!(f, "{}", i),
+            Pattern::Ctor(Ctor::Tuple(_), pats) => {
+                f.write_str("(")?;
+                for (pat_idx, pat) in pats.iter().enumerate() {
+                    if pat_idx != 0 {
+                        f.write_str(", ")?;
+                    }
+                    pat.fmt(f)?;
+                }
+                f.write_str(")")
+            }
+            Pattern::Ctor(Ctor::Nil, _) => f.write_str("[]"),
+            Pattern::Ctor(Ctor::Cons, pats) => match &pats[0] {
+                hd @ Pattern::Ctor(Ctor::Cons, _) => write!(f, "({}) :: {}", hd, pats[1]),
+                hd => write!(f, "{} :: {}", hd, pats[1]),
+            },
+            Pattern::Ctor(Ctor::Variant(decl, ctor_idx), pats) => {
+                f.write_str(&decl.ctors[*ctor_idx].name)?;
+                match pats.as_slice() {
+                    [] => Ok(()),
+                    [pat @ Pattern::Ctor(Ctor::Variant(_, _), args)] if !args.is_empty() => {
+                        write!(f, " ({})", pat)
+                    }
+                    [pat @ Pattern::Ctor(Ctor::Cons, _)] => write!(f, " ({})", pat),
+                    [pat] => write!(f, " {}", pat),
+                    pats => {
+                        f.write_str(" (")?;
+                        for (pat_idx, pat) in pats.iter().enumerate() {
+                            if pat_idx != 0 {
+                                f.write_str(", ")?;
+                            }
+                            pat.fmt(f)?;
+                        }
+                        f.write_str(")")
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Rows that match the given constructor, with the constructor's fields in place of the first
+// column
+fn specialize(rows: &[Row], ctor: &Ctor) -> Vec<Row> {
+    rows.iter()
+        .filter_map(|row| {
+            let fields: Vec<Pattern> = match &row[0] {
+                Pattern::Wildcard => vec![Pattern::Wildcard; ctor.arity()],
+                Pattern::Ctor(ctor_, fields) if ctor_ == ctor => fields.clone(),
+                Pattern::Ctor(_, _) => return None,
+            };
+            Some(fields.into_iter().chain(row[1..].iter().cloned()).collect())
+        })
+        .collect()
+}
+
+// Rows with a wildcard in the first column, without the first column
+fn default(rows: &[Row]) -> Vec<Row> {
+    rows.iter()
+        .filter(|row| matches!(row[0], Pattern::Wildcard))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+// Constructors in the first column
+fn head_ctors(rows: &[Row]) -> Vec<Ctor> {
+    let mut ctors: Vec<Ctor> = vec![];
+    for row in rows {
+        if let Pattern::Ctor(ctor, _) = &row[0] {
+            if !ctors.contains(ctor) {
+                ctors.push(ctor.clone());
+            }
+        }
+    }
+    ctors
+}
+
+// A constructor of the type of the given constructors that is not in the list. `None` if the
+// constructors cover all values of the type.
+fn missing_ctor(ctors: &[Ctor]) -> Option<Ctor> {
+    match ctors.first()? {
+        Ctor::Unit | Ctor::Tuple(_) => None,
+        Ctor::Bool(_) => [true, false]
+            .iter()
+            .map(|b| Ctor::Bool(*b))
+            .find(|ctor| !ctors.contains(ctor)),
+        Ctor::Int(_) => (0..).map(Ctor::Int).find(|ctor| !ctors.contains(ctor)),
+        Ctor::Nil | Ctor::Cons => [Ctor::Nil, Ctor::Cons]
+            .iter()
+            .find(|ctor| !ctors.contains(ctor))
+            .cloned(),
+        Ctor::Variant(decl, _) => (0..decl.ctors.len())
+            .map(|ctor_idx| Ctor::Variant(decl.clone(), ctor_idx))
+            .find(|ctor| !ctors.contains(ctor)),
+    }
+}
+
+fn is_complete(ctors: &[Ctor]) -> bool {
+    match ctors.first() {
+        None => false,
+        Some(Ctor::Variant(decl, _)) if decl.is_extensible() => false,
+        Some(_) => missing_ctor(ctors).is_none(),
+    }
+}
+
+fn useful(rows: &[Row], row: &[Pattern]) -> bool {
+    let (head, rest) = match row.split_first() {
+        None => return rows.is_empty(),
+        Some(split) => split,
+    };
+
+    match head {
+        Pattern::Ctor(ctor, fields) => {
+            let row: Row = fields.iter().chain(rest.iter()).cloned().collect();
+            useful(&specialize(rows, ctor), &row)
+        }
+        Pattern::Wildcard => {
+            let ctors = head_ctors(rows);
+            if is_complete(&ctors) {
+                ctors.into_iter().any(|ctor| {
+                    let row: Row = vec![Pattern::Wildcard; ctor.arity()]
+                        .into_iter()
+                        .chain(rest.iter().cloned())
+                        .collect();
+                    useful(&specialize(rows, &ctor), &row)
+                })
+            } else {
+                useful(&default(rows), rest)
+            }
+        }
+    }
+}
+
+// A row of `n` patterns not matched by any of the rows
+fn missing(rows: &[Row], n: usize) -> Option<Row> {
+    if n == 0 {
+        return if rows.is_empty() { Some(vec![]) } else { None };
+    }
+
+    let ctors = head_ctors(rows);
+    if is_complete(&ctors) {
+        for ctor in ctors {
+            let arity = ctor.arity();
+            if let Some(mut row) = missing(&specialize(rows, &ctor), arity + n - 1) {
+                let rest = row.split_off(arity);
+                let mut ret = vec![Pattern::Ctor(ctor, row)];
+                ret.extend(rest);
+                return Some(ret);
+            }
+        }
+        None
+    } else {
+        let rest = missing(&default(rows), n - 1)?;
+        let head = match missing_ctor(&ctors) {
+            None => Pattern::Wildcard,
+            Some(ctor) => Pattern::Ctor(ctor.clone(), vec![Pattern::Wildcard; ctor.arity()]),
+        };
+        let mut ret = vec![head];
+        ret.extend(rest);
+        Some(ret)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::ast::ExprKind;
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+    use crate::type_check::{declare_exception, declare_types};
+
+    // Check the arms of the `match` in the given program. The program can start with type and
+    // exception declarations.
+    fn check(pgm: &str) -> (Vec<usize>, Option<String>) {
+        let (mut expr, errs) = parse(&tokenize(pgm).unwrap());
+        assert!(errs.is_empty(), "{:?}", errs);
+        let mut ctx: Ctx = Default::default();
+        loop {
+            expr = match expr.kind {
+                ExprKind::TypeDecl { decls, body } => {
+                    declare_types(&mut ctx, &decls).unwrap();
+                    *body
+                }
+                ExprKind::Exception { decl, body } => {
+                    declare_exception(&mut ctx, &decl).unwrap();
+                    *body
+                }
+                _ => break,
+            };
+        }
+        match expr.kind {
+            ExprKind::Match { arms, .. } => {
+                let arms: Vec<(&Pat<String>, bool)> = arms
+                    .iter()
+                    .map(|arm| (&arm.pat, arm.guard.is_some()))
+                    .collect();
+                let MatchCheck { redundant, missing } = check_match(&ctx, &arms);
+                (redundant, missing.map(|w| w.to_string()))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn exhaustive() {
+        assert_eq!(check("match x with true -> 1 | false -> 2"), (vec![], None));
+        assert_eq!(
+            check("match x with (_, 0) -> 1 | (0, _) -> 2 | _ -> 3"),
+            (vec![], None)
+        );
+        assert_eq!(check("match x with () -> 1"), (vec![], None));
+        assert_eq!(
+            check("match x with (true, b) -> 1 | (false, true) -> 2 | (_, false) -> 3"),
+            (vec![], None)
+        );
+    }
+
+    #[test]
+    fn missing_patterns() {
+        assert_eq!(
+            check("match x with true -> 1"),
+            (vec![], Some("false".to_owned()))
+        );
+        assert_eq!(
+            check("match x with 0 -> 1 | 1 -> 2"),
+            (vec![], Some("2".to_owned()))
+        );
+        assert_eq!(
+            check("match x with (true, _) -> 1 | (_, 0) -> 2"),
+            (vec![], Some("(false, 1)".to_owned()))
+        );
+        // Guarded arms may not match
+        assert_eq!(
+            check("match x with n when n > 0 -> 1"),
+            (vec![], Some("_".to_owned()))
+        );
+    }
+
+    #[test]
+    fn redundant_arms() {
+        assert_eq!(check("match x with _ -> 1 | 0 -> 2"), (vec![1], None));
+        assert_eq!(
+            check("match x with (true, _) -> 1 | (false, _) -> 2 | (_, 3) -> 3"),
+            (vec![2], None)
+        );
+        assert_eq!(
+            check("match x with n when n > 0 -> 1 | 1 -> 2 | _ -> 3"),
+            (vec![], None)
+        );
+    }
+
+    #[test]
+    fn variants() {
+        let ty = "type t = A | B of int * t | C of t;; ";
+        assert_eq!(
+            check(&format!(
+                "{}match x with A -> 1 | B (_, A) -> 2 | B (_, _) -> 3 | C _ -> 4",
+                ty
+            )),
+            (vec![], None)
+        );
+        assert_eq!(
+            check(&format!("{}match x with A -> 1 | C _ -> 2", ty)),
+            (vec![], Some("B (_, _)".to_owned()))
+        );
+        assert_eq!(
+            check(&format!(
+                "{}match x with A -> 1 | B (_, _) -> 2 | C A -> 3 | C (B (0, _)) -> 4",
+                ty
+            )),
+            (vec![], Some("C (C _)".to_owned()))
+        );
+        assert_eq!(
+            check(&format!("{}match x with C _ -> 1 | C A -> 2 | _ -> 3", ty)),
+            (vec![1], None)
+        );
+    }
+
+    #[test]
+    fn exceptions() {
+        // More exceptions can be declared, only a wildcard matches all of them. Exceptions of the
+        // runtime system are declared first.
+        let exns = "exception A;; exception B of int;; ";
+        assert_eq!(
+            check(&format!("{}match x with A -> 1 | B _ -> 2", exns)),
+            (vec![], Some("Match_failure (_, _, _)".to_owned()))
+        );
+        assert_eq!(
+            check(&format!("{}match x with B 0 -> 1", exns)),
+            (vec![], Some("Match_failure (_, _, _)".to_owned()))
+        );
+        assert_eq!(
+            check(&format!("{}match x with A -> 1 | B _ -> 2 | _ -> 3", exns)),
+            (vec![], None)
+        );
+        assert_eq!(
+            check(&format!(
+                "{}match x with B _ -> 1 | B 0 -> 2 | _ -> 3",
+                exns
+            )),
+            (vec![1], None)
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(check("match x with [] -> 1 | _ :: _ -> 2"), (vec![], None));
+        assert_eq!(
+            check("match x with [] -> 1 | [_] -> 2 | _ :: _ :: _ -> 3"),
+            (vec![], None)
+        );
+        assert_eq!(
+            check("match x with [] -> 1 | [_] -> 2"),
+            (vec![], Some("_ :: _ :: _".to_owned()))
+        );
+        assert_eq!(
+            check("match x with (0 :: _) :: _ -> 1 | [] -> 2"),
+            (vec![], Some("[] :: _".to_owned()))
+        );
+        assert_eq!(
+            check("match x with _ :: _ -> 1 | [] -> 2 | [_; _] -> 3"),
+            (vec![2], None)
+        );
+    }
+}
@@ -1,3 +1,10 @@
+// lexgen generates manual range checks
+#![allow(clippy::manual_range_contains)]
+
+use crate::diagnostics::Diagnostic;
+use crate::span::Span;
+
+use std::fmt;
 use std::str::FromStr;
 
 use lexgen::lexer;
@@ -6,19 +13,51 @@ use lexgen::lexer;
 pub enum Token {
     LParen,
     RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
     Bool(bool),
     Not,
+    Ref,
+    Assert,
+    Bang,
+    ColonEqual,
     If,
     Then,
     Else,
     Let,
     Rec,
+    And,
     In,
+    Type,
+    Of,
+    Mutable,
+    External,
+    Exception,
+    Fun,
+    MinusGreater,
+    Match,
+    Try,
+    With,
+    For,
+    To,
+    Downto,
+    While,
+    Do,
+    Done,
+    When,
+    Bar,
+    AmpAmp,
+    BarBar,
     Minus,
     MinusDot,
     Plus,
     PlusDot,
+    Ast,
     AstDot,
+    Slash,
+    Mod,
     SlashDot,
     Equal,
     LessGreater,
@@ -29,18 +68,111 @@ pub enum Token {
     Greater,
     Dot,
     Comma,
+    Colon,
+    ColonColon,
     Semicolon,
+    SemiSemi,
+    Caret,
     Underscore,
-    ArrayCreate,
     Id(String),
+    // Capitalized identifier
+    UpperId(String),
     Int(i64),
     Float(f64),
+    // Bytes of a string literal, after escapes
+    String(Vec<u8>),
+    Char(u8),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Bool(b) => return write!(f, "{}", b),
+            Token::Not => "not",
+            Token::Ref => "ref",
+            Token::Assert => "assert",
+            Token::Bang => "!",
+            Token::ColonEqual => ":=",
+            Token::If => "if",
+            Token::Then => "then",
+            Token::Else => "else",
+            Token::Let => "let",
+            Token::Rec => "rec",
+            Token::And => "and",
+            Token::In => "in",
+            Token::Type => "type",
+            Token::Of => "of",
+            Token::Mutable => "mutable",
+            Token::External => "external",
+            Token::Exception => "exception",
+            Token::Fun => "fun",
+            Token::MinusGreater => "->",
+            Token::Match => "match",
+            Token::Try => "try",
+            Token::With => "with",
+            Token::For => "for",
+            Token::To => "to",
+            Token::Downto => "downto",
+            Token::While => "while",
+            Token::Do => "do",
+            Token::Done => "done",
+            Token::When => "when",
+            Token::Bar => "|",
+            Token::AmpAmp => "&&",
+            Token::BarBar => "||",
+            Token::Minus => "-",
+            Token::MinusDot => "-.",
+            Token::Plus => "+",
+            Token::PlusDot => "+.",
+            Token::Ast => "*",
+            Token::AstDot => "*.",
+            Token::Slash => "/",
+            Token::Mod => "mod",
+            Token::SlashDot => "/.",
+            Token::Equal => "=",
+            Token::LessGreater => "<>",
+            Token::LessEqual => "<=",
+            Token::LessMinus => "<-",
+            Token::Less => "<",
+            Token::GreaterEqual => ">=",
+            Token::Greater => ">",
+            Token::Dot => ".",
+            Token::Comma => ",",
+            Token::Colon => ":",
+            Token::ColonColon => "::",
+            Token::Semicolon => ";",
+            Token::SemiSemi => ";;",
+            Token::Caret => "^",
+            Token::Underscore => "_",
+            Token::Id(id) | Token::UpperId(id) => id,
+            Token::Int(i) => return write!(f, "{}", i),
+            Token::Float(d) => return write!(f, "{:?}", d),
+            Token::String(bytes) => return write!(f, "{:?}", String::from_utf8_lossy(bytes)),
+            Token::Char(c) => return write!(f, "'{}'", std::ascii::escape_default(*c)),
+        };
+        f.write_str(s)
+    }
+}
+
+/// A token with its location in the source
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum LexErr {
     InvalidFloat { found: String },
     InvalidInt { found: String },
+    InvalidEscape { found: String },
+    WrongCharLength { found: String },
 }
 
 #[derive(Debug, Default)]
@@ -48,18 +180,119 @@ pub struct LexerState {
     comment_depth: usize,
 }
 
-pub fn tokenize(expr_str: &str) -> Result<Vec<Token>, LexerError> {
-    let lexer = Lexer::new(expr_str);
+// Contents of a string or character literal, without the quotes. Escapes are as in OCaml:
+// `\\`, `\"`, `\'`, `\n`, `\t`, `\r`, `\b`, `\ `, and `\ddd` with a decimal character code.
+fn unescape(s: &str) -> Result<Vec<u8>, LexErr> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(s.len());
+    let mut iter = s.bytes().enumerate();
+    while let Some((idx, byte)) = iter.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let escaped = match iter.next().map(|(_, byte)| byte) {
+            Some(b'\\') => b'\\',
+            Some(b'"') => b'"',
+            Some(b'\'') => b'\'',
+            Some(b'n') => b'\n',
+            Some(b't') => b'\t',
+            Some(b'r') => b'\r',
+            Some(b'b') => 8,
+            Some(b' ') => b' ',
+            Some(b'0'..=b'9') => {
+                let code = s
+                    .get(idx + 1..idx + 4)
+                    .and_then(|code| code.parse::<u8>().ok());
+                match code {
+                    Some(code) => {
+                        iter.next();
+                        iter.next();
+                        code
+                    }
+                    None => return Err(invalid_escape(s, idx)),
+                }
+            }
+            _ => return Err(invalid_escape(s, idx)),
+        };
+        bytes.push(escaped);
+    }
+    Ok(bytes)
+}
+
+fn invalid_escape(s: &str, backslash_idx: usize) -> LexErr {
+    let found: String = s[backslash_idx..].chars().take(2).collect();
+    LexErr::InvalidEscape { found }
+}
+
+pub fn tokenize(expr_str: &str) -> Result<Vec<SpannedToken>, (LexerError, Span)> {
+    let mut lexer = Lexer::new(expr_str);
     let mut tokens = vec![];
-    for next in lexer {
+    while let Some(next) = lexer.next() {
         match next {
-            Ok(token) => tokens.push(token.1),
-            Err(err) => return Err(err),
+            Ok((start, token, end)) => {
+                tokens.push(SpannedToken { token, span: Span::new(start, end) })
+            }
+            Err(err) => {
+                let span = match err {
+                    LexerError::LexerError { char_idx } => {
+                        // Underline the character we couldn't lex
+                        let char_len = expr_str[char_idx..]
+                            .chars()
+                            .next()
+                            .map(char::len_utf8)
+                            .unwrap_or(0);
+                        Span::new(char_idx, char_idx + char_len)
+                    }
+                    // User errors are raised in semantic actions, after matching the whole token
+                    LexerError::UserError(_) => {
+                        Span::new(lexer.current_match_start, lexer.current_match_end)
+                    }
+                };
+                return Err((err, span));
+            }
         }
     }
     Ok(tokens)
 }
 
+pub fn lexer_err_diagnostic(err: &LexerError, span: Span, source: &str) -> Diagnostic {
+    match err {
+        // `(` is always a valid token, so a failure at `(*` means the comment is not closed
+        LexerError::LexerError { .. } if source[span.start..].starts_with("(*") => {
+            Diagnostic::error(
+                "unterminated comment",
+                Span::new(source.len(), source.len()),
+            )
+            .with_secondary(Span::new(span.start, span.start + 2), "comment starts here")
+        }
+        LexerError::LexerError { .. } if source[span.start..].starts_with('"') => {
+            Diagnostic::error("unterminated string", Span::new(source.len(), source.len()))
+                .with_secondary(Span::new(span.start, span.start + 1), "string starts here")
+        }
+        LexerError::LexerError { .. } => Diagnostic::error(
+            format!("unexpected character `{}`", &source[span.start..span.end]),
+            span,
+        )
+        .with_label("unexpected character"),
+        LexerError::UserError(LexErr::InvalidInt { found }) => {
+            Diagnostic::error(format!("invalid integer literal `{}`", found), span)
+                .with_label("integer literal is too large")
+                .with_note(format!("the largest integer is {}", i64::MAX))
+        }
+        LexerError::UserError(LexErr::InvalidFloat { found }) => {
+            Diagnostic::error(format!("invalid float literal `{}`", found), span)
+        }
+        LexerError::UserError(LexErr::InvalidEscape { found }) => {
+            Diagnostic::error(format!("invalid escape sequence `{}`", found), span)
+                .with_note(r#"valid escapes are \\, \", \', \n, \t, \r, \b, \<space> and \ddd"#)
+        }
+        LexerError::UserError(LexErr::WrongCharLength { found }) => {
+            Diagnostic::error(format!("invalid character literal `{}`", found), span)
+                .with_label("character literals must have exactly one character")
+        }
+    }
+}
+
 lexer! {
     pub Lexer(LexerState) -> Token;
 
@@ -70,20 +303,52 @@ lexer! {
 
         "(" = Token::LParen,
         ")" = Token::RParen,
+        "{" = Token::LBrace,
+        "}" = Token::RBrace,
+        "[" = Token::LBracket,
+        "]" = Token::RBracket,
         "true" = Token::Bool(true),
         "false" = Token::Bool(false),
         "not" = Token::Not,
+        "ref" = Token::Ref,
+        "assert" = Token::Assert,
+        "!" = Token::Bang,
+        ":=" = Token::ColonEqual,
         "if" = Token::If,
         "then" = Token::Then,
         "else" = Token::Else,
         "let" = Token::Let,
         "rec" = Token::Rec,
+        "and" = Token::And,
         "in" = Token::In,
+        "type" = Token::Type,
+        "of" = Token::Of,
+        "mutable" = Token::Mutable,
+        "external" = Token::External,
+        "exception" = Token::Exception,
+        "fun" = Token::Fun,
+        "->" = Token::MinusGreater,
+        "match" = Token::Match,
+        "try" = Token::Try,
+        "with" = Token::With,
+        "for" = Token::For,
+        "to" = Token::To,
+        "downto" = Token::Downto,
+        "while" = Token::While,
+        "do" = Token::Do,
+        "done" = Token::Done,
+        "when" = Token::When,
+        "|" = Token::Bar,
+        "&&" = Token::AmpAmp,
+        "||" = Token::BarBar,
         "-" = Token::Minus,
         "-." = Token::MinusDot,
         "+" = Token::Plus,
         "+." = Token::PlusDot,
+        "*" = Token::Ast,
         "*." = Token::AstDot,
+        "/" = Token::Slash,
+        "mod" = Token::Mod,
         "/." = Token::SlashDot,
         "=" = Token::Equal,
         "<>" = Token::LessGreater,
@@ -94,10 +359,12 @@ lexer! {
         ">" = Token::Greater,
         "." = Token::Dot,
         "," = Token::Comma,
+        ":" = Token::Colon,
+        "::" = Token::ColonColon,
         ";" = Token::Semicolon,
+        ";;" = Token::SemiSemi,
+        "^" = Token::Caret,
         "_" = Token::Underscore,
-        "Array.create" = Token::ArrayCreate,
-        "Array.make" = Token::ArrayCreate,
 
         ['a'-'z'] ['a'-'z' 'A'-'Z' '_' '0'-'9']* =>
             |lexer| {
@@ -105,6 +372,12 @@ lexer! {
                 lexer.return_(Token::Id(match_.to_owned()))
             },
 
+        ['A'-'Z'] ['a'-'z' 'A'-'Z' '_' '0'-'9']* =>
+            |lexer| {
+                let match_ = lexer.match_();
+                lexer.return_(Token::UpperId(match_.to_owned()))
+            },
+
         ['0'-'9']+ =?
             |lexer| {
                 let match_ = lexer.match_();
@@ -128,13 +401,50 @@ lexer! {
                 lexer.state().comment_depth = 1;
                 lexer.switch(LexerRule::Comment)
             },
+
+        '"' => |lexer| lexer.switch(LexerRule::String),
+
+        '\'' => |lexer| lexer.switch(LexerRule::Char),
+    },
+
+    // In string and character literals the match includes the opening quote, so we unescape the
+    // whole literal when we see the closing quote. Escaped quotes and backslashes are skipped here
+    // so that they don't end the literal.
+    rule String {
+        "\\\\" | "\\\"",
+
+        '"' =?
+            |lexer| {
+                let match_ = lexer.match_();
+                let ret = unescape(&match_[1..match_.len() - 1]).map(Token::String);
+                lexer.switch_and_return(LexerRule::Init, ret)
+            },
+
+        _,
+    },
+
+    rule Char {
+        "\\\\" | "\\'",
+
+        '\'' =?
+            |lexer| {
+                let match_ = lexer.match_();
+                let ret = match unescape(&match_[1..match_.len() - 1]) {
+                    Ok(bytes) if bytes.len() == 1 => Ok(Token::Char(bytes[0])),
+                    Ok(_) => Err(LexErr::WrongCharLength { found: match_.to_owned() }),
+                    Err(err) => Err(err),
+                };
+                lexer.switch_and_return(LexerRule::Init, ret)
+            },
+
+        _,
     },
 
     rule Comment {
         "(*" =>
             |mut lexer| {
                 let depth = &mut lexer.state().comment_depth;
-                *depth =  *depth + 1;
+                *depth += 1;
                 lexer.continue_()
             },
 
@@ -144,7 +454,7 @@ lexer! {
                 if *depth == 1 {
                     lexer.switch(LexerRule::Init)
                 } else {
-                    *depth = *depth - 1;
+                    *depth -= 1;
                     lexer.continue_()
                 }
             },
@@ -173,3 +483,75 @@ fn lexer_test() {
     assert_eq!(unwrap_ignore_pos(lexer.next()), Token::Rec);
     assert_eq!(lexer.next(), None);
 }
+
+#[test]
+fn tokenize_spans() {
+    let input = "let x = 12 in (* comment *) x +. 3.5";
+    let spans: Vec<(Token, Span)> = tokenize(input)
+        .unwrap()
+        .into_iter()
+        .map(|t| (t.token, t.span))
+        .collect();
+    assert_eq!(
+        spans,
+        vec![
+            (Token::Let, Span::new(0, 3)),
+            (Token::Id("x".to_owned()), Span::new(4, 5)),
+            (Token::Equal, Span::new(6, 7)),
+            (Token::Int(12), Span::new(8, 10)),
+            (Token::In, Span::new(11, 13)),
+            (Token::Id("x".to_owned()), Span::new(28, 29)),
+            (Token::PlusDot, Span::new(30, 32)),
+            (Token::Float(3.5), Span::new(33, 36)),
+        ]
+    );
+}
+
+#[test]
+fn string_literals() {
+    let input = r#""a\"b\\c\n\065" ^ "" 'x' '\'' '\010'"#;
+    let tokens: Vec<Token> = tokenize(input)
+        .unwrap()
+        .into_iter()
+        .map(|t| t.token)
+        .collect();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::String(b"a\"b\\c\nA".to_vec()),
+            Token::Caret,
+            Token::String(vec![]),
+            Token::Char(b'x'),
+            Token::Char(b'\''),
+            Token::Char(b'\n'),
+        ]
+    );
+
+    assert!(tokenize(r#""a\qb""#).is_err());
+    assert!(tokenize("'ab'").is_err());
+}
+
+#[test]
+fn list_tokens() {
+    let input = "x :: [1; 2] : int list";
+    let tokens: Vec<Token> = tokenize(input)
+        .unwrap()
+        .into_iter()
+        .map(|t| t.token)
+        .collect();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Id("x".to_owned()),
+            Token::ColonColon,
+            Token::LBracket,
+            Token::Int(1),
+            Token::Semicolon,
+            Token::Int(2),
+            Token::RBracket,
+            Token::Colon,
+            Token::Id("int".to_owned()),
+            Token::Id("list".to_owned()),
+        ]
+    );
+}
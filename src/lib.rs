@@ -6,20 +6,28 @@ mod cg_types;
 mod codegen;
 mod common;
 mod ctx;
+mod diagnostics;
+mod exhaustiveness;
 mod interner;
 mod lexer;
 mod locals;
 mod lower;
+mod mono;
 mod parser;
 mod perf;
+mod prelude;
+mod span;
 mod type_check;
 mod utils;
 mod var;
 
 use anormal::anormal;
 use codegen::codegen;
-use lexer::{tokenize, Token};
+use diagnostics::Diagnostic;
+use lexer::{lexer_err_diagnostic, tokenize, SpannedToken};
 use lower::lower_pgm;
+use mono::monomorphize;
+use prelude::add_prelude;
 use type_check::type_check_pgm;
 
 use std::fs::File;
@@ -53,15 +61,21 @@ fn record_pass_stats<A, F: FnOnce() -> A>(
 
 type ObjectCode = Vec<u8>;
 
+fn report_diagnostic(diagnostic: &Diagnostic, path: &str, source: &str) {
+    let mut s = String::new();
+    diagnostic.render(path, source, &mut s).unwrap();
+    eprintln!("{}", s);
+}
+
 fn compile_expr(
-    expr_str: &str, dump_cc: bool, dump_cg: bool, show_pass_stats: bool,
+    path: &str, expr_str: &str, dump_cc: bool, dump_cg: bool, show_pass_stats: bool,
 ) -> Option<ObjectCode> {
     let mut pass_stats: Vec<PassStats> = Vec::with_capacity(10);
 
-    let tokens: Vec<Token> =
+    let tokens: Vec<SpannedToken> =
         match record_pass_stats(&mut pass_stats, "tokenize", || tokenize(expr_str)) {
-            Err(err) => {
-                println!("Lexer error: {:#?}", err);
+            Err((err, span)) => {
+                report_diagnostic(&lexer_err_diagnostic(&err, span, expr_str), path, expr_str);
                 return None;
             }
             Ok(tokens) => tokens,
@@ -71,32 +85,40 @@ fn compile_expr(
 
     let mut ctx = Default::default();
 
-    let expr = match record_pass_stats(&mut pass_stats, "parse", || {
-        parser::Expr::parse(tokens.into_iter().map(|r| Ok::<_, ()>(r)))
-    }) {
-        Err(err) => {
-            println!("Parser error: {:#?}", err);
-            return None;
+    let (expr, parse_errs) = record_pass_stats(&mut pass_stats, "parse", || parser::parse(&tokens));
+    if !parse_errs.is_empty() {
+        for err in &parse_errs {
+            report_diagnostic(&err.to_diagnostic(), path, expr_str);
         }
-        Ok(expr) => expr,
-    };
+        return None;
+    }
 
     // println!("Expr: {:#?}", expr);
 
+    let expr = record_pass_stats(&mut pass_stats, "prelude", || add_prelude(expr));
+
     let mut expr = record_pass_stats(&mut pass_stats, "intern", || expr.intern(&mut ctx));
 
     match record_pass_stats(&mut pass_stats, "type check", || {
         type_check_pgm(&mut ctx, &mut expr)
     }) {
         Err(err) => {
-            println!("Type error: {:#?}", err);
+            report_diagnostic(&err.to_diagnostic(&ctx), path, expr_str);
             return None;
         }
-        Ok(()) => {}
-    };
+        Ok(warnings) => {
+            for warning in &warnings {
+                report_diagnostic(&warning.to_diagnostic(), path, expr_str);
+            }
+        }
+    }
 
     // println!("Type-checked expr: {:#?}", expr);
 
+    record_pass_stats(&mut pass_stats, "monomorphize", || {
+        monomorphize(&mut ctx, &mut expr)
+    });
+
     let expr = record_pass_stats(&mut pass_stats, "anormal", || anormal(&mut ctx, expr));
 
     // println!("K normalized:");
@@ -122,7 +144,7 @@ fn compile_expr(
     }
 
     let object_code = record_pass_stats(&mut pass_stats, "codegen", || {
-        codegen(&mut ctx, &funs, main, dump_cg)
+        codegen(&mut ctx, &funs, main, path, expr_str, dump_cg)
     });
 
     if show_pass_stats {
@@ -162,7 +184,7 @@ pub fn compile_file(
     path: &str, out_dir: Option<&str>, dump_cc: bool, dump_cg: bool, show_pass_stats: bool,
 ) -> i32 {
     let contents = std::fs::read_to_string(path).unwrap();
-    match compile_expr(&contents, dump_cc, dump_cg, show_pass_stats) {
+    match compile_expr(path, &contents, dump_cc, dump_cg, show_pass_stats) {
         None => 1,
         Some(object_code) => link(path, out_dir, object_code),
     }
@@ -174,14 +196,14 @@ fn link(path: &str, out_dir: Option<&str>, object_code: ObjectCode) -> i32 {
     let file_stem = path.file_stem().unwrap().to_str().unwrap();
     let o_file_name = format!("{}.o", file_stem);
 
-    File::create(&format!("{}/{}", out_dir, o_file_name))
+    File::create(format!("{}/{}", out_dir, o_file_name))
         .unwrap()
         .write_all(&object_code)
         .unwrap();
 
     // Build RTS
     let output = Command::new("gcc")
-        .args(&["rts.c", "-c", "-o", &format!("{}/rts.o", out_dir)])
+        .args(["rts.c", "-c", "-o", &format!("{}/rts.o", out_dir)])
         .spawn()
         .unwrap()
         .wait_with_output()
@@ -191,7 +213,7 @@ fn link(path: &str, out_dir: Option<&str>, object_code: ObjectCode) -> i32 {
 
     // Link
     let output = Command::new("gcc")
-        .args(&[
+        .args([
             &o_file_name,
             "rts.o",
             "-o",
@@ -3,12 +3,11 @@ mod types;
 
 use crate::anormal;
 use crate::cg_types::RepType;
-use crate::common::{BinOp, Cmp, IntBinOp};
+use crate::common::{self, BinOp, Cmp, ForDir, IntBinOp};
 use crate::ctx::{Ctx, VarId};
 use crate::type_check::Type;
 use crate::var::CompilerPhase::ClosureConvert;
 
-pub use print::*;
 pub use types::*;
 
 use cranelift_entity::PrimaryMap;
@@ -145,6 +144,8 @@ impl<'ctx> CcCtx<'ctx> {
 }
 
 pub fn lower_pgm(ctx: &mut Ctx, expr: anormal::Expr) -> (Vec<Fun>, VarId) {
+    add_globals(ctx, &expr);
+
     let mut ctx = CcCtx::new(ctx);
 
     let main_name = ctx.fresh_var(RepType::Word);
@@ -161,6 +162,26 @@ pub fn lower_pgm(ctx: &mut Ctx, expr: anormal::Expr) -> (Vec<Fun>, VarId) {
     (ctx.funs, main_name)
 }
 
+// Top-level arrays are shared by all functions in the program, so instead of capturing them in
+// every closure we allocate them in module data objects. Only the binders in the spine of `let`s
+// of the program are considered, other binders may be evaluated more than once.
+fn add_globals(ctx: &mut Ctx, mut expr: &anormal::Expr) {
+    loop {
+        match expr {
+            anormal::Expr::Let { id, rhs: _, body } => {
+                if let Some(Type::Array(_)) = ctx.var_type_(*id).as_deref() {
+                    ctx.add_global(*id);
+                }
+                expr = body;
+            }
+            anormal::Expr::LetRec { defs: _, body } => {
+                expr = body;
+            }
+            _ => break,
+        }
+    }
+}
+
 // Returns whether the added block was a fork (i.e. then or else branch of an if)
 fn cc_block(ctx: &mut CcCtx, mut block: BlockBuilder, sequel: Sequel, expr: anormal::Expr) {
     match expr {
@@ -170,6 +191,12 @@ fn cc_block(ctx: &mut CcCtx, mut block: BlockBuilder, sequel: Sequel, expr: anor
 
         anormal::Expr::Float(f) => ctx.finish_block(block, sequel, Atom::Float(f)),
 
+        anormal::Expr::String(bytes) => {
+            let tmp = sequel.get_ret_var(ctx, RepType::Word);
+            block.asgn(tmp, Expr::String(bytes));
+            ctx.finish_block(block, sequel, Atom::Var(tmp));
+        }
+
         anormal::Expr::Neg(var) => {
             let tmp = ctx.fresh_var(RepType::Word);
             block.asgn(tmp, Expr::Neg(var));
@@ -194,21 +221,10 @@ fn cc_block(ctx: &mut CcCtx, mut block: BlockBuilder, sequel: Sequel, expr: anor
             ctx.finish_block(block, sequel, Atom::Var(tmp));
         }
 
-        anormal::Expr::If(v1, v2, cmp, e1, e2) => {
+        anormal::Expr::If(cond, e1, e2) => {
             let then_block = ctx.create_block();
             let else_block = ctx.create_block();
-            ctx.finish_block_(Block {
-                idx: block.idx,
-                comment: block.comment,
-                stmts: block.stmts,
-                exit: Exit::Branch {
-                    v1,
-                    v2,
-                    cond: cmp,
-                    then_block: then_block.idx,
-                    else_block: else_block.idx,
-                },
-            });
+            cc_cond(ctx, block, cond, then_block.idx, else_block.idx);
             cc_block(ctx, then_block, sequel.clone(), *e1);
             cc_block(ctx, else_block, sequel, *e2);
         }
@@ -217,7 +233,7 @@ fn cc_block(ctx: &mut CcCtx, mut block: BlockBuilder, sequel: Sequel, expr: anor
             ctx.finish_block(block, sequel, Atom::Var(var));
         }
 
-        anormal::Expr::Let { id, ty_id: _, rhs, body } => {
+        anormal::Expr::Let { id, rhs, body } => {
             // TODO: When the RHS is not if-then-else we can continue extending the last block RHS
             // generates and avoid creating a block for the continuation.
             let cont_block = ctx.create_block();
@@ -226,56 +242,70 @@ fn cc_block(ctx: &mut CcCtx, mut block: BlockBuilder, sequel: Sequel, expr: anor
             cc_block(ctx, cont_block, sequel, *body)
         }
 
-        anormal::Expr::LetRec { name, ty_id, mut args, rhs, body } => {
-            // TODO: Not sure about reusing 'name' in multiple places below.
+        anormal::Expr::LetRec { defs, body } => {
+            // Closure tuples of the functions, with the functions and the captured variables
+            let mut closures: Vec<(VarId, Vec<VarId>)> = Vec::with_capacity(defs.len());
+
+            for anormal::FunDef { name, ty_id, mut args, rhs } in defs {
+                // TODO: Not sure about reusing 'name' in multiple places below.
+
+                // After cc 'name' will refer to the closure tuple. For the function we'll need a
+                // fresh variable.
+                let fun_var = ctx.fresh_var(RepType::Word);
+
+                // Free variables of the closure will be moved to tuple payload. Other functions
+                // in the group are captured like any other variable.
+                // NOTE: An inefficiency here is that if we have deeply nested letrecs we'll be
+                // computing fvs of nested letrecs when computing the outer ones. One solution
+                // could be to annotate LetRecs with fvs. Doesn't matter in practice though.
+                let closure_fvs: Vec<VarId> = {
+                    let mut closure_fvs: FxHashSet<VarId> = Default::default();
+                    fvs(ctx.ctx, &rhs, &mut closure_fvs);
+                    closure_fvs.remove(&name);
+                    for arg in &args {
+                        closure_fvs.remove(arg);
+                    }
+                    closure_fvs.into_iter().collect()
+                };
 
-            // After cc 'name' will refer to the closure tuple. For the function we'll need a fresh
-            // variable.
-            let fun_var = ctx.fresh_var(RepType::Word);
+                // In the RHS and the body, 'name' will refer to the tuple. However in the RHS the
+                // tuple will be the first argument of the function, in the body we'll allocate a
+                // tuple.
+
+                // Emit function
+                args.insert(0, name); // first argument will be 'self'
+                ctx.fork_fun(|ctx| {
+                    let mut entry_block = ctx.create_block();
+                    // Bind captured variables in function body
+                    for (fv_idx, fv) in closure_fvs.iter().enumerate() {
+                        let fv_ty = ctx.ctx.var_rep_type(*fv);
+                        entry_block.asgn(*fv, Expr::TupleGet(name, fv_idx + 1, fv_ty));
+                    }
+                    cc_block(ctx, entry_block, Sequel::Return, rhs);
 
-            // Free variables of the closure will be moved to tuple payload
-            // NOTE: An inefficiency here is that if we have deeply nested letrecs we'll be
-            // computing fvs of nested letrecs when computing the outer ones. One solution could be
-            // to annotate LetRecs with fvs. Doesn't matter in practice though.
-            let closure_fvs: Vec<VarId> = {
-                let mut closure_fvs: FxHashSet<VarId> = Default::default();
-                fvs(ctx.ctx, &*rhs, &mut closure_fvs);
-                closure_fvs.remove(&name);
-                for arg in &args {
-                    closure_fvs.remove(arg);
-                }
-                closure_fvs.into_iter().collect()
-            };
+                    let fun_type = ctx.ctx.get_type(ty_id);
+                    let fun_return_type = match &*fun_type {
+                        Type::Fun { ret, .. } => RepType::from(&**ret),
+                        _ => panic!("Non-function in function position"),
+                    };
 
-            // In the RHS and the body, 'name' will refer to the tuple. However in the RHS the
-            // tuple will be the first argument of the function, in the body we'll allocate a
-            // tuple.
-
-            // Emit function
-            args.insert(0, name); // first argument will be 'self'
-            ctx.fork_fun(|ctx| {
-                let mut entry_block = ctx.create_block();
-                // Bind captured variables in function body
-                for (fv_idx, fv) in closure_fvs.iter().enumerate() {
-                    entry_block.asgn(*fv, Expr::TupleGet(name, fv_idx + 1));
-                }
-                cc_block(ctx, entry_block, Sequel::Return, *rhs);
+                    FunSig { name: fun_var, args, return_type: fun_return_type }
+                });
 
-                let fun_type = ctx.ctx.get_type(ty_id);
-                let fun_return_type = match &*fun_type {
-                    Type::Fun { ret, .. } => RepType::from(&**ret),
-                    _ => panic!("Non-function in function position"),
-                };
-
-                FunSig { name: fun_var, args, return_type: fun_return_type }
-            });
+                let mut closure_tuple_args = closure_fvs;
+                closure_tuple_args.insert(0, fun_var);
+                closures.push((name, closure_tuple_args));
+            }
 
-            // Body
-            let mut closure_tuple_args = closure_fvs;
-            closure_tuple_args.insert(0, fun_var);
-            block.asgn(name, Expr::Tuple { len: closure_tuple_args.len() });
-            for (arg_idx, arg) in closure_tuple_args.iter().enumerate() {
-                block.expr(Expr::TuplePut(name, arg_idx, *arg));
+            // Body. Allocate all closures before initializing them, as they may refer to each
+            // other.
+            for (name, closure_tuple_args) in &closures {
+                block.asgn(*name, Expr::Tuple { len: closure_tuple_args.len() });
+            }
+            for (name, closure_tuple_args) in &closures {
+                for (arg_idx, arg) in closure_tuple_args.iter().enumerate() {
+                    block.expr(Expr::TuplePut(*name, arg_idx, *arg));
+                }
             }
             cc_block(ctx, block, sequel, *body)
         }
@@ -283,19 +313,31 @@ fn cc_block(ctx: &mut CcCtx, mut block: BlockBuilder, sequel: Sequel, expr: anor
         anormal::Expr::App(fun, mut args) => {
             // f(x) -> f.0(f, x)
             let fun_tmp = ctx.fresh_var(RepType::Word);
-            block.asgn(fun_tmp, Expr::TupleGet(fun, 0));
-            args.insert(0, fun);
+            block.asgn(fun_tmp, Expr::TupleGet(fun, 0, RepType::Word));
 
-            let fun_ret_ty = match &*ctx.ctx.var_type(fun) {
-                Type::Fun { args: _, ret } => RepType::from(&**ret),
-                other => panic!("Non-function in function position: {:?}", other),
-            };
+            // The function is applied to the number of arguments it takes, see `App` in `anormal`
+            let fun_ret_ty = RepType::from(&ctx.ctx.var_type(fun).apply(args.len()));
+            args.insert(0, fun);
             let ret_tmp = sequel.get_ret_var(ctx, fun_ret_ty);
 
             block.asgn(ret_tmp, Expr::App(fun_tmp, args, fun_ret_ty));
             ctx.finish_block(block, sequel, Atom::Var(ret_tmp));
         }
 
+        anormal::Expr::PartialApp(fun, args) => {
+            let (param_tys, ret_ty): (Vec<RepType>, RepType) = match &*ctx.ctx.var_type(fun) {
+                Type::Fun { args, ret } => (
+                    args.iter().map(RepType::from).collect(),
+                    RepType::from(&**ret),
+                ),
+                other => panic!("Non-function in function position: {:?}", other),
+            };
+            let pap_var = cc_pap(ctx, &param_tys, ret_ty, args.len());
+            let closure_tmp = sequel.get_ret_var(ctx, RepType::Word);
+            pap_closure(&mut block, closure_tmp, pap_var, fun, &args);
+            ctx.finish_block(block, sequel, Atom::Var(closure_tmp));
+        }
+
         anormal::Expr::Tuple(args) => {
             let ret_tmp = sequel.get_ret_var(ctx, RepType::Word);
             block.asgn(ret_tmp, Expr::Tuple { len: args.len() });
@@ -308,16 +350,25 @@ fn cc_block(ctx: &mut CcCtx, mut block: BlockBuilder, sequel: Sequel, expr: anor
         anormal::Expr::TupleGet(tuple, idx) => {
             let elem_ty = match &*ctx.ctx.var_type(tuple) {
                 Type::Tuple(args) => RepType::from(&args[idx]),
+                Type::Ref(elem) => RepType::from(&**elem),
+                Type::Record(name) => {
+                    RepType::from(&ctx.ctx.get_record(name).unwrap().fields[idx].ty)
+                }
                 other => panic!(
                     "Non-tuple type in tuple position: {:?} (type={:?})",
                     tuple, other
                 ),
             };
             let ret_tmp = sequel.get_ret_var(ctx, elem_ty);
-            block.asgn(ret_tmp, Expr::TupleGet(tuple, idx));
+            block.asgn(ret_tmp, Expr::TupleGet(tuple, idx, elem_ty));
             ctx.finish_block(block, sequel, Atom::Var(ret_tmp));
         }
 
+        anormal::Expr::TuplePut(tuple, idx, val) => {
+            block.expr(Expr::TuplePut(tuple, idx, val));
+            ctx.finish_block(block, sequel, Atom::Unit);
+        }
+
         anormal::Expr::ArrayAlloc { len, elem } => {
             let array_tmp = sequel.get_ret_var(ctx, RepType::Word);
             block.asgn(array_tmp, Expr::ArrayAlloc { len });
@@ -382,24 +433,270 @@ fn cc_block(ctx: &mut CcCtx, mut block: BlockBuilder, sequel: Sequel, expr: anor
         }
 
         anormal::Expr::ArrayPut(array, idx, val) => {
-            let elem_ty = match &*ctx.ctx.var_type(array) {
-                Type::Array(elem_ty) => RepType::from(&**elem_ty),
-                other => panic!(
-                    "Non-array type in array position: {:?} (type={:?})",
-                    array, other
-                ),
-            };
-            let ret_tmp = sequel.get_ret_var(ctx, elem_ty);
+            // Evaluates to unit
+            let ret_tmp = sequel.get_ret_var(ctx, RepType::Word);
             block.asgn(ret_tmp, Expr::ArrayPut(array, idx, val));
             ctx.finish_block(block, sequel, Atom::Var(ret_tmp));
         }
+
+        anormal::Expr::MatchFailure(span) => {
+            block.expr(Expr::MatchFailure(span));
+            ctx.finish_block(block, sequel, Atom::Unit);
+        }
+
+        anormal::Expr::Abort(abort, span) => {
+            block.expr(Expr::Abort(abort, span));
+            ctx.finish_block(block, sequel, Atom::Unit);
+        }
+
+        anormal::Expr::Try(body, handler) => {
+            let ret_ty = match &*ctx.ctx.var_type(body) {
+                Type::Fun { args: _, ret } => RepType::from(&**ret),
+                other => panic!("Non-function body in try: {:?}", other),
+            };
+            let ret_tmp = sequel.get_ret_var(ctx, ret_ty);
+            block.asgn(ret_tmp, Expr::Try(body, handler, ret_ty));
+            ctx.finish_block(block, sequel, Atom::Var(ret_tmp));
+        }
+
+        // The range is checked once before the loop, and the loop exits after the body when `var`
+        // is `end`, so that the step doesn't overflow when `end` is `max_int` (or `min_int`)
+        anormal::Expr::For { var, start, dir, end, body } => {
+            let mut loop_body_block = ctx.create_block();
+            let loop_test_block = ctx.create_block();
+            let mut loop_step_block = ctx.create_block();
+            let cont_block = ctx.create_block();
+            let loop_body_idx = loop_body_block.idx;
+
+            let (exit_cmp, step_op) = match dir {
+                ForDir::To => (Cmp::GreaterThan, IntBinOp::Add),
+                ForDir::Downto => (Cmp::LessThan, IntBinOp::Sub),
+            };
+
+            block.asgn(var, Expr::Atom(Atom::Var(start)));
+            ctx.finish_block_(Block {
+                idx: block.idx,
+                comment: block.comment,
+                stmts: block.stmts,
+                exit: Exit::Branch {
+                    v1: var,
+                    v2: end,
+                    cond: exit_cmp,
+                    then_block: cont_block.idx,
+                    else_block: loop_body_idx,
+                },
+            });
+
+            // loop_body, value of the body is ignored
+            loop_body_block.comment = Some("for loop body".to_string());
+            let body_var = ctx.fresh_var(RepType::Word);
+            cc_block(
+                ctx,
+                loop_body_block,
+                Sequel::Asgn(body_var, loop_test_block.idx),
+                *body,
+            );
+
+            // loop_test
+            ctx.finish_block_(Block {
+                idx: loop_test_block.idx,
+                comment: Some("for loop test".to_string()),
+                stmts: vec![],
+                exit: Exit::Branch {
+                    v1: var,
+                    v2: end,
+                    cond: Cmp::Equal,
+                    then_block: cont_block.idx,
+                    else_block: loop_step_block.idx,
+                },
+            });
+
+            // loop_step
+            let step_var = ctx.fresh_var(RepType::Word);
+            loop_step_block.asgn(step_var, Expr::Atom(Atom::Int(1)));
+            loop_step_block.asgn(
+                var,
+                Expr::IBinOp(BinOp { op: step_op, arg1: var, arg2: step_var }),
+            );
+            ctx.finish_block_(Block {
+                idx: loop_step_block.idx,
+                comment: Some("for loop step".to_string()),
+                stmts: loop_step_block.stmts,
+                exit: Exit::Jump(loop_body_idx),
+            });
+
+            ctx.finish_block(cont_block, sequel, Atom::Unit);
+        }
+
+        anormal::Expr::While { cond, body } => {
+            let mut loop_cond_block = ctx.create_block();
+            let mut loop_body_block = ctx.create_block();
+            let cont_block = ctx.create_block();
+            let loop_cond_idx = loop_cond_block.idx;
+
+            ctx.finish_block_(Block {
+                idx: block.idx,
+                comment: block.comment,
+                stmts: block.stmts,
+                exit: Exit::Jump(loop_cond_idx),
+            });
+
+            // loop_cond, may be multiple blocks
+            loop_cond_block.comment = Some("while loop cond".to_string());
+            let loop_body_idx = loop_body_block.idx;
+            cc_cond(ctx, loop_cond_block, cond, loop_body_idx, cont_block.idx);
+
+            // loop_body, value of the body is ignored
+            loop_body_block.comment = Some("while loop body".to_string());
+            let body_var = ctx.fresh_var(RepType::Word);
+            cc_block(
+                ctx,
+                loop_body_block,
+                Sequel::Asgn(body_var, loop_cond_idx),
+                *body,
+            );
+
+            ctx.finish_block(cont_block, sequel, Atom::Unit);
+        }
+    }
+}
+
+// Finish the block with branches to `then_idx` when the condition holds, to `else_idx` otherwise
+// Code of a partial application closure `[pap, fun, args...]`, where `args` are the first
+// `n_args` arguments of `fun`. Partial applications take one argument. With the last argument
+// `fun` is called with all of the arguments, otherwise the closure of the partial application with
+// one more argument is returned.
+fn cc_pap(ctx: &mut CcCtx, param_tys: &[RepType], ret_ty: RepType, n_args: usize) -> VarId {
+    let next_pap = if n_args + 1 < param_tys.len() {
+        Some(cc_pap(ctx, param_tys, ret_ty, n_args + 1))
+    } else {
+        None
+    };
+
+    let pap_var = ctx.fresh_var(RepType::Word);
+    ctx.fork_fun(|ctx| {
+        let self_var = ctx.fresh_var(RepType::Word);
+        let arg_var = ctx.fresh_var(param_tys[n_args]);
+
+        let mut entry_block = ctx.create_block();
+        let fun_var = ctx.fresh_var(RepType::Word);
+        entry_block.asgn(fun_var, Expr::TupleGet(self_var, 1, RepType::Word));
+        let mut args: Vec<VarId> = Vec::with_capacity(n_args + 1);
+        for (arg_idx, arg_ty) in param_tys[..n_args].iter().enumerate() {
+            let arg = ctx.fresh_var(*arg_ty);
+            entry_block.asgn(arg, Expr::TupleGet(self_var, arg_idx + 2, *arg_ty));
+            args.push(arg);
+        }
+        args.push(arg_var);
+
+        let (ret_tmp, return_type) = match next_pap {
+            Some(next_pap) => {
+                let closure_tmp = ctx.fresh_var(RepType::Word);
+                pap_closure(&mut entry_block, closure_tmp, next_pap, fun_var, &args);
+                (closure_tmp, RepType::Word)
+            }
+            None => {
+                // f(args) -> f.0(f, args)
+                let fun_tmp = ctx.fresh_var(RepType::Word);
+                entry_block.asgn(fun_tmp, Expr::TupleGet(fun_var, 0, RepType::Word));
+                args.insert(0, fun_var);
+                let ret_tmp = ctx.fresh_var(ret_ty);
+                entry_block.asgn(ret_tmp, Expr::App(fun_tmp, args, ret_ty));
+                (ret_tmp, ret_ty)
+            }
+        };
+        ctx.finish_block(entry_block, Sequel::Return, Atom::Var(ret_tmp));
+
+        FunSig { name: pap_var, args: vec![self_var, arg_var], return_type }
+    });
+    pap_var
+}
+
+// Allocate the closure `[pap, fun, args...]` of a partial application
+fn pap_closure(block: &mut BlockBuilder, closure: VarId, pap: VarId, fun: VarId, args: &[VarId]) {
+    block.asgn(closure, Expr::Tuple { len: args.len() + 2 });
+    block.expr(Expr::TuplePut(closure, 0, pap));
+    block.expr(Expr::TuplePut(closure, 1, fun));
+    for (arg_idx, arg) in args.iter().enumerate() {
+        block.expr(Expr::TuplePut(closure, arg_idx + 2, *arg));
+    }
+}
+
+fn cc_cond(
+    ctx: &mut CcCtx, block: BlockBuilder, cond: anormal::Cond, then_idx: BlockIdx,
+    else_idx: BlockIdx,
+) {
+    let BlockBuilder { idx, stmts, comment } = block;
+    match cond {
+        anormal::Cond::Cmp(v1, v2, cmp) => {
+            ctx.finish_block_(Block {
+                idx,
+                comment,
+                stmts,
+                exit: Exit::Branch {
+                    v1,
+                    v2,
+                    cond: cmp,
+                    then_block: then_idx,
+                    else_block: else_idx,
+                },
+            });
+        }
+
+        anormal::Cond::Bool(b) => {
+            let exit = Exit::Jump(if b { then_idx } else { else_idx });
+            ctx.finish_block_(Block { idx, comment, stmts, exit });
+        }
+
+        anormal::Cond::If(c1, c2, c3) => {
+            // Constant conditions in the branches (as in `&&` and `||`) jump directly to the
+            // target blocks
+            let c2_idx = cond_target(ctx, *c2, then_idx, else_idx);
+            let c3_idx = cond_target(ctx, *c3, then_idx, else_idx);
+            cc_cond(
+                ctx,
+                BlockBuilder { idx, stmts, comment },
+                *c1,
+                c2_idx,
+                c3_idx,
+            );
+        }
+
+        anormal::Cond::Let { id, rhs, body } => {
+            let cont_block = ctx.create_block();
+            let cont_idx = cont_block.idx;
+            cc_block(
+                ctx,
+                BlockBuilder { idx, stmts, comment },
+                Sequel::Asgn(id, cont_idx),
+                *rhs,
+            );
+            cc_cond(ctx, cont_block, *body, then_idx, else_idx);
+        }
+    }
+}
+
+// Block that tests the condition and branches to `then_idx` or `else_idx`
+fn cond_target(
+    ctx: &mut CcCtx, cond: anormal::Cond, then_idx: BlockIdx, else_idx: BlockIdx,
+) -> BlockIdx {
+    match cond {
+        anormal::Cond::Bool(true) => then_idx,
+        anormal::Cond::Bool(false) => else_idx,
+        cond => {
+            let block = ctx.create_block();
+            let block_idx = block.idx;
+            cc_cond(ctx, block, cond, then_idx, else_idx);
+            block_idx
+        }
     }
 }
 
 fn fvs(ctx: &Ctx, e: &anormal::Expr, acc: &mut FxHashSet<VarId>) {
     use anormal::Expr::*;
     match e {
-        Unit | Int(_) | Float(_) => {}
+        Unit | Int(_) | Float(_) | String(_) | MatchFailure(_) => {}
+        Abort(common::Abort::AssertFailure, _) => {}
         IBinOp(BinOp { arg1, arg2, op: _ }) => {
             fv(ctx, *arg1, acc);
             fv(ctx, *arg2, acc);
@@ -408,16 +705,19 @@ fn fvs(ctx: &Ctx, e: &anormal::Expr, acc: &mut FxHashSet<VarId>) {
             fv(ctx, *arg1, acc);
             fv(ctx, *arg2, acc);
         }
-        Neg(arg) | FNeg(arg) => {
+        Neg(arg)
+        | FNeg(arg)
+        | Abort(common::Abort::Failwith(arg), _)
+        | Abort(common::Abort::Exit(arg), _)
+        | Abort(common::Abort::Raise(arg), _) => {
             fv(ctx, *arg, acc);
         }
-        If(arg1, arg2, _, e1, e2) => {
-            fv(ctx, *arg1, acc);
-            fv(ctx, *arg2, acc);
+        If(cond, e1, e2) => {
+            cond_fvs(ctx, cond, acc);
             fvs(ctx, e1, acc);
             fvs(ctx, e2, acc);
         }
-        Let { id, ty_id: _, rhs, body } => {
+        Let { id, rhs, body } => {
             fvs(ctx, rhs, acc);
             fvs(ctx, body, acc);
             acc.remove(id);
@@ -425,15 +725,21 @@ fn fvs(ctx: &Ctx, e: &anormal::Expr, acc: &mut FxHashSet<VarId>) {
         Var(id) => {
             fv(ctx, *id, acc);
         }
-        LetRec { name, ty_id: _, args, rhs, body } => {
-            fvs(ctx, rhs, acc);
+        LetRec { defs, body } => {
+            for anormal::FunDef { args, rhs, .. } in defs {
+                let mut rhs_fvs: FxHashSet<VarId> = Default::default();
+                fvs(ctx, rhs, &mut rhs_fvs);
+                for arg in args {
+                    rhs_fvs.remove(arg);
+                }
+                acc.extend(rhs_fvs);
+            }
             fvs(ctx, body, acc);
-            acc.remove(name);
-            for arg in args {
-                acc.remove(arg);
+            for anormal::FunDef { name, .. } in defs {
+                acc.remove(name);
             }
         }
-        App(fun, args) => {
+        App(fun, args) | PartialApp(fun, args) => {
             fv(ctx, *fun, acc);
             for arg in args {
                 fv(ctx, *arg, acc);
@@ -447,11 +753,25 @@ fn fvs(ctx: &Ctx, e: &anormal::Expr, acc: &mut FxHashSet<VarId>) {
         TupleGet(arg, _) => {
             fv(ctx, *arg, acc);
         }
+        TuplePut(arg1, _, arg2) => {
+            fv(ctx, *arg1, acc);
+            fv(ctx, *arg2, acc);
+        }
+        For { var, start, end, body, .. } => {
+            fv(ctx, *start, acc);
+            fv(ctx, *end, acc);
+            fvs(ctx, body, acc);
+            acc.remove(var);
+        }
+        While { cond, body } => {
+            cond_fvs(ctx, cond, acc);
+            fvs(ctx, body, acc);
+        }
         ArrayAlloc { len, elem } => {
             fv(ctx, *len, acc);
             fv(ctx, *elem, acc);
         }
-        ArrayGet(arg1, arg2) => {
+        ArrayGet(arg1, arg2) | Try(arg1, arg2) => {
             fv(ctx, *arg1, acc);
             fv(ctx, *arg2, acc);
         }
@@ -463,8 +783,101 @@ fn fvs(ctx: &Ctx, e: &anormal::Expr, acc: &mut FxHashSet<VarId>) {
     }
 }
 
+fn cond_fvs(ctx: &Ctx, cond: &anormal::Cond, acc: &mut FxHashSet<VarId>) {
+    use anormal::Cond::*;
+    match cond {
+        Cmp(arg1, arg2, _) => {
+            fv(ctx, *arg1, acc);
+            fv(ctx, *arg2, acc);
+        }
+        Bool(_) => {}
+        If(c1, c2, c3) => {
+            cond_fvs(ctx, c1, acc);
+            cond_fvs(ctx, c2, acc);
+            cond_fvs(ctx, c3, acc);
+        }
+        Let { id, rhs, body } => {
+            fvs(ctx, rhs, acc);
+            cond_fvs(ctx, body, acc);
+            acc.remove(id);
+        }
+    }
+}
+
 fn fv(ctx: &Ctx, var: VarId, acc: &mut FxHashSet<VarId>) {
-    if !ctx.is_builtin_var(var) {
+    if !ctx.is_builtin_var(var) && !ctx.is_global_var(var) {
         acc.insert(var);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+
+    // Lower the program and return the function `f` in it
+    fn lower_f(pgm: &str) -> Fun {
+        let (expr, errs) = parse(&tokenize(pgm).unwrap());
+        assert!(errs.is_empty(), "{:?}", errs);
+        let mut ctx: Ctx = Default::default();
+        let mut expr = expr.intern(&mut ctx);
+        crate::type_check::type_check_pgm(&mut ctx, &mut expr).unwrap();
+        crate::mono::monomorphize(&mut ctx, &mut expr);
+        let expr = anormal::anormal(&mut ctx, expr);
+        let (funs, main) = lower_pgm(&mut ctx, expr);
+        funs.into_iter().find(|fun| fun.name != main).unwrap()
+    }
+
+    // Conditions with `&&`, `||` and `not` should branch directly to the `then` and `else`
+    // blocks, without materializing booleans
+    fn check_branches(pgm: &str, n_branches: usize) {
+        let fun = lower_f(pgm);
+        let blocks: Vec<&Block> = fun
+            .blocks
+            .values()
+            .map(|block| block.get_block().unwrap())
+            .collect();
+        let branches = blocks
+            .iter()
+            .filter(|block| matches!(block.exit, Exit::Branch { .. }))
+            .count();
+        assert_eq!(branches, n_branches, "{:#?}", blocks);
+        for block in blocks.iter() {
+            for stmt in &block.stmts {
+                assert!(
+                    !matches!(
+                        stmt,
+                        Stmt::Asgn(Asgn { rhs: Expr::Atom(Atom::Int(0 | 1)), .. })
+                    ),
+                    "{:#?}",
+                    blocks
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_cond_branches() {
+        check_branches(
+            "let rec f a b c d = if a < b && c < d then a else d in print_int (f 5 3 2 4)",
+            2,
+        );
+        check_branches(
+            "let rec f a b c d = if not (a < b) || c = d then a else d in print_int (f 5 3 2 4)",
+            2,
+        );
+        check_branches(
+            "let rec f a b c d = \
+             if (a < b || b < c) && not (c < d) then a else d in print_int (f 5 3 2 4)",
+            3,
+        );
+        check_branches(
+            "let rec f n m = \
+             let i = ref n in while !i < m && !i <> 5 do i := !i + n done; !i in \
+             print_int (f 2 10)",
+            2,
+        );
+    }
+}
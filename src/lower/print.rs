@@ -123,8 +123,9 @@ impl Expr {
                 let op_str = match op {
                     IntBinOp::Add => " + ",
                     IntBinOp::Sub => " - ",
-                    // IntBinOp::Mul => " * ",
-                    // IntBinOp::Div => " / ",
+                    IntBinOp::Mul => " * ",
+                    IntBinOp::Div => " / ",
+                    IntBinOp::Mod => " mod ",
                 };
                 write!(w, "{}", op_str)?;
                 pp_id(ctx, *arg2, w)
@@ -155,12 +156,17 @@ impl Expr {
                 w.write_str(")")
             }
             Tuple { len } => write!(w, "alloc_tuple(len={})", len),
+            String(bytes) => write!(
+                w,
+                "alloc_string({:?})",
+                std::string::String::from_utf8_lossy(bytes)
+            ),
             TuplePut(tuple, idx, val) => {
                 pp_id(ctx, *tuple, w)?;
                 write!(w, ".{{{}}} <- ", idx)?;
                 pp_id(ctx, *val, w)
             }
-            TupleGet(tuple, idx) => {
+            TupleGet(tuple, idx, _) => {
                 pp_id(ctx, *tuple, w)?;
                 write!(w, ".{}", idx)
             }
@@ -182,6 +188,30 @@ impl Expr {
                 w.write_str(") <- ")?;
                 pp_id(ctx, *val, w)
             }
+            MatchFailure(_) => w.write_str("match_failure()"),
+            Abort(crate::common::Abort::Failwith(msg), _) => {
+                w.write_str("failwith(")?;
+                pp_id(ctx, *msg, w)?;
+                w.write_str(")")
+            }
+            Abort(crate::common::Abort::AssertFailure, _) => w.write_str("assert_failure()"),
+            Abort(crate::common::Abort::Exit(code), _) => {
+                w.write_str("exit(")?;
+                pp_id(ctx, *code, w)?;
+                w.write_str(")")
+            }
+            Abort(crate::common::Abort::Raise(exn), _) => {
+                w.write_str("raise(")?;
+                pp_id(ctx, *exn, w)?;
+                w.write_str(")")
+            }
+            Try(body, handler, _) => {
+                w.write_str("try(")?;
+                pp_id(ctx, *body, w)?;
+                w.write_str(", ")?;
+                pp_id(ctx, *handler, w)?;
+                w.write_str(")")
+            }
         }
     }
 }
@@ -1,6 +1,7 @@
 use crate::cg_types::RepType;
-use crate::common::{BinOp, Cmp, FloatBinOp, IntBinOp};
+use crate::common::{Abort, BinOp, Cmp, FloatBinOp, IntBinOp};
 use crate::ctx::VarId;
+use crate::span::Span;
 
 use cranelift_entity::{entity_impl, PrimaryMap};
 
@@ -73,8 +74,10 @@ pub enum Expr {
     App(VarId, Vec<VarId>, RepType),
     // Tuple allocation
     Tuple { len: usize },
-    // Tuple field read
-    TupleGet(VarId, usize),
+    // String allocation: length word followed by the bytes
+    String(Vec<u8>),
+    // Tuple field read, with the field type
+    TupleGet(VarId, usize, RepType),
     // Tuple field write
     TuplePut(VarId, usize, VarId),
     // Array allocation
@@ -83,6 +86,12 @@ pub enum Expr {
     ArrayGet(VarId, VarId),
     // Array field write
     ArrayPut(VarId, VarId, VarId),
+    // Raise `Match_failure` because no `match` arm matched, at the location of the `match`
+    MatchFailure(Span),
+    // `failwith`, `exit`, `raise` or a failing `assert`, at the given location
+    Abort(Abort, Span),
+    // `try`, with the body and handler closures and the type of the value
+    Try(VarId, VarId, RepType),
 }
 
 #[derive(Debug, PartialEq)]
@@ -0,0 +1,365 @@
+// Monomorphization: specializes polymorphic functions for each instantiation of their type
+// variables, so that later passes only see monomorphic functions.
+//
+// Functions are specialized by the representations of the instantiations rather than the types, as
+// the code generated for a function mostly depends on the representations of its arguments and
+// locals: `id 1` and `id true` use the same copy of `id`, `id 1.0` uses another one. Strings are
+// words, but comparisons on strings compare the contents, so they get their own copies. In the
+// copies type variables are replaced with `int` for words, `float` for floats, and `string` for
+// strings.
+//
+// Copies of a definition are generated after the body of the `let` or `let rec`, when all
+// instantiations in the body are known. Instantiations in the copies are specializations of
+// functions defined in the outer scopes, which are still being collected at that point.
+
+use crate::ast::{Arm, Expr, ExprKind, FieldExpr, FunDef, Pat, PatKind};
+use crate::ctx::{Ctx, VarId};
+use crate::type_check::{norm_ty, Instance, SubstEnv, TyVar, Type};
+
+use fxhash::FxHashMap;
+
+// A polymorphic `let` binder or `let rec` group, and the copies generated for it so far
+struct Group {
+    // Binders of the group. A `let` has one binder.
+    binders: Vec<VarId>,
+    // Type variables of the binders
+    generics: Vec<TyVar>,
+    // Environment of the definition
+    env: Env,
+    // Representations of the type variables, and binders of the copy for each binder
+    copies: Vec<(Vec<CopyRep>, Vec<VarId>)>,
+}
+
+// Representation of a type variable in a copy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CopyRep {
+    Word,
+    Float,
+    String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Env {
+    // Types of the type variables of the enclosing polymorphic functions
+    subst: SubstEnv,
+    // Renamed binders. Binders in copies are renamed to keep binders unique.
+    renames: FxHashMap<VarId, VarId>,
+}
+
+impl Group {
+    fn new(binders: Vec<VarId>, generics: Vec<TyVar>, env: &Env) -> Group {
+        Group { binders, generics, env: env.clone(), copies: vec![] }
+    }
+
+    // Environment of a copy of the group
+    fn copy_env(&self, rep_tys: &[CopyRep], copy_bndrs: &[VarId]) -> Env {
+        let mut env = self.env.clone();
+        for (ty_var, rep_ty) in self.generics.iter().zip(rep_tys) {
+            env.subst.insert(*ty_var, copy_rep_stand_in(*rep_ty));
+        }
+        for (bndr, copy_bndr) in self.binders.iter().zip(copy_bndrs) {
+            env.renames.insert(*bndr, *copy_bndr);
+        }
+        env
+    }
+}
+
+pub fn monomorphize(ctx: &mut Ctx, expr: &mut Expr) {
+    mono(ctx, &mut vec![], &mut Env::default(), expr);
+}
+
+fn mono(ctx: &mut Ctx, groups: &mut Vec<Group>, env: &mut Env, expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Unit
+        | ExprKind::Bool(_)
+        | ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Char(_)
+        | ExprKind::Nil => {}
+
+        ExprKind::Not(e)
+        | ExprKind::Neg(e)
+        | ExprKind::FNeg(e)
+        | ExprKind::Ref(e)
+        | ExprKind::Deref(e)
+        | ExprKind::Abort(_, e)
+        | ExprKind::Annot { expr: e, .. }
+        | ExprKind::GetField { expr: e, .. } => mono(ctx, groups, env, e),
+
+        ExprKind::IntBinOp(e1, _, e2)
+        | ExprKind::FloatBinOp(e1, _, e2)
+        | ExprKind::Cmp(e1, _, e2)
+        | ExprKind::And(e1, e2)
+        | ExprKind::Or(e1, e2)
+        | ExprKind::Get(e1, e2)
+        | ExprKind::Assign(e1, e2)
+        | ExprKind::Array { len: e1, elem: e2 }
+        | ExprKind::Cons(e1, e2)
+        | ExprKind::While { cond: e1, body: e2 }
+        | ExprKind::SetField { expr: e1, value: e2, .. } => {
+            mono(ctx, groups, env, e1);
+            mono(ctx, groups, env, e2);
+        }
+
+        ExprKind::If(e1, e2, e3) | ExprKind::Put(e1, e2, e3) => {
+            mono(ctx, groups, env, e1);
+            mono(ctx, groups, env, e2);
+            mono(ctx, groups, env, e3);
+        }
+
+        ExprKind::Let { bndr, rhs, body } => {
+            let generics = ctx.generic_vars(*bndr).to_vec();
+            if generics.is_empty() {
+                mono(ctx, groups, env, rhs);
+                rename_bndr(ctx, env, bndr);
+                mono(ctx, groups, env, body);
+                return;
+            }
+
+            groups.push(Group::new(vec![*bndr], generics, env));
+            mono(ctx, groups, env, body);
+            let group = groups.pop().unwrap();
+
+            // A `let` for each copy, or just the body when the function is not used
+            let span = expr.span;
+            let mut ret = std::mem::replace(&mut **body, Expr { kind: ExprKind::Unit, span });
+            for (rep_tys, copy_bndrs) in group.copies.iter().rev() {
+                let mut copy_env = group.copy_env(rep_tys, copy_bndrs);
+                let mut copy_rhs = (**rhs).clone();
+                mono(ctx, groups, &mut copy_env, &mut copy_rhs);
+                ret = Expr {
+                    kind: ExprKind::Let {
+                        bndr: copy_bndrs[0],
+                        rhs: Box::new(copy_rhs),
+                        body: Box::new(ret),
+                    },
+                    span,
+                };
+            }
+            *expr = ret;
+        }
+
+        ExprKind::Var(var) => {
+            if let Some(Instance { binder, tys }) = ctx.get_instance(*var).cloned() {
+                let copy = instantiate(ctx, groups, env, binder, &tys);
+                // Type variables in the type of the copy are replaced with stand-ins, but the
+                // use may need the instantiated type, e.g. to apply the return value of the
+                // function. Bind the copy to a variable with the instantiated type.
+                let inst_ty = norm_ty(&env.subst, (*ctx.var_type(*var)).clone());
+                if *ctx.var_type(copy) == inst_ty {
+                    *var = copy;
+                } else {
+                    let inst = ctx.fresh_user_var(&ctx.var_name(*var));
+                    let inst_ty_id = ctx.intern_type(inst_ty);
+                    ctx.set_var_type(inst, inst_ty_id);
+                    let span = expr.span;
+                    expr.kind = ExprKind::Let {
+                        bndr: inst,
+                        rhs: Box::new(Expr { kind: ExprKind::Var(copy), span }),
+                        body: Box::new(Expr { kind: ExprKind::Var(inst), span }),
+                    };
+                }
+            } else if let Some(renamed) = env.renames.get(var) {
+                *var = *renamed;
+            }
+        }
+
+        ExprKind::LetRec { defs, body } => {
+            let generics = ctx.generic_vars(defs[0].bndr).to_vec();
+            if generics.is_empty() {
+                for FunDef { bndr, .. } in defs.iter_mut() {
+                    rename_bndr(ctx, env, bndr);
+                }
+                for FunDef { args, rhs, .. } in defs.iter_mut() {
+                    for arg in args.iter_mut() {
+                        rename_bndr(ctx, env, arg);
+                    }
+                    mono(ctx, groups, env, rhs);
+                }
+                mono(ctx, groups, env, body);
+                return;
+            }
+
+            let binders: Vec<VarId> = defs.iter().map(|FunDef { bndr, .. }| *bndr).collect();
+            groups.push(Group::new(binders, generics, env));
+            mono(ctx, groups, env, body);
+            let group = groups.pop().unwrap();
+
+            // All copies are in the same group, as a copy can call the functions in the group
+            // with other instantiations
+            let mut copy_defs: Vec<FunDef<VarId>> = vec![];
+            for (rep_tys, copy_bndrs) in &group.copies {
+                let mut copy_env = group.copy_env(rep_tys, copy_bndrs);
+                for (def, copy_bndr) in defs.iter().zip(copy_bndrs) {
+                    let mut copy_def = def.clone();
+                    copy_def.bndr = *copy_bndr;
+                    for arg in copy_def.args.iter_mut() {
+                        rename_bndr(ctx, &mut copy_env, arg);
+                    }
+                    mono(ctx, groups, &mut copy_env, &mut copy_def.rhs);
+                    copy_defs.push(copy_def);
+                }
+            }
+
+            let span = expr.span;
+            let body = std::mem::replace(&mut **body, Expr { kind: ExprKind::Unit, span });
+            if copy_defs.is_empty() {
+                *expr = body;
+            } else {
+                expr.kind = ExprKind::LetRec { defs: copy_defs, body: Box::new(body) };
+            }
+        }
+
+        ExprKind::Fun { args, body, .. } => {
+            for arg in args.iter_mut() {
+                rename_bndr(ctx, env, arg);
+            }
+            mono(ctx, groups, env, body);
+        }
+
+        ExprKind::App { fun, args } => {
+            mono(ctx, groups, env, fun);
+            for arg in args.iter_mut() {
+                mono(ctx, groups, env, arg);
+            }
+        }
+
+        ExprKind::Match { scrut: e, arms } | ExprKind::Try { body: e, arms } => {
+            mono(ctx, groups, env, e);
+            for Arm { pat, guard, rhs } in arms.iter_mut() {
+                rename_pat(ctx, env, pat);
+                if let Some(guard) = guard {
+                    mono(ctx, groups, env, guard);
+                }
+                mono(ctx, groups, env, rhs);
+            }
+        }
+
+        ExprKind::Tuple(args) | ExprKind::Ctor { args, .. } => {
+            for arg in args.iter_mut() {
+                mono(ctx, groups, env, arg);
+            }
+        }
+
+        ExprKind::LetTuple { bndrs, rhs, body } => {
+            mono(ctx, groups, env, rhs);
+            for bndr in bndrs.iter_mut() {
+                rename_bndr(ctx, env, bndr);
+            }
+            mono(ctx, groups, env, body);
+        }
+
+        ExprKind::For { var, start, end, body, .. } => {
+            mono(ctx, groups, env, start);
+            mono(ctx, groups, env, end);
+            rename_bndr(ctx, env, var);
+            mono(ctx, groups, env, body);
+        }
+
+        ExprKind::TypeDecl { body, .. }
+        | ExprKind::External { body, .. }
+        | ExprKind::Exception { body, .. } => mono(ctx, groups, env, body),
+
+        ExprKind::Record(fields) => {
+            for FieldExpr { expr, .. } in fields.iter_mut() {
+                mono(ctx, groups, env, expr);
+            }
+        }
+    }
+}
+
+// Get the copy of a polymorphic binder for an instantiation, adding a new copy to the group of the
+// binder if this is the first use of the binder with the instantiation
+fn instantiate(
+    ctx: &mut Ctx, groups: &mut [Group], env: &Env, binder: VarId, tys: &[Type],
+) -> VarId {
+    let rep_tys: Vec<CopyRep> = tys
+        .iter()
+        .map(|ty| ty_copy_rep(&norm_ty(&env.subst, ty.clone())))
+        .collect();
+
+    let group = groups
+        .iter_mut()
+        .rev()
+        .find(|group| group.binders.contains(&binder))
+        .unwrap_or_else(|| panic!("Polymorphic binder not in scope: {}", ctx.get_var(binder)));
+    let binder_idx = group
+        .binders
+        .iter()
+        .position(|bndr| *bndr == binder)
+        .unwrap();
+
+    if let Some((_, copy_bndrs)) = group
+        .copies
+        .iter()
+        .find(|(rep_tys_, _)| *rep_tys_ == rep_tys)
+    {
+        return copy_bndrs[binder_idx];
+    }
+
+    let copy_subst = group.copy_env(&rep_tys, &[]).subst;
+    let copy_bndrs: Vec<VarId> = group
+        .binders
+        .iter()
+        .map(|bndr| {
+            let copy_ty = norm_ty(&copy_subst, (*ctx.var_type(*bndr)).clone());
+            let copy_bndr = ctx.fresh_user_var(&ctx.var_name(*bndr));
+            let copy_ty_id = ctx.intern_type(copy_ty);
+            ctx.set_var_type(copy_bndr, copy_ty_id);
+            copy_bndr
+        })
+        .collect();
+    let copy_bndr = copy_bndrs[binder_idx];
+    group.copies.push((rep_tys, copy_bndrs));
+    copy_bndr
+}
+
+// Binders in copies of polymorphic functions are renamed, with the type variables substituted
+fn rename_bndr(ctx: &mut Ctx, env: &mut Env, bndr: &mut VarId) {
+    if env.subst.is_empty() {
+        return;
+    }
+    let renamed = ctx.fresh_user_var(&ctx.var_name(*bndr));
+    if let Some(ty) = ctx.var_type_(*bndr) {
+        let ty_id = ctx.intern_type(norm_ty(&env.subst, (*ty).clone()));
+        ctx.set_var_type(renamed, ty_id);
+    }
+    env.renames.insert(*bndr, renamed);
+    *bndr = renamed;
+}
+
+fn rename_pat(ctx: &mut Ctx, env: &mut Env, pat: &mut Pat<VarId>) {
+    match &mut pat.kind {
+        PatKind::Wildcard | PatKind::Unit | PatKind::Bool(_) | PatKind::Int(_) | PatKind::Nil => {}
+        PatKind::Var(var) => rename_bndr(ctx, env, var),
+        PatKind::Cons(hd, tl) => {
+            rename_pat(ctx, env, hd);
+            rename_pat(ctx, env, tl);
+        }
+        PatKind::Tuple(pats) | PatKind::Ctor { args: pats, .. } => {
+            for pat in pats.iter_mut() {
+                rename_pat(ctx, env, pat);
+            }
+        }
+    }
+}
+
+// Representation of a type, with type variables not instantiated in the program represented as
+// words
+fn ty_copy_rep(ty: &Type) -> CopyRep {
+    match ty {
+        Type::Float => CopyRep::Float,
+        Type::String => CopyRep::String,
+        _ => CopyRep::Word,
+    }
+}
+
+// Type for the type variables represented as a `CopyRep` in a copy
+fn copy_rep_stand_in(rep: CopyRep) -> Type {
+    match rep {
+        CopyRep::Word => Type::Int,
+        CopyRep::Float => Type::Float,
+        CopyRep::String => Type::String,
+    }
+}
@@ -1,344 +1,1432 @@
-use crate::ast::ParsedExpr;
-use crate::common::{Cmp, FloatBinOp, IntBinOp};
-use crate::lexer::Token;
-
-use parsegen::parser;
-
-parser! {
-    enum Token {
-        "(" => Token::LParen, // 0
-        ")" => Token::RParen,
-        "bool" => Token::Bool(<bool>),
-        "not" => Token::Not,
-        "if" => Token::If,
-        "then" => Token::Then, // 5
-        "else" => Token::Else,
-        "let" => Token::Let,
-        "rec" => Token::Rec,
-        "in" => Token::In,
-        "-" => Token::Minus, // 10
-        "-." => Token::MinusDot,
-        "+" => Token::Plus,
-        "+." => Token::PlusDot,
-        "*." => Token::AstDot,
-        "/." => Token::SlashDot, // 15
-        "=" => Token::Equal,
-        "<>" => Token::LessGreater,
-        "<=" => Token::LessEqual,
-        "<-" => Token::LessMinus,
-        "<" => Token::Less, // 20
-        ">=" => Token::GreaterEqual,
-        ">" => Token::Greater,
-        "." => Token::Dot,
-        "," => Token::Comma,
-        ";" => Token::Semicolon, // 25
-        "_" => Token::Underscore,
-        "Array.create" => Token::ArrayCreate,
-        "id" => Token::Id(<String>), // 28
-        "int" => Token::Int(<i64>),
-        "float" => Token::Float(<f64>), // 30
-    }
-
-    // Entry point
-    pub Expr: ParsedExpr = {
-        <expr:SeqExpr> =>
-            expr,
-    };
-
-    // `;`
-    SeqExpr: ParsedExpr = {
-        <expr:LetExpr> =>
-            expr,
-
-        // Making it right associative. It works either way.
-        <expr1:LetExpr> ";" <expr2:SeqExpr> =>
-            // TODO: fix the binder
-            ParsedExpr::Let { bndr: "_".to_string(), rhs: Box::new(expr1), body: Box::new(expr2) },
-    };
-
-    // `let`, `let rec`, and `let (...)`
-    LetExpr: ParsedExpr = {
-        <expr:IfExpr> =>
-            expr,
-
-        "let" <bndr:Binder> "=" <rhs:SeqExpr> "in" <body:SeqExpr> =>
-            ParsedExpr::Let { bndr, rhs: Box::new(rhs), body: Box::new(body) },
-
-        "let" "rec" <bndr:Binder> <args:Binders1> "=" <rhs:SeqExpr> "in" <body:SeqExpr> =>
-            ParsedExpr::LetRec { bndr, args, rhs: Box::new(rhs), body: Box::new(body) },
-
-        "let" "(" <bndr:Binder> <mut bndrs:CommaBinder1_Rev> ")" "=" <rhs:SeqExpr> "in" <body:SeqExpr> => {
-            bndrs.push(bndr);
-            bndrs.reverse();
-            ParsedExpr::LetTuple {
-                bndrs,
-                rhs: Box::new(rhs),
-                body: Box::new(body),
+// Recursive-descent parser. Each precedence level of the grammar is one method below, from the
+// loosest binding (`;`) to the tightest (atoms). Every node gets the span of the tokens it was
+// parsed from.
+//
+// On a syntax error the parser records the error, skips tokens until a `;`, `in`, `and`, `then`,
+// `else`, `with`, `|`, `->`, `done` or `)`, and continues, so that one run reports all syntax
+// errors.
+// Skipped tokens are replaced with `()` in the AST.
+
+use crate::ast::{
+    AbortKind, Arm, CtorDecl, ExprKind, FieldDecl, FieldExpr, FunDef, ParsedExpr, Pat, PatKind,
+    TypeDecl, TypeDeclKind, TypeExpr, TypeExprKind,
+};
+use crate::common::{Cmp, FloatBinOp, ForDir, IntBinOp};
+use crate::diagnostics::Diagnostic;
+use crate::lexer::{SpannedToken, Token};
+use crate::span::Span;
+
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub struct ParseErr {
+    /// Location of the unexpected token, or the end of the input
+    pub span: Span,
+    /// The unexpected token. `None` means unexpected end of input.
+    pub found: Option<Token>,
+    /// What the parser would accept at this location
+    pub expected: Vec<Expected>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expected {
+    Token(Token),
+    Ident,
+    Int,
+    Expr,
+    Operator,
+    Pattern,
+    Type,
+    Ctor,
+    String,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Token(token) => write!(f, "`{}`", token),
+            Expected::Ident => f.write_str("identifier"),
+            Expected::Int => f.write_str("integer"),
+            Expected::Expr => f.write_str("expression"),
+            Expected::Operator => f.write_str("operator"),
+            Expected::Pattern => f.write_str("pattern"),
+            Expected::Type => f.write_str("type"),
+            Expected::Ctor => f.write_str("constructor"),
+            Expected::String => f.write_str("string"),
+        }
+    }
+}
+
+impl ParseErr {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let found = match &self.found {
+            None => "end of input".to_owned(),
+            Some(token) => format!("`{}`", token),
+        };
+
+        let expected = match self.expected.as_slice() {
+            [] => return Diagnostic::error(format!("unexpected {}", found), self.span),
+            [expected] => expected.to_string(),
+            [expected @ .., last] => {
+                let expected: Vec<String> = expected.iter().map(|e| e.to_string()).collect();
+                format!("one of {} or {}", expected.join(", "), last)
             }
-        },
-    };
+        };
 
-    Binder: String = {
-        "_" =>
-            "_".to_owned(),
+        Diagnostic::error(format!("expected {}, found {}", expected, found), self.span)
+            .with_label(format!("expected {}", expected))
+    }
+}
 
-        <id:"id"> =>
-            id,
-    };
+/// Parse a program. Returns all syntax errors found. When there are errors the returned AST has
+/// `()` in place of the unparsable parts.
+pub fn parse(tokens: &[SpannedToken]) -> (ParsedExpr, Vec<ParseErr>) {
+    let mut parser =
+        Parser { tokens, pos: 0, expected: vec![], errors: vec![], last_err_pos: None };
+    let expr = parser.program();
+    (expr, parser.errors)
+}
+
+struct Parser<'a> {
+    tokens: &'a [SpannedToken],
+    // Index of the next token
+    pos: usize,
+    // Tokens tried at the current position, for error messages. Cleared when consuming a token.
+    expected: Vec<Expected>,
+    errors: Vec<ParseErr>,
+    // Token index of the last error reported
+    last_err_pos: Option<usize>,
+}
+
+type ParseResult<A> = Result<A, ParseErr>;
+
+// A `let` without the body. Shared by `let ... in` expressions and top-level definitions.
+enum LetBinding {
+    Let { bndr: String, rhs: ParsedExpr },
+    LetRec { defs: Vec<FunDef<String>> },
+    LetTuple { bndrs: Vec<String>, rhs: ParsedExpr },
+}
+
+impl LetBinding {
+    fn with_body(self, let_span: Span, body: ParsedExpr) -> ParsedExpr {
+        let span = let_span.to(body.span);
+        let body = Box::new(body);
+        let kind = match self {
+            LetBinding::Let { bndr, rhs } => ExprKind::Let { bndr, rhs: Box::new(rhs), body },
+            LetBinding::LetRec { defs } => ExprKind::LetRec { defs, body },
+            LetBinding::LetTuple { bndrs, rhs } => {
+                ExprKind::LetTuple { bndrs, rhs: Box::new(rhs), body }
+            }
+        };
+        mk(kind, span)
+    }
+}
+
+// Array index or record field after a `.`
+enum Access {
+    Index(ParsedExpr),
+    Field(String),
+}
+
+enum Item {
+    // `let` definition without `in`, with the span of the `let` keyword
+    Let(LetBinding, Span),
+    // Type declarations, with the span of the `type` keyword
+    Type(Vec<TypeDecl>, Span),
+    // `external` declaration, with the span of the `external` keyword
+    External { bndr: String, ty: TypeExpr, symbol: String, span: Span },
+    // `exception` declaration, with the span of the `exception` keyword
+    Exception(CtorDecl, Span),
+    Expr(ParsedExpr),
+}
+
+fn mk(kind: ExprKind<String>, span: Span) -> ParsedExpr {
+    ParsedExpr { kind, span }
+}
 
-    // One or more ", id", returned in reverse
-    CommaBinder1_Rev: Vec<String> = {
-        "," <bndr:Binder> <mut bndrs:CommaBinder0_Rev> => {
-            bndrs.push(bndr);
-            bndrs
+// Annotate the RHS of `let (x : t) = <expr>`
+fn annot(expr: ParsedExpr, ty: TypeExpr) -> ParsedExpr {
+    let span = expr.span;
+    mk(ExprKind::Annot { expr: Box::new(expr), ty }, span)
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    // Span of the next token. At the end of input this is the empty span right after the last
+    // token.
+    fn peek_span(&self) -> Span {
+        match self.tokens.get(self.pos) {
+            Some(t) => t.span,
+            None => match self.tokens.last() {
+                Some(t) => Span::new(t.span.end, t.span.end),
+                None => Span::new(0, 0),
+            },
         }
-    };
+    }
 
-    CommaBinder0_Rev: Vec<String> = {
-        => vec![],
+    // Skip the next token, return its span
+    fn bump(&mut self) -> Span {
+        let span = self.peek_span();
+        self.pos += 1;
+        self.expected.clear();
+        span
+    }
 
-        "," <bndr:Binder> <mut bndrs:CommaBinder0_Rev> => {
-            bndrs.push(bndr);
-            bndrs
+    // Record that we tried to parse the given token or construct at the current position
+    fn expected(&mut self, expected: Expected) {
+        if !self.expected.contains(&expected) {
+            self.expected.push(expected);
         }
-    };
+    }
+
+    // Skip the next token if it's the given one
+    fn skip(&mut self, token: &Token) -> Option<Span> {
+        if self.peek() == Some(token) {
+            Some(self.bump())
+        } else {
+            self.expected(Expected::Token(token.clone()));
+            None
+        }
+    }
 
-    // One or more identifiers
-    Binders1: Vec<String> = {
-        <bndr:Binder> <mut bndrs:Binders0_Rev> => {
-            bndrs.push(bndr);
-            bndrs.reverse();
-            bndrs
+    fn expect(&mut self, token: &Token) -> ParseResult<Span> {
+        match self.skip(token) {
+            Some(span) => Ok(span),
+            None => self.unexpected(),
         }
-    };
+    }
+
+    fn unexpected<A>(&self) -> ParseResult<A> {
+        Err(self.unexpected_err())
+    }
 
-    // Zero or more identifiers
-    Binders0_Rev: Vec<String> = {
-        => vec![],
+    fn unexpected_err(&self) -> ParseErr {
+        ParseErr {
+            span: self.peek_span(),
+            found: self.peek().cloned(),
+            expected: self.expected.clone(),
+        }
+    }
 
-        // TODO: I don't understand why I have to inline Binder here.
-        <bndr:"id"> <mut bndrs:Binders0_Rev> => {
-            bndrs.push(bndr);
-            bndrs
+    fn error(&mut self, err: ParseErr) {
+        // Errors at the same token are reported again by enclosing expressions while recovering,
+        // and errors right after a recovery point are usually caused by the previous error. Skip
+        // those.
+        if let Some(last_err_pos) = self.last_err_pos {
+            if self.pos <= last_err_pos + 1 {
+                return;
+            }
         }
+        self.last_err_pos = Some(self.pos);
+        self.errors.push(err);
+    }
 
-        "_" <mut bndrs:Binders0_Rev> => {
-            bndrs.push("_".to_owned());
-            bndrs
+    // Parse a `let_expr`. On error record the error, skip to the next `;`, `in`, `and`, `then`,
+    // `else`, `with`, `|`, `->`, `done` or `)` that is not nested in the skipped tokens, and
+    // return `()` for the skipped part.
+    fn recover_let_expr(&mut self) -> ParsedExpr {
+        let start_pos = self.pos;
+        let err = match self.let_expr() {
+            Ok(expr) => return expr,
+            Err(err) => err,
+        };
+        let err_span = err.span;
+        self.error(err);
+
+        // Nesting of skipped parens, braces, brackets, `let`s, `if`s, `match`es, `try`s, `fun`s and
+        // loops
+        let mut depth = 0u32;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::LParen
+                | Token::LBrace
+                | Token::LBracket
+                | Token::Let
+                | Token::If
+                | Token::Match
+                | Token::Try
+                | Token::Fun
+                | Token::For
+                | Token::While => depth += 1,
+                Token::RParen
+                | Token::RBrace
+                | Token::RBracket
+                | Token::In
+                | Token::Else
+                | Token::With
+                | Token::MinusGreater
+                | Token::Done
+                    if depth > 0 =>
+                {
+                    depth -= 1
+                }
+                Token::Semicolon
+                | Token::In
+                | Token::And
+                | Token::Then
+                | Token::Else
+                | Token::With
+                | Token::Bar
+                | Token::MinusGreater
+                | Token::Done
+                | Token::RParen
+                | Token::RBrace
+                | Token::RBracket
+                    if depth == 0 =>
+                {
+                    break
+                }
+                _ => {}
+            }
+            self.bump();
         }
-    };
 
-    // if-then-else
-    IfExpr: ParsedExpr = {
-        <expr:TupleExpr> =>
-            expr,
-
-        "if" <e1:SeqExpr> "then" <e2:SeqExpr> "else" <e3:LetExpr> =>
-            ParsedExpr::If(Box::new(e1), Box::new(e2), Box::new(e3)),
-    };
-
-    TupleExpr: ParsedExpr = {
-        <expr:CmpOpExpr> =>
-            expr,
-
-        <expr:CmpOpExpr> <mut exprs:CommaExpr1> => {
-            exprs.push(expr);
-            exprs.reverse();
-            ParsedExpr::Tuple(exprs)
-        },
-    };
-
-    // One or more ", <expr>"
-    CommaExpr1: Vec<ParsedExpr> = {
-        "," <expr:CmpOpExpr> <mut exprs:CommaExpr0> => {
-            exprs.push(expr);
-            exprs
-        },
-    };
-
-    // Zero or more ", <expr>"
-    CommaExpr0: Vec<ParsedExpr> = {
-        => vec![],
-
-        "," <expr:CmpOpExpr> <mut exprs:CommaExpr0> => {
-            exprs.push(expr);
-            exprs
-        },
-    };
+        let span = if self.pos > start_pos {
+            self.tokens[start_pos]
+                .span
+                .to(self.tokens[self.pos - 1].span)
+        } else {
+            err_span
+        };
+        mk(ExprKind::Unit, span)
+    }
 
-    // Comparison operators: `=`, `<>`, `<=` `<`, `>=`, `>`. These all have the same the same
-    // precedence, and are all left associative.
-    CmpOpExpr: ParsedExpr = {
-        <expr:BinOp1Expr> =>
-            expr,
-
-        <expr1:CmpOpExpr> <op:CmpOp> <expr2:BinOp1Expr> =>
-            ParsedExpr::Cmp(Box::new(expr1), op, Box::new(expr2)),
-    };
-
-    CmpOp: Cmp = {
-        "=" => Cmp::Equal,
-        "<>" => Cmp::NotEqual,
-        "<" => Cmp::LessThan,
-        "<=" => Cmp::LessThanOrEqual,
-        ">" => Cmp::GreaterThan,
-        ">=" => Cmp::GreaterThanOrEqual,
-    };
-
-    // `+` and `-`
-    BinOp1Expr: ParsedExpr = {
-        <expr:BinOp2Expr> =>
-            expr,
-
-        // Left associative
-        <expr1:BinOp1Expr> "-" <expr2:BinOp2Expr> =>
-            ParsedExpr::IntBinOp(Box::new(expr1), IntBinOp::Sub, Box::new(expr2)),
-
-        // Left associative
-        <expr1:BinOp1Expr> "+" <expr2:BinOp2Expr> =>
-            ParsedExpr::IntBinOp(Box::new(expr1), IntBinOp::Add, Box::new(expr2)),
-
-        // Left associative
-        <expr1:BinOp1Expr> "+." <expr2:BinOp2Expr> =>
-            ParsedExpr::FloatBinOp(Box::new(expr1), FloatBinOp::Add, Box::new(expr2)),
-
-        // Left associative
-        <expr1:BinOp1Expr> "-." <expr2:BinOp2Expr> =>
-            ParsedExpr::FloatBinOp(Box::new(expr1), FloatBinOp::Sub, Box::new(expr2)),
-    };
-
-    // `*.` and `/.`
-    BinOp2Expr: ParsedExpr = {
-        <expr:UnOpExpr> =>
-            expr,
-
-        // Left associative
-        <expr1:BinOp2Expr> "*." <expr2:UnOpExpr> =>
-            ParsedExpr::FloatBinOp(Box::new(expr1), FloatBinOp::Mul, Box::new(expr2)),
-
-        // Left associative
-        <expr1:BinOp2Expr> "/." <expr2:UnOpExpr> =>
-            ParsedExpr::FloatBinOp(Box::new(expr1), FloatBinOp::Div, Box::new(expr2)),
-    };
-
-    UnOpExpr: ParsedExpr = {
-        "-" <expr:AppExpr> =>
-            match expr {
-                ParsedExpr::Float(f) =>
-                    ParsedExpr::FNeg(Box::new(ParsedExpr::Float(f))),
-                other =>
-                    ParsedExpr::Neg(Box::new(other)),
+    // A program is a sequence of top-level items, optionally separated by `;;`. Items are `let`
+    // definitions without `in`, type declarations, `external` declarations, `exception`
+    // declarations, and expressions. Expressions can only appear at the beginning or after `;;`.
+    //
+    // Items are desugared to nested `let`s. The innermost body is the last item if it's an
+    // expression, `()` otherwise.
+    fn program(&mut self) -> ParsedExpr {
+        let mut items: Vec<Item> = vec![];
+        let mut expr_allowed = true;
+
+        loop {
+            while self.skip(&Token::SemiSemi).is_some() {
+                expr_allowed = true;
+            }
+            match self.peek() {
+                None => break,
+                Some(Token::Let) => {
+                    match self.let_item() {
+                        Ok(item) => items.push(item),
+                        Err(err) => {
+                            self.error(err);
+                            self.skip_to_item();
+                        }
+                    }
+                    expr_allowed = false;
+                }
+                Some(Token::External) => {
+                    match self.external_item() {
+                        Ok(item) => items.push(item),
+                        Err(err) => {
+                            self.error(err);
+                            self.skip_to_item();
+                        }
+                    }
+                    expr_allowed = false;
+                }
+                Some(Token::Type) => {
+                    match self.type_item() {
+                        Ok(item) => items.push(item),
+                        Err(err) => {
+                            self.error(err);
+                            self.skip_to_item();
+                        }
+                    }
+                    expr_allowed = false;
+                }
+                Some(Token::Exception) => {
+                    let exception_span = self.bump();
+                    match self.ctor_decl() {
+                        Ok(decl) => items.push(Item::Exception(decl, exception_span)),
+                        Err(err) => {
+                            self.error(err);
+                            self.skip_to_item();
+                        }
+                    }
+                    expr_allowed = false;
+                }
+                Some(_) if expr_allowed => {
+                    items.push(Item::Expr(self.seq_expr()));
+                    expr_allowed = false;
+                }
+                Some(_) => {
+                    self.expected(Expected::Token(Token::Let));
+                    let err = self.unexpected_err();
+                    self.error(err);
+                    self.skip_to_item();
+                }
+            }
+        }
+
+        let mut expr = match items.pop() {
+            Some(Item::Expr(expr)) => expr,
+            item => {
+                items.extend(item);
+                mk(ExprKind::Unit, self.peek_span())
+            }
+        };
+
+        for item in items.into_iter().rev() {
+            expr = match item {
+                Item::Let(binding, let_span) => binding.with_body(let_span, expr),
+                Item::Type(decls, type_span) => {
+                    let span = type_span.to(expr.span);
+                    mk(ExprKind::TypeDecl { decls, body: Box::new(expr) }, span)
+                }
+                Item::External { bndr, ty, symbol, span: external_span } => {
+                    let span = external_span.to(expr.span);
+                    mk(
+                        ExprKind::External { bndr, ty, symbol, body: Box::new(expr) },
+                        span,
+                    )
+                }
+                Item::Exception(decl, exception_span) => {
+                    let span = exception_span.to(expr.span);
+                    mk(ExprKind::Exception { decl, body: Box::new(expr) }, span)
+                }
+                Item::Expr(item_expr) => {
+                    let span = item_expr.span.to(expr.span);
+                    mk(
+                        ExprKind::Let {
+                            bndr: "_".to_owned(),
+                            rhs: Box::new(item_expr),
+                            body: Box::new(expr),
+                        },
+                        span,
+                    )
+                }
+            };
+        }
+
+        expr
+    }
+
+    // Top-level `let`: a definition, or a `let ... in` expression
+    fn let_item(&mut self) -> ParseResult<Item> {
+        let let_span = self.bump();
+        let binding = self.let_binding()?;
+        if self.skip(&Token::In).is_none() {
+            return Ok(Item::Let(binding, let_span));
+        }
+        let body = self.seq_expr();
+        Ok(Item::Expr(binding.with_body(let_span, body)))
+    }
+
+    // `type <type_decl> (and <type_decl>)*`
+    fn type_item(&mut self) -> ParseResult<Item> {
+        let type_span = self.bump();
+        let mut decls = vec![self.type_decl()?];
+        while self.skip(&Token::And).is_some() {
+            decls.push(self.type_decl()?);
+        }
+        Ok(Item::Type(decls, type_span))
+    }
+
+    // `external <ident> : <type> = <string>`
+    fn external_item(&mut self) -> ParseResult<Item> {
+        let external_span = self.bump();
+        let bndr = match self.peek() {
+            Some(Token::Id(name)) => name.clone(),
+            _ => {
+                self.expected(Expected::Ident);
+                return self.unexpected();
+            }
+        };
+        self.bump();
+        self.expect(&Token::Colon)?;
+        let ty = self.type_expr()?;
+        self.expect(&Token::Equal)?;
+        let symbol = match self.peek() {
+            Some(Token::String(bytes)) => String::from_utf8_lossy(bytes).into_owned(),
+            _ => {
+                self.expected(Expected::String);
+                return self.unexpected();
+            }
+        };
+        self.bump();
+        Ok(Item::External { bndr, ty, symbol, span: external_span })
+    }
+
+    // `<ident> = <ctor_decl> (| <ctor_decl>)*` or `<ident> = { <field_decl> (; <field_decl>)* }`.
+    // The `|` before the first constructor and the `;` after the last field are optional.
+    fn type_decl(&mut self) -> ParseResult<TypeDecl> {
+        let span = self.peek_span();
+        let name = match self.peek() {
+            Some(Token::Id(name)) => name.clone(),
+            _ => {
+                self.expected(Expected::Ident);
+                return self.unexpected();
+            }
+        };
+        self.bump();
+        self.expect(&Token::Equal)?;
+        if self.skip(&Token::LBrace).is_some() {
+            let mut fields = vec![self.field_decl()?];
+            while self.skip(&Token::Semicolon).is_some() {
+                if self.peek() == Some(&Token::RBrace) {
+                    break;
+                }
+                fields.push(self.field_decl()?);
+            }
+            self.expect(&Token::RBrace)?;
+            return Ok(TypeDecl { name, kind: TypeDeclKind::Record(fields), span });
+        }
+        self.skip(&Token::Bar);
+        let mut ctors = vec![self.ctor_decl()?];
+        while self.skip(&Token::Bar).is_some() {
+            ctors.push(self.ctor_decl()?);
+        }
+        Ok(TypeDecl { name, kind: TypeDeclKind::Variant(ctors), span })
+    }
+
+    // `mutable? <ident> : <type>`
+    fn field_decl(&mut self) -> ParseResult<FieldDecl> {
+        let mutable = self.skip(&Token::Mutable).is_some();
+        let span = self.peek_span();
+        let name = match self.peek() {
+            Some(Token::Id(name)) => name.clone(),
+            _ => {
+                self.expected(Expected::Ident);
+                return self.unexpected();
+            }
+        };
+        self.bump();
+        self.expect(&Token::Colon)?;
+        let ty = self.type_expr()?;
+        Ok(FieldDecl { name, mutable, ty, span })
+    }
+
+    // `<ctor> (of <type>)?`. Fields of the constructor are given as a tuple type.
+    fn ctor_decl(&mut self) -> ParseResult<CtorDecl> {
+        let span = self.peek_span();
+        let name = match self.peek() {
+            Some(Token::UpperId(name)) => name.clone(),
+            _ => {
+                self.expected(Expected::Ctor);
+                return self.unexpected();
+            }
+        };
+        self.bump();
+        let fields = match self.skip(&Token::Of) {
+            None => vec![],
+            Some(_) => match self.type_expr()? {
+                TypeExpr { kind: TypeExprKind::Tuple(tys), .. } => tys,
+                ty => vec![ty],
             },
+        };
+        Ok(CtorDecl { name, fields, span })
+    }
+
+    // Skip to the next `;;`, `let`, `type`, `external` or `exception` outside of parens, to
+    // recover from an error in a top-level item
+    fn skip_to_item(&mut self) {
+        let mut depth = 0u32;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::LParen => depth += 1,
+                Token::RParen if depth > 0 => depth -= 1,
+                Token::SemiSemi | Token::Let | Token::Type | Token::External | Token::Exception
+                    if depth == 0 =>
+                {
+                    break
+                }
+                _ => {}
+            }
+            self.bump();
+        }
+    }
+
+    // `<expr> ; <expr>`. Right associative.
+    fn seq_expr(&mut self) -> ParsedExpr {
+        let expr1 = self.recover_let_expr();
+        if self.skip(&Token::Semicolon).is_none() {
+            return expr1;
+        }
+        let expr2 = self.seq_expr();
+        let span = expr1.span.to(expr2.span);
+        // TODO: fix the binder
+        mk(
+            ExprKind::Let { bndr: "_".to_owned(), rhs: Box::new(expr1), body: Box::new(expr2) },
+            span,
+        )
+    }
 
-        "-." <expr:AppExpr> =>
-            ParsedExpr::FNeg(Box::new(expr)),
+    // `let`, `let rec`, `let (...)`, `fun`, `match`, `try`, and loops
+    fn let_expr(&mut self) -> ParseResult<ParsedExpr> {
+        match self.peek() {
+            Some(Token::Fun) => return self.fun_expr(),
+            Some(Token::Match) => return self.match_expr(),
+            Some(Token::Try) => return self.try_expr(),
+            Some(Token::For) => return self.for_expr(),
+            Some(Token::While) => return self.while_expr(),
+            _ => {}
+        }
+        if self.peek() != Some(&Token::Let) {
+            return self.if_expr();
+        }
+        let let_span = self.bump();
+        let binding = self.let_binding()?;
+        self.expect(&Token::In)?;
+        let body = self.seq_expr();
+        Ok(binding.with_body(let_span, body))
+    }
 
-        "not" <expr:AppExpr> =>
-            ParsedExpr::Not(Box::new(expr)),
+    // Binders and RHS of a `let`, after the `let` keyword
+    fn let_binding(&mut self) -> ParseResult<LetBinding> {
+        match self.peek() {
+            Some(Token::Rec) => {
+                self.bump();
+                let mut defs = vec![self.fun_def()?];
+                while self.skip(&Token::And).is_some() {
+                    defs.push(self.fun_def()?);
+                }
+                Ok(LetBinding::LetRec { defs })
+            }
+
+            Some(Token::LParen) => {
+                let lparen_span = self.bump();
+
+                // `let () = ...`. The RHS is annotated to check that it has type unit.
+                if let Some(rparen_span) = self.skip(&Token::RParen) {
+                    let ty = TypeExpr {
+                        kind: TypeExprKind::Named("unit".to_owned()),
+                        span: lparen_span.to(rparen_span),
+                    };
+                    self.expect(&Token::Equal)?;
+                    let rhs = annot(self.seq_expr(), ty);
+                    return Ok(LetBinding::Let { bndr: "_".to_owned(), rhs });
+                }
 
-        <expr:AppExpr> =>
-            expr,
-    };
+                let bndr = self.binder()?;
 
-    AppExpr: ParsedExpr = {
-        <expr:GetPutExpr> =>
-            expr,
+                // `let (x : t) = ...`
+                if self.skip(&Token::Colon).is_some() {
+                    let ty = self.type_expr()?;
+                    self.expect(&Token::RParen)?;
+                    self.expect(&Token::Equal)?;
+                    let rhs = annot(self.seq_expr(), ty);
+                    return Ok(LetBinding::Let { bndr, rhs });
+                }
 
-        <expr1:GetPutExpr> <exprs:GetPutExprs1> =>
-            ParsedExpr::App { fun: Box::new(expr1), args: exprs },
+                let mut bndrs = vec![bndr];
+                self.expect(&Token::Comma)?;
+                bndrs.push(self.binder()?);
+                while self.skip(&Token::Comma).is_some() {
+                    bndrs.push(self.binder()?);
+                }
+                self.expect(&Token::RParen)?;
+                self.expect(&Token::Equal)?;
+                let rhs = self.seq_expr();
+                Ok(LetBinding::LetTuple { bndrs, rhs })
+            }
 
-        "Array.create" <e1:GetPutExpr> <e2:GetPutExpr> =>
-            ParsedExpr::Array { len: Box::new(e1), elem: Box::new(e2) },
-    };
+            _ => {
+                let bndr = self.binder()?;
+                let ty = match self.skip(&Token::Colon) {
+                    Some(_) => Some(self.type_expr()?),
+                    None => None,
+                };
+                self.expect(&Token::Equal)?;
+                let rhs = self.seq_expr();
+                let rhs = match ty {
+                    Some(ty) => annot(rhs, ty),
+                    None => rhs,
+                };
+                Ok(LetBinding::Let { bndr, rhs })
+            }
+        }
+    }
 
-    // Array get and put expressions: `<expr> . ( <expr> )`, `<expr> . ( <expr> ) <- `<expr>`
-    GetPutExpr: ParsedExpr = {
-        <expr1:SimpleExpr> =>
-            expr1,
+    // `<ident> <arg>+ (: <type>)? = <expr>` in a `let rec`
+    fn fun_def(&mut self) -> ParseResult<FunDef<String>> {
+        let bndr = self.binder()?;
+        let (args, arg_tys) = self.fun_args()?;
+        let ret_ty = match self.skip(&Token::Colon) {
+            Some(_) => Some(self.type_expr()?),
+            None => None,
+        };
+        self.expect(&Token::Equal)?;
+        let rhs = self.seq_expr();
+        Ok(FunDef { bndr, args, arg_tys, ret_ty, rhs })
+    }
+
+    // `fun <arg>+ -> <expr>`. Like `let`, the body extends as far to the right as possible.
+    fn fun_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let fun_span = self.bump();
+        let (args, arg_tys) = self.fun_args()?;
+        self.expect(&Token::MinusGreater)?;
+        let body = self.seq_expr();
+        let span = fun_span.to(body.span);
+        Ok(mk(
+            ExprKind::Fun { args, arg_tys, body: Box::new(body) },
+            span,
+        ))
+    }
+
+    // Function arguments: one or more `<ident>` or `(<ident> : <type>)`
+    fn fun_args(&mut self) -> ParseResult<(Vec<String>, Vec<Option<TypeExpr>>)> {
+        let mut args = vec![];
+        let mut arg_tys = vec![];
+        loop {
+            match self.peek() {
+                Some(Token::LParen) => {
+                    self.bump();
+                    args.push(self.binder()?);
+                    self.expect(&Token::Colon)?;
+                    arg_tys.push(Some(self.type_expr()?));
+                    self.expect(&Token::RParen)?;
+                }
+                Some(Token::Id(_)) | Some(Token::Underscore) => {
+                    args.push(self.binder()?);
+                    arg_tys.push(None);
+                }
+                _ if args.is_empty() => {
+                    self.expected(Expected::Ident);
+                    return self.unexpected();
+                }
+                _ => return Ok((args, arg_tys)),
+            }
+        }
+    }
+
+    // `<type> (-> <type>)*`. A chain of arrows is one function type with multiple arguments.
+    fn type_expr(&mut self) -> ParseResult<TypeExpr> {
+        let mut tys = vec![self.tuple_type()?];
+        while self.skip(&Token::MinusGreater).is_some() {
+            tys.push(self.tuple_type()?);
+        }
+        let ret = tys.pop().unwrap();
+        if tys.is_empty() {
+            return Ok(ret);
+        }
+        let span = tys[0].span.to(ret.span);
+        Ok(TypeExpr { kind: TypeExprKind::Fun { args: tys, ret: Box::new(ret) }, span })
+    }
 
-        <expr1:SimpleExpr> <get_put_suffix:GetOrPutSuffix1_Rev> => {
-            let mut expr = expr1;
-            let (mut gets, put) = get_put_suffix;
+    // `<type> (* <type>)*`
+    fn tuple_type(&mut self) -> ParseResult<TypeExpr> {
+        let ty = self.array_type()?;
+        if self.peek() != Some(&Token::Ast) {
+            self.expected(Expected::Token(Token::Ast));
+            return Ok(ty);
+        }
+        let mut tys = vec![ty];
+        while self.skip(&Token::Ast).is_some() {
+            tys.push(self.array_type()?);
+        }
+        let span = tys[0].span.to(tys[tys.len() - 1].span);
+        Ok(TypeExpr { kind: TypeExprKind::Tuple(tys), span })
+    }
+
+    // `<type> (array | list | ref)*`
+    fn array_type(&mut self) -> ParseResult<TypeExpr> {
+        let mut ty = self.simple_type()?;
+        loop {
+            let ctor: fn(Box<TypeExpr>) -> TypeExprKind = match self.peek() {
+                Some(Token::Id(id)) if id == "array" => TypeExprKind::Array,
+                Some(Token::Id(id)) if id == "list" => TypeExprKind::List,
+                Some(Token::Ref) => TypeExprKind::Ref,
+                _ => return Ok(ty),
+            };
+            let span = ty.span.to(self.bump());
+            ty = TypeExpr { kind: ctor(Box::new(ty)), span };
+        }
+    }
+
+    fn simple_type(&mut self) -> ParseResult<TypeExpr> {
+        match self.peek() {
+            Some(Token::LParen) => {
+                self.bump();
+                let ty = self.type_expr()?;
+                self.expect(&Token::RParen)?;
+                Ok(ty)
+            }
+            Some(Token::Id(id)) => {
+                let kind = TypeExprKind::Named(id.clone());
+                let span = self.bump();
+                Ok(TypeExpr { kind, span })
+            }
+            _ => {
+                self.expected(Expected::Type);
+                self.unexpected()
+            }
+        }
+    }
+
+    // `match <expr> with | <pat> (when <expr>)? -> <expr> ...`. The `|` before the first arm is
+    // optional. Like in OCaml, arms of a nested `match` need to be in parens, otherwise the
+    // following arms belong to the nested `match`.
+    fn match_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let match_span = self.bump();
+        let scrut = self.seq_expr();
+        self.expect(&Token::With)?;
+        let arms = self.match_arms()?;
+        let span = match_span.to(arms[arms.len() - 1].rhs.span);
+        Ok(mk(ExprKind::Match { scrut: Box::new(scrut), arms }, span))
+    }
+
+    // `try <expr> with (| <pat> (when <expr>)? -> <expr>)+`
+    fn try_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let try_span = self.bump();
+        let body = self.seq_expr();
+        self.expect(&Token::With)?;
+        let arms = self.match_arms()?;
+        let span = try_span.to(arms[arms.len() - 1].rhs.span);
+        Ok(mk(ExprKind::Try { body: Box::new(body), arms }, span))
+    }
+
+    // Arms of a `match` or `try`. The `|` before the first arm is optional.
+    fn match_arms(&mut self) -> ParseResult<Vec<Arm<String>>> {
+        self.skip(&Token::Bar);
+        let mut arms = vec![self.match_arm()?];
+        while self.skip(&Token::Bar).is_some() {
+            arms.push(self.match_arm()?);
+        }
+        Ok(arms)
+    }
+
+    // `for <ident> = <expr> (to | downto) <expr> do <expr> done`
+    fn for_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let for_span = self.bump();
+        let var = self.binder()?;
+        self.expect(&Token::Equal)?;
+        let start = self.seq_expr();
+        let dir = match self.peek() {
+            Some(Token::To) => ForDir::To,
+            Some(Token::Downto) => ForDir::Downto,
+            _ => {
+                self.expected(Expected::Token(Token::To));
+                self.expected(Expected::Token(Token::Downto));
+                return self.unexpected();
+            }
+        };
+        self.bump();
+        let end = self.seq_expr();
+        self.expect(&Token::Do)?;
+        let body = self.seq_expr();
+        let done_span = self.expect(&Token::Done)?;
+        Ok(mk(
+            ExprKind::For {
+                var,
+                start: Box::new(start),
+                dir,
+                end: Box::new(end),
+                body: Box::new(body),
+            },
+            for_span.to(done_span),
+        ))
+    }
+
+    // `while <expr> do <expr> done`
+    fn while_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let while_span = self.bump();
+        let cond = self.seq_expr();
+        self.expect(&Token::Do)?;
+        let body = self.seq_expr();
+        let done_span = self.expect(&Token::Done)?;
+        Ok(mk(
+            ExprKind::While { cond: Box::new(cond), body: Box::new(body) },
+            while_span.to(done_span),
+        ))
+    }
 
-            match put {
-                None => {
-                    for idx in gets.into_iter().rev() {
-                        expr = ParsedExpr::Get(Box::new(expr), Box::new(idx));
+    fn match_arm(&mut self) -> ParseResult<Arm<String>> {
+        let pat = self.pattern()?;
+        let guard = self.skip(&Token::When).map(|_| self.seq_expr());
+        self.expect(&Token::MinusGreater)?;
+        let rhs = self.seq_expr();
+        Ok(Arm { pat, guard, rhs })
+    }
+
+    // `<pat> (, <pat>)*`
+    fn pattern(&mut self) -> ParseResult<Pat<String>> {
+        let pat = self.cons_pattern()?;
+        if self.peek() != Some(&Token::Comma) {
+            self.expected(Expected::Token(Token::Comma));
+            return Ok(pat);
+        }
+        let mut pats = vec![pat];
+        while self.skip(&Token::Comma).is_some() {
+            pats.push(self.cons_pattern()?);
+        }
+        let span = pats[0].span.to(pats[pats.len() - 1].span);
+        Ok(Pat { kind: PatKind::Tuple(pats), span })
+    }
+
+    // `<pat> :: <pat>`. Right associative.
+    fn cons_pattern(&mut self) -> ParseResult<Pat<String>> {
+        let hd = self.simple_pattern()?;
+        if self.peek() != Some(&Token::ColonColon) {
+            self.expected(Expected::Token(Token::ColonColon));
+            return Ok(hd);
+        }
+        self.bump();
+        let tl = self.cons_pattern()?;
+        let span = hd.span.to(tl.span);
+        Ok(Pat { kind: PatKind::Cons(Box::new(hd), Box::new(tl)), span })
+    }
+
+    fn simple_pattern(&mut self) -> ParseResult<Pat<String>> {
+        let kind = match self.peek() {
+            Some(Token::LParen) => {
+                let lparen_span = self.bump();
+                if let Some(rparen_span) = self.skip(&Token::RParen) {
+                    return Ok(Pat { kind: PatKind::Unit, span: lparen_span.to(rparen_span) });
+                }
+                let pat = self.pattern()?;
+                self.expect(&Token::RParen)?;
+                return Ok(pat);
+            }
+            // `[]` or `[<pat>; ...]`, desugared to `::`s
+            Some(Token::LBracket) => {
+                let lbracket_span = self.bump();
+                let mut pats: Vec<Pat<String>> = vec![];
+                if self.peek() != Some(&Token::RBracket) {
+                    loop {
+                        pats.push(self.pattern()?);
+                        if self.skip(&Token::Semicolon).is_none()
+                            || self.peek() == Some(&Token::RBracket)
+                        {
+                            break;
+                        }
                     }
                 }
-                Some(put) => {
-                    let put_idx = gets.remove(0);
-                    for idx in gets.into_iter().rev() {
-                        expr = ParsedExpr::Get(Box::new(expr), Box::new(idx));
+                let rbracket_span = self.expect(&Token::RBracket)?;
+                let mut pat = Pat { kind: PatKind::Nil, span: rbracket_span };
+                for hd in pats.into_iter().rev() {
+                    let span = hd.span;
+                    pat = Pat { kind: PatKind::Cons(Box::new(hd), Box::new(pat)), span };
+                }
+                pat.span = lbracket_span.to(rbracket_span);
+                return Ok(pat);
+            }
+            Some(Token::Minus) => {
+                let minus_span = self.bump();
+                match self.peek() {
+                    Some(Token::Int(i)) => {
+                        let kind = PatKind::Int(i.wrapping_neg());
+                        let span = minus_span.to(self.bump());
+                        return Ok(Pat { kind, span });
                     }
-                    expr = ParsedExpr::Put(Box::new(expr), Box::new(put_idx), Box::new(put));
+                    _ => {
+                        self.expected(Expected::Int);
+                        return self.unexpected();
+                    }
+                }
+            }
+            Some(Token::UpperId(ctor)) => {
+                let ctor = ctor.clone();
+                let ctor_span = self.bump();
+                if !self.at_simple_pattern() {
+                    return Ok(Pat { kind: PatKind::Ctor { ctor, args: vec![] }, span: ctor_span });
                 }
+                let arg = self.simple_pattern()?;
+                let span = ctor_span.to(arg.span);
+                let args = match arg.kind {
+                    PatKind::Tuple(args) => args,
+                    _ => vec![arg],
+                };
+                return Ok(Pat { kind: PatKind::Ctor { ctor, args }, span });
+            }
+            Some(Token::Underscore) => PatKind::Wildcard,
+            Some(Token::Id(var)) => PatKind::Var(var.clone()),
+            Some(Token::Bool(b)) => PatKind::Bool(*b),
+            Some(Token::Int(i)) => PatKind::Int(*i),
+            _ => {
+                self.expected(Expected::Pattern);
+                return self.unexpected();
             }
+        };
+        let span = self.bump();
+        Ok(Pat { kind, span })
+    }
+
+    // Whether the next token can start a `simple_pattern`
+    fn at_simple_pattern(&mut self) -> bool {
+        self.expected(Expected::Pattern);
+        matches!(
+            self.peek(),
+            Some(Token::LParen)
+                | Some(Token::LBracket)
+                | Some(Token::Underscore)
+                | Some(Token::Id(_))
+                | Some(Token::UpperId(_))
+                | Some(Token::Bool(_))
+                | Some(Token::Int(_))
+        )
+    }
 
-            expr
+    fn binder(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            Some(Token::Underscore) => {
+                self.bump();
+                Ok("_".to_owned())
+            }
+            Some(Token::Id(id)) => {
+                self.bump();
+                Ok(id.clone())
+            }
+            _ => {
+                self.expected(Expected::Ident);
+                self.unexpected()
+            }
         }
-    };
+    }
 
-    GetOrPutSuffix1_Rev: (Vec<ParsedExpr>, Option<ParsedExpr>) = {
-        "." "(" <expr:SeqExpr> ")" <mut rest:GetOrPutSuffix0_Rev> => {
-            let (mut gets, put) = rest;
-            gets.push(expr);
-            (gets, put)
-        },
-    };
+    // if-then-else
+    fn if_expr(&mut self) -> ParseResult<ParsedExpr> {
+        if self.peek() != Some(&Token::If) {
+            return self.assign_expr();
+        }
+        let if_span = self.bump();
+        let e1 = self.seq_expr();
+        self.expect(&Token::Then)?;
+        let e2 = self.seq_expr();
+        self.expect(&Token::Else)?;
+        let e3 = self.let_expr()?;
+        let span = if_span.to(e3.span);
+        Ok(mk(
+            ExprKind::If(Box::new(e1), Box::new(e2), Box::new(e3)),
+            span,
+        ))
+    }
 
-    GetOrPutSuffix0_Rev: (Vec<ParsedExpr>, Option<ParsedExpr>) = {
-        => (vec![], None),
+    // `<expr> := <expr>`. Like array writes, the RHS extends as far to the right as possible.
+    fn assign_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let expr = self.tuple_expr()?;
+        if self.peek() != Some(&Token::ColonEqual) {
+            self.expected(Expected::Operator);
+            return Ok(expr);
+        }
+        self.bump();
+        let expr2 = self.let_expr()?;
+        let span = expr.span.to(expr2.span);
+        Ok(mk(ExprKind::Assign(Box::new(expr), Box::new(expr2)), span))
+    }
 
-        "." "(" <expr:SeqExpr> ")" <mut rest:GetOrPutSuffix0_Rev> => {
-            let (mut gets, put) = rest;
-            gets.push(expr);
-            (gets, put)
-        },
+    // `<expr> (, <expr>)*`
+    fn tuple_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let expr = self.or_expr()?;
+        if self.peek() != Some(&Token::Comma) {
+            self.expected(Expected::Token(Token::Comma));
+            return Ok(expr);
+        }
+        let mut exprs = vec![expr];
+        while self.skip(&Token::Comma).is_some() {
+            exprs.push(self.or_expr()?);
+        }
+        let span = exprs[0].span.to(exprs[exprs.len() - 1].span);
+        Ok(mk(ExprKind::Tuple(exprs), span))
+    }
 
-        "<-" <expr:LetExpr> =>
-            (vec![], Some(expr)),
-    };
+    // `||`. Right associative.
+    fn or_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let expr = self.and_expr()?;
+        if self.peek() != Some(&Token::BarBar) {
+            self.expected(Expected::Operator);
+            return Ok(expr);
+        }
+        self.bump();
+        let expr2 = self.or_expr()?;
+        let span = expr.span.to(expr2.span);
+        Ok(mk(ExprKind::Or(Box::new(expr), Box::new(expr2)), span))
+    }
 
-    // One or more exprs
-    GetPutExprs1: Vec<ParsedExpr> = {
-        <expr:GetPutExpr> <mut exprs:GetPutExprs0> => {
-            exprs.push(expr);
-            exprs.reverse();
-            exprs
+    // `&&`. Right associative.
+    fn and_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let expr = self.cmp_expr()?;
+        if self.peek() != Some(&Token::AmpAmp) {
+            self.expected(Expected::Operator);
+            return Ok(expr);
         }
-    };
+        self.bump();
+        let expr2 = self.and_expr()?;
+        let span = expr.span.to(expr2.span);
+        Ok(mk(ExprKind::And(Box::new(expr), Box::new(expr2)), span))
+    }
 
-    // Zero or more exprs
-    GetPutExprs0: Vec<ParsedExpr> = {
-        => vec![],
+    // Comparison operators: `=`, `<>`, `<=` `<`, `>=`, `>`. These all have the same the same
+    // precedence, and are all left associative.
+    fn cmp_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let mut expr = self.concat_expr()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Equal) => Cmp::Equal,
+                Some(Token::LessGreater) => Cmp::NotEqual,
+                Some(Token::Less) => Cmp::LessThan,
+                Some(Token::LessEqual) => Cmp::LessThanOrEqual,
+                Some(Token::Greater) => Cmp::GreaterThan,
+                Some(Token::GreaterEqual) => Cmp::GreaterThanOrEqual,
+                _ => {
+                    self.expected(Expected::Operator);
+                    return Ok(expr);
+                }
+            };
+            self.bump();
+            let expr2 = self.concat_expr()?;
+            let span = expr.span.to(expr2.span);
+            expr = mk(ExprKind::Cmp(Box::new(expr), op, Box::new(expr2)), span);
+        }
+    }
 
-        <expr1:GetPutExpr> <mut exprs:GetPutExprs0> => {
-            exprs.push(expr1);
-            exprs
-        },
-    };
+    // String concatenation: `^`. Right associative. Parsed as an application of the builtin `^`.
+    fn concat_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let expr = self.cons_expr()?;
+        if self.peek() != Some(&Token::Caret) {
+            self.expected(Expected::Operator);
+            return Ok(expr);
+        }
+        let op_span = self.bump();
+        let expr2 = self.concat_expr()?;
+        let span = expr.span.to(expr2.span);
+        Ok(mk(
+            ExprKind::App {
+                fun: Box::new(mk(ExprKind::Var("^".to_owned()), op_span)),
+                args: vec![expr, expr2],
+            },
+            span,
+        ))
+    }
 
+    // `<expr> :: <expr>`. Right associative.
+    fn cons_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let hd = self.binop1_expr()?;
+        if self.peek() != Some(&Token::ColonColon) {
+            self.expected(Expected::Operator);
+            return Ok(hd);
+        }
+        self.bump();
+        let tl = self.cons_expr()?;
+        let span = hd.span.to(tl.span);
+        Ok(mk(ExprKind::Cons(Box::new(hd), Box::new(tl)), span))
+    }
 
-    SimpleExpr: ParsedExpr = {
-        "(" ")" =>
-            ParsedExpr::Unit,
+    // `+`, `-`, `+.`, `-.`. Left associative.
+    fn binop1_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let mut expr = self.binop2_expr()?;
+        loop {
+            let op: fn(Box<ParsedExpr>, Box<ParsedExpr>) -> ExprKind<String> = match self.peek() {
+                Some(Token::Plus) => |e1, e2| ExprKind::IntBinOp(e1, IntBinOp::Add, e2),
+                Some(Token::Minus) => |e1, e2| ExprKind::IntBinOp(e1, IntBinOp::Sub, e2),
+                Some(Token::PlusDot) => |e1, e2| ExprKind::FloatBinOp(e1, FloatBinOp::Add, e2),
+                Some(Token::MinusDot) => |e1, e2| ExprKind::FloatBinOp(e1, FloatBinOp::Sub, e2),
+                _ => {
+                    self.expected(Expected::Operator);
+                    return Ok(expr);
+                }
+            };
+            self.bump();
+            let expr2 = self.binop2_expr()?;
+            let span = expr.span.to(expr2.span);
+            expr = mk(op(Box::new(expr), Box::new(expr2)), span);
+        }
+    }
 
-        "(" <expr:SeqExpr> ")" =>
-            expr,
+    // `*`, `/`, `mod`, `*.`, `/.`. Left associative.
+    fn binop2_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let mut expr = self.unop_expr()?;
+        loop {
+            let op: fn(Box<ParsedExpr>, Box<ParsedExpr>) -> ExprKind<String> = match self.peek() {
+                Some(Token::Ast) => |e1, e2| ExprKind::IntBinOp(e1, IntBinOp::Mul, e2),
+                Some(Token::Slash) => |e1, e2| ExprKind::IntBinOp(e1, IntBinOp::Div, e2),
+                Some(Token::Mod) => |e1, e2| ExprKind::IntBinOp(e1, IntBinOp::Mod, e2),
+                Some(Token::AstDot) => |e1, e2| ExprKind::FloatBinOp(e1, FloatBinOp::Mul, e2),
+                Some(Token::SlashDot) => |e1, e2| ExprKind::FloatBinOp(e1, FloatBinOp::Div, e2),
+                _ => {
+                    self.expected(Expected::Operator);
+                    return Ok(expr);
+                }
+            };
+            self.bump();
+            let expr2 = self.unop_expr()?;
+            let span = expr.span.to(expr2.span);
+            expr = mk(op(Box::new(expr), Box::new(expr2)), span);
+        }
+    }
 
-        <b:"bool"> =>
-            ParsedExpr::Bool(b),
+    // `-`, `-.`, `not`, `ref`, `assert`
+    fn unop_expr(&mut self) -> ParseResult<ParsedExpr> {
+        match self.peek() {
+            Some(Token::Minus) => {
+                let op_span = self.bump();
+                let expr = self.app_expr()?;
+                let span = op_span.to(expr.span);
+                let kind = match expr.kind {
+                    ExprKind::Float(_) => ExprKind::FNeg(Box::new(expr)),
+                    _ => ExprKind::Neg(Box::new(expr)),
+                };
+                Ok(mk(kind, span))
+            }
+            Some(Token::MinusDot) => {
+                let op_span = self.bump();
+                let expr = self.app_expr()?;
+                let span = op_span.to(expr.span);
+                Ok(mk(ExprKind::FNeg(Box::new(expr)), span))
+            }
+            Some(Token::Not) => {
+                let op_span = self.bump();
+                let expr = self.app_expr()?;
+                let span = op_span.to(expr.span);
+                Ok(mk(ExprKind::Not(Box::new(expr)), span))
+            }
+            Some(Token::Ref) => {
+                let op_span = self.bump();
+                let expr = self.app_expr()?;
+                let span = op_span.to(expr.span);
+                Ok(mk(ExprKind::Ref(Box::new(expr)), span))
+            }
+            Some(Token::Assert) => {
+                let op_span = self.bump();
+                let expr = self.app_expr()?;
+                let span = op_span.to(expr.span);
+                Ok(mk(ExprKind::Abort(AbortKind::Assert, Box::new(expr)), span))
+            }
+            _ => self.app_expr(),
+        }
+    }
 
-        <i:"int"> =>
-            ParsedExpr::Int(i),
+    // Function application. `Array.make` (or `Array.create`) applied to two arguments is array
+    // allocation. `failwith`, `exit` and `raise` applied to one argument abort the program.
+    fn app_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let fun = self.get_put_expr()?;
+        let mut args = vec![];
+        while self.at_simple_expr() {
+            args.push(self.get_put_expr()?);
+        }
+        let span = match args.last() {
+            None => return Ok(fun),
+            Some(last) => fun.span.to(last.span),
+        };
+        match &fun.kind {
+            ExprKind::Ctor { ctor, args: ctor_args } if ctor_args.is_empty() && args.len() == 1 => {
+                let ctor = ctor.clone();
+                let arg = args.pop().unwrap();
+                let args = match arg.kind {
+                    ExprKind::Tuple(args) => args,
+                    _ => vec![arg],
+                };
+                Ok(mk(ExprKind::Ctor { ctor, args }, span))
+            }
+            ExprKind::Var(var)
+                if (var == "Array.make" || var == "Array.create") && args.len() == 2 =>
+            {
+                let mut args = args.into_iter();
+                let len = Box::new(args.next().unwrap());
+                let elem = Box::new(args.next().unwrap());
+                Ok(mk(ExprKind::Array { len, elem }, span))
+            }
+            ExprKind::Var(var)
+                if (var == "failwith" || var == "exit" || var == "raise") && args.len() == 1 =>
+            {
+                let kind = match var.as_str() {
+                    "failwith" => AbortKind::Failwith,
+                    "exit" => AbortKind::Exit,
+                    _ => AbortKind::Raise,
+                };
+                Ok(mk(
+                    ExprKind::Abort(kind, Box::new(args.pop().unwrap())),
+                    span,
+                ))
+            }
+            _ => Ok(mk(ExprKind::App { fun: Box::new(fun), args }, span)),
+        }
+    }
 
-        <f:"float"> =>
-            ParsedExpr::Float(f),
+    // Array get and put expressions: `<expr> . ( <expr> )`, `<expr> . ( <expr> ) <- <expr>`
+    fn get_put_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let mut expr = self.simple_expr()?;
+
+        // Indices and fields, with the span of the closing paren or the field name
+        let mut accesses: Vec<(Access, Span)> = vec![];
+        while self.skip(&Token::Dot).is_some() {
+            if let Some(Token::Id(field)) = self.peek() {
+                let field = field.clone();
+                let field_span = self.bump();
+                accesses.push((Access::Field(field), field_span));
+                continue;
+            }
+            self.expected(Expected::Ident);
+            self.expect(&Token::LParen)?;
+            let idx = self.seq_expr();
+            let rparen_span = self.expect(&Token::RParen)?;
+            accesses.push((Access::Index(idx), rparen_span));
+        }
 
-        <var:"id"> =>
-            ParsedExpr::Var(var),
-    };
+        let put = if !accesses.is_empty() && self.skip(&Token::LessMinus).is_some() {
+            let (put_access, _) = accesses.pop().unwrap();
+            Some((put_access, self.let_expr()?))
+        } else {
+            None
+        };
+
+        for (access, span) in accesses {
+            let span = expr.span.to(span);
+            let kind = match access {
+                Access::Index(idx) => ExprKind::Get(Box::new(expr), Box::new(idx)),
+                Access::Field(field) => ExprKind::GetField { expr: Box::new(expr), field },
+            };
+            expr = mk(kind, span);
+        }
+
+        if let Some((put_access, val)) = put {
+            let span = expr.span.to(val.span);
+            let kind = match put_access {
+                Access::Index(idx) => ExprKind::Put(Box::new(expr), Box::new(idx), Box::new(val)),
+                Access::Field(field) => {
+                    ExprKind::SetField { expr: Box::new(expr), field, value: Box::new(val) }
+                }
+            };
+            expr = mk(kind, span);
+        }
+
+        Ok(expr)
+    }
+
+    // Whether the next token can start a `simple_expr`
+    fn at_simple_expr(&mut self) -> bool {
+        self.expected(Expected::Expr);
+        matches!(
+            self.peek(),
+            Some(Token::LParen)
+                | Some(Token::LBrace)
+                | Some(Token::LBracket)
+                | Some(Token::Bool(_))
+                | Some(Token::Int(_))
+                | Some(Token::Float(_))
+                | Some(Token::String(_))
+                | Some(Token::Char(_))
+                | Some(Token::Id(_))
+                | Some(Token::UpperId(_))
+                | Some(Token::Bang)
+        )
+    }
+
+    fn simple_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let kind = match self.peek() {
+            Some(Token::LParen) => {
+                let lparen_span = self.bump();
+                if let Some(rparen_span) = self.skip(&Token::RParen) {
+                    return Ok(mk(ExprKind::Unit, lparen_span.to(rparen_span)));
+                }
+                let expr = self.seq_expr();
+                if self.skip(&Token::Colon).is_none() {
+                    self.expect(&Token::RParen)?;
+                    return Ok(expr);
+                }
+                let ty = self.type_expr()?;
+                let rparen_span = self.expect(&Token::RParen)?;
+                let kind = ExprKind::Annot { expr: Box::new(expr), ty };
+                return Ok(mk(kind, lparen_span.to(rparen_span)));
+            }
+            Some(Token::LBrace) => return self.record_expr(),
+            Some(Token::LBracket) => return self.list_expr(),
+            Some(Token::Bool(b)) => ExprKind::Bool(*b),
+            Some(Token::Int(i)) => ExprKind::Int(*i),
+            Some(Token::Float(f)) => ExprKind::Float(*f),
+            Some(Token::String(bytes)) => ExprKind::String(bytes.clone()),
+            Some(Token::Char(c)) => ExprKind::Char(*c),
+            Some(Token::Id(var)) => ExprKind::Var(var.clone()),
+            Some(Token::UpperId(_)) => return self.path(),
+            // `!` binds tighter than application and array indexing
+            Some(Token::Bang) => {
+                let op_span = self.bump();
+                let expr = self.simple_expr()?;
+                let span = op_span.to(expr.span);
+                return Ok(mk(ExprKind::Deref(Box::new(expr)), span));
+            }
+            _ => {
+                self.expected(Expected::Expr);
+                return self.unexpected();
+            }
+        };
+        let span = self.bump();
+        Ok(mk(kind, span))
+    }
+
+    // `{ <ident> = <expr> (; <ident> = <expr>)* }`. The `;` after the last field is optional.
+    fn record_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let lbrace_span = self.bump();
+        let mut fields: Vec<FieldExpr<String>> = vec![];
+        loop {
+            let span = self.peek_span();
+            let field = match self.peek() {
+                Some(Token::Id(field)) => field.clone(),
+                _ => {
+                    self.expected(Expected::Ident);
+                    return self.unexpected();
+                }
+            };
+            self.bump();
+            self.expect(&Token::Equal)?;
+            let expr = self.let_expr()?;
+            fields.push(FieldExpr { field, span, expr });
+            if self.skip(&Token::Semicolon).is_none() || self.peek() == Some(&Token::RBrace) {
+                break;
+            }
+        }
+        let rbrace_span = self.expect(&Token::RBrace)?;
+        Ok(mk(ExprKind::Record(fields), lbrace_span.to(rbrace_span)))
+    }
+
+    // `[]` or `[<expr>; ...]`, desugared to `::`s. The `;` after the last element is optional.
+    // Tails of the list get the spans of their first elements, to report type errors in the
+    // elements.
+    fn list_expr(&mut self) -> ParseResult<ParsedExpr> {
+        let lbracket_span = self.bump();
+        let mut elems: Vec<ParsedExpr> = vec![];
+        if self.peek() != Some(&Token::RBracket) {
+            loop {
+                elems.push(self.let_expr()?);
+                if self.skip(&Token::Semicolon).is_none() || self.peek() == Some(&Token::RBracket) {
+                    break;
+                }
+            }
+        }
+        let rbracket_span = self.expect(&Token::RBracket)?;
+        let mut expr = mk(ExprKind::Nil, rbracket_span);
+        for elem in elems.into_iter().rev() {
+            let span = elem.span;
+            expr = mk(ExprKind::Cons(Box::new(elem), Box::new(expr)), span);
+        }
+        expr.span = lbracket_span.to(rbracket_span);
+        Ok(expr)
+    }
+
+    // Qualified name: `<Module> (. <Module>)* . <ident>`. Parsed as a variable with the dotted
+    // name, which is resolved to a builtin by the type checker. A capitalized name without a `.`
+    // is a constructor.
+    fn path(&mut self) -> ParseResult<ParsedExpr> {
+        let start_span = self.peek_span();
+        let mut path = String::new();
+        while let Some(Token::UpperId(module)) = self.peek() {
+            let module_span = self.bump();
+            if self.peek() != Some(&Token::Dot) && path.is_empty() {
+                self.expected(Expected::Token(Token::Dot));
+                let ctor = module.clone();
+                return Ok(mk(ExprKind::Ctor { ctor, args: vec![] }, module_span));
+            }
+            path.push_str(module);
+            path.push('.');
+            self.expect(&Token::Dot)?;
+        }
+        match self.peek() {
+            Some(Token::Id(id)) => {
+                path.push_str(id);
+                let span = start_span.to(self.bump());
+                Ok(mk(ExprKind::Var(path), span))
+            }
+            _ => {
+                self.expected(Expected::Ident);
+                self.unexpected()
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -347,47 +1435,724 @@ mod test {
 
     use crate::lexer::tokenize;
 
-    fn parse(s: &str) -> ParsedExpr {
+    fn parse_str(s: &str) -> Result<ParsedExpr, Vec<ParseErr>> {
         let tokens = tokenize(s).unwrap();
-        Expr::parse(tokens.into_iter().map(|r| Ok::<_, ()>(r))).unwrap()
+        let (expr, errs) = parse(&tokens);
+        if errs.is_empty() {
+            Ok(expr)
+        } else {
+            Err(errs)
+        }
+    }
+
+    fn e(kind: ExprKind<String>, start: usize, end: usize) -> Box<ParsedExpr> {
+        Box::new(mk(kind, Span::new(start, end)))
     }
 
     #[test]
     fn test_binop_1() {
         assert_eq!(
-            parse("1 - 2"),
-            ParsedExpr::IntBinOp(
-                Box::new(ParsedExpr::Int(1)),
-                IntBinOp::Sub,
-                Box::new(ParsedExpr::Int(2))
+            parse_str("1 - 2").unwrap(),
+            *e(
+                ExprKind::IntBinOp(
+                    e(ExprKind::Int(1), 0, 1),
+                    IntBinOp::Sub,
+                    e(ExprKind::Int(2), 4, 5)
+                ),
+                0,
+                5
             )
         );
         assert_eq!(
-            parse("1 2"),
-            ParsedExpr::App { fun: Box::new(ParsedExpr::Int(1)), args: vec![ParsedExpr::Int(2)] }
+            parse_str("1 2").unwrap(),
+            *e(
+                ExprKind::App {
+                    fun: e(ExprKind::Int(1), 0, 1),
+                    args: vec![*e(ExprKind::Int(2), 2, 3)]
+                },
+                0,
+                3
+            )
         );
         assert_eq!(
-            parse("- 1 2"),
-            ParsedExpr::Neg(Box::new(ParsedExpr::App {
-                fun: Box::new(ParsedExpr::Int(1)),
-                args: vec![ParsedExpr::Int(2)],
-            }))
+            parse_str("- 1 2").unwrap(),
+            *e(
+                ExprKind::Neg(e(
+                    ExprKind::App {
+                        fun: e(ExprKind::Int(1), 2, 3),
+                        args: vec![*e(ExprKind::Int(2), 4, 5)]
+                    },
+                    2,
+                    5
+                )),
+                0,
+                5
+            )
         );
         assert_eq!(
-            parse("- 1 - 2"),
-            ParsedExpr::IntBinOp(
-                Box::new(ParsedExpr::Neg(Box::new(ParsedExpr::Int(1)))),
-                IntBinOp::Sub,
-                Box::new(ParsedExpr::Int(2))
+            parse_str("- 1 - 2").unwrap(),
+            *e(
+                ExprKind::IntBinOp(
+                    e(ExprKind::Neg(e(ExprKind::Int(1), 2, 3)), 0, 3),
+                    IntBinOp::Sub,
+                    e(ExprKind::Int(2), 6, 7)
+                ),
+                0,
+                7
             )
         );
         assert_eq!(
-            parse("1 - - 2"),
-            ParsedExpr::IntBinOp(
-                Box::new(ParsedExpr::Int(1)),
-                IntBinOp::Sub,
-                Box::new(ParsedExpr::Neg(Box::new(ParsedExpr::Int(2))))
+            parse_str("1 - - 2").unwrap(),
+            *e(
+                ExprKind::IntBinOp(
+                    e(ExprKind::Int(1), 0, 1),
+                    IntBinOp::Sub,
+                    e(ExprKind::Neg(e(ExprKind::Int(2), 6, 7)), 4, 7)
+                ),
+                0,
+                7
             )
         );
     }
+
+    #[test]
+    fn test_int_binop_prec() {
+        assert_eq!(
+            parse_str("1 + 2 * 3 mod 4").unwrap(),
+            *e(
+                ExprKind::IntBinOp(
+                    e(ExprKind::Int(1), 0, 1),
+                    IntBinOp::Add,
+                    e(
+                        ExprKind::IntBinOp(
+                            e(
+                                ExprKind::IntBinOp(
+                                    e(ExprKind::Int(2), 4, 5),
+                                    IntBinOp::Mul,
+                                    e(ExprKind::Int(3), 8, 9)
+                                ),
+                                4,
+                                9
+                            ),
+                            IntBinOp::Mod,
+                            e(ExprKind::Int(4), 14, 15)
+                        ),
+                        4,
+                        15
+                    )
+                ),
+                0,
+                15
+            )
+        );
+    }
+
+    #[test]
+    fn test_path() {
+        assert_eq!(
+            parse_str("Float.of_int x").unwrap(),
+            *e(
+                ExprKind::App {
+                    fun: e(ExprKind::Var("Float.of_int".to_owned()), 0, 12),
+                    args: vec![*e(ExprKind::Var("x".to_owned()), 13, 14)]
+                },
+                0,
+                14
+            )
+        );
+        assert_eq!(
+            parse_str("Array.make 2 x").unwrap(),
+            *e(
+                ExprKind::Array {
+                    len: e(ExprKind::Int(2), 11, 12),
+                    elem: e(ExprKind::Var("x".to_owned()), 13, 14)
+                },
+                0,
+                14
+            )
+        );
+        assert_eq!(
+            parse_str("Float.Foo 1").unwrap_err()[0].span,
+            Span::new(10, 11)
+        );
+    }
+
+    #[test]
+    fn test_let_span() {
+        let expr = parse_str("let x = (1, 2) in\nx").unwrap();
+        assert_eq!(expr.span, Span::new(0, 19));
+        match expr.kind {
+            ExprKind::Let { rhs, body, .. } => {
+                // Parens are not part of the tuple span
+                assert_eq!(rhs.span, Span::new(9, 13));
+                assert_eq!(body.span, Span::new(18, 19));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_let_rec_and() {
+        let expr = parse_str("let rec f x = g x and g y z = f y in f 1").unwrap();
+        match expr.kind {
+            ExprKind::LetRec { defs, .. } => {
+                let names: Vec<(&str, usize)> = defs
+                    .iter()
+                    .map(|def| (def.bndr.as_str(), def.args.len()))
+                    .collect();
+                assert_eq!(names, vec![("f", 1), ("g", 2)]);
+                assert_eq!(defs[1].rhs.span, Span::new(30, 33));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_fun() {
+        assert_eq!(
+            parse_str("f (fun x _ -> x; 1) 2").unwrap(),
+            *e(
+                ExprKind::App {
+                    fun: e(ExprKind::Var("f".to_owned()), 0, 1),
+                    args: vec![
+                        *e(
+                            ExprKind::Fun {
+                                args: vec!["x".to_owned(), "_".to_owned()],
+                                arg_tys: vec![None, None],
+                                body: e(
+                                    ExprKind::Let {
+                                        bndr: "_".to_owned(),
+                                        rhs: e(ExprKind::Var("x".to_owned()), 14, 15),
+                                        body: e(ExprKind::Int(1), 17, 18),
+                                    },
+                                    14,
+                                    18
+                                ),
+                            },
+                            3,
+                            18
+                        ),
+                        *e(ExprKind::Int(2), 20, 21),
+                    ]
+                },
+                0,
+                21
+            )
+        );
+    }
+
+    #[test]
+    fn test_annot() {
+        let t = |kind: TypeExprKind, start: usize, end: usize| TypeExpr {
+            kind,
+            span: Span::new(start, end),
+        };
+        let named = |name: &str, start: usize, end: usize| {
+            t(TypeExprKind::Named(name.to_owned()), start, end)
+        };
+
+        let expr = parse_str("let rec f (x : int) y : int * bool -> float array = (y : unit) in f")
+            .unwrap();
+        let def = match expr.kind {
+            ExprKind::LetRec { mut defs, .. } => defs.pop().unwrap(),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(def.arg_tys, vec![Some(named("int", 15, 18)), None]);
+        assert_eq!(
+            def.ret_ty,
+            Some(t(
+                TypeExprKind::Fun {
+                    args: vec![t(
+                        TypeExprKind::Tuple(vec![named("int", 24, 27), named("bool", 30, 34)]),
+                        24,
+                        34
+                    )],
+                    ret: Box::new(t(
+                        TypeExprKind::Array(Box::new(named("float", 38, 43))),
+                        38,
+                        49
+                    )),
+                },
+                24,
+                49
+            ))
+        );
+        assert_eq!(
+            def.rhs,
+            *e(
+                ExprKind::Annot {
+                    expr: e(ExprKind::Var("y".to_owned()), 53, 54),
+                    ty: named("unit", 57, 61),
+                },
+                52,
+                62
+            )
+        );
+
+        // `let (x : t) = e` annotates `e`
+        let expr = parse_str("let (x : int) = 1 in x").unwrap();
+        match expr.kind {
+            ExprKind::Let { bndr, rhs, .. } => {
+                assert_eq!(bndr, "x");
+                assert_eq!(
+                    *rhs,
+                    *e(
+                        ExprKind::Annot {
+                            expr: e(ExprKind::Int(1), 16, 17),
+                            ty: named("int", 9, 12)
+                        },
+                        16,
+                        17
+                    )
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_and_or() {
+        // `&&` binds tighter than `||`, comparisons bind tighter than both
+        let expr = parse_str("a || b && c = d").unwrap();
+        let (e1, e2) = match expr.kind {
+            ExprKind::Or(e1, e2) => (e1, e2),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(e1.kind, ExprKind::Var("a".to_owned()));
+        assert_eq!(e2.span, Span::new(5, 15));
+        match e2.kind {
+            ExprKind::And(e1, e2) => {
+                assert_eq!(e1.kind, ExprKind::Var("b".to_owned()));
+                assert!(matches!(e2.kind, ExprKind::Cmp(_, Cmp::Equal, _)));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ref() {
+        let var =
+            |name: &str, start: usize, end: usize| e(ExprKind::Var(name.to_owned()), start, end);
+        assert_eq!(
+            parse_str("r := !r + f !x").unwrap(),
+            *e(
+                ExprKind::Assign(
+                    var("r", 0, 1),
+                    e(
+                        ExprKind::IntBinOp(
+                            e(ExprKind::Deref(var("r", 6, 7)), 5, 7),
+                            IntBinOp::Add,
+                            e(
+                                ExprKind::App {
+                                    fun: var("f", 10, 11),
+                                    args: vec![*e(ExprKind::Deref(var("x", 13, 14)), 12, 14)],
+                                },
+                                10,
+                                14
+                            ),
+                        ),
+                        5,
+                        14
+                    ),
+                ),
+                0,
+                14
+            )
+        );
+        assert_eq!(
+            parse_str("ref 1").unwrap(),
+            *e(ExprKind::Ref(e(ExprKind::Int(1), 4, 5)), 0, 5)
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        let expr = parse_str("for i = n downto 1 do f i; g i done; while c do () done").unwrap();
+        let (for_, while_) = match expr.kind {
+            ExprKind::Let { rhs, body, .. } => (rhs, body),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(for_.span, Span::new(0, 35));
+        match for_.kind {
+            ExprKind::For { var, dir, body, .. } => {
+                assert_eq!(var, "i");
+                assert_eq!(dir, ForDir::Downto);
+                assert_eq!(body.span, Span::new(22, 30));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(while_.span, Span::new(37, 55));
+        assert!(matches!(while_.kind, ExprKind::While { .. }));
+
+        // Recovery skips to `done`
+        let errs = parse_str("for i = 1 to 2 do let done; x + ; 1").unwrap_err();
+        assert_eq!(errs.len(), 2);
+    }
+
+    #[test]
+    fn test_variants() {
+        let expr = parse_str(
+            "type t = A | B of int * t and u = | C of t array\n;; \
+             match B (1, A) with B (n, _) -> C | A -> D 1",
+        )
+        .unwrap();
+        let (decls, body) = match expr.kind {
+            ExprKind::TypeDecl { decls, body } => (decls, body),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(decls.len(), 2);
+        assert_eq!(
+            (decls[0].name.as_str(), decls[0].span),
+            ("t", Span::new(5, 6))
+        );
+        match (&decls[0].kind, &decls[1].kind) {
+            (TypeDeclKind::Variant(t_ctors), TypeDeclKind::Variant(u_ctors)) => {
+                assert_eq!(t_ctors[1].name, "B");
+                assert_eq!(t_ctors[1].fields.len(), 2);
+                assert!(matches!(u_ctors[0].fields[0].kind, TypeExprKind::Array(_)));
+            }
+            other => panic!("{:?}", other),
+        }
+        match body.kind {
+            ExprKind::Match { scrut, arms } => {
+                assert_eq!(scrut.span, Span::new(58, 65));
+                match &scrut.kind {
+                    ExprKind::Ctor { ctor, args } => {
+                        assert_eq!((ctor.as_str(), args.len()), ("B", 2));
+                    }
+                    other => panic!("{:?}", other),
+                }
+                assert_eq!(arms[0].pat.span, Span::new(72, 79));
+                match &arms[0].pat.kind {
+                    PatKind::Ctor { ctor, args } => {
+                        assert_eq!(ctor, "B");
+                        assert_eq!(args[0].kind, PatKind::Var("n".to_owned()));
+                        assert_eq!(args[1].kind, PatKind::Wildcard);
+                    }
+                    other => panic!("{:?}", other),
+                }
+                assert_eq!(
+                    arms[1].pat.kind,
+                    PatKind::Ctor { ctor: "A".to_owned(), args: vec![] }
+                );
+                assert_eq!(arms[1].rhs.span, Span::new(93, 96));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_records() {
+        let expr = parse_str(
+            "type p = { x : int; mutable y : p array; }\n;; p.y.(0).y <- { x = 1; y = a }; r.x.y",
+        )
+        .unwrap();
+        let (decls, body) = match expr.kind {
+            ExprKind::TypeDecl { decls, body } => (decls, body),
+            other => panic!("{:?}", other),
+        };
+        match &decls[0].kind {
+            TypeDeclKind::Record(fields) => {
+                assert_eq!(fields.len(), 2);
+                assert!(!fields[0].mutable);
+                assert!(fields[1].mutable);
+                assert_eq!(
+                    (fields[1].name.as_str(), fields[1].span),
+                    ("y", Span::new(28, 29))
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+        let (set, get) = match body.kind {
+            ExprKind::Let { rhs, body, .. } => (rhs, body),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(set.span, Span::new(46, 75));
+        match set.kind {
+            ExprKind::SetField { expr, field, value } => {
+                assert_eq!(field, "y");
+                assert_eq!(expr.span, Span::new(46, 53));
+                assert!(matches!(expr.kind, ExprKind::Get(_, _)));
+                assert_eq!(value.span, Span::new(59, 75));
+                match value.kind {
+                    ExprKind::Record(fields) => {
+                        assert_eq!(fields[1].field, "y");
+                        assert_eq!(fields[1].expr.kind, ExprKind::Var("a".to_owned()));
+                    }
+                    other => panic!("{:?}", other),
+                }
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(get.span, Span::new(77, 82));
+        match get.kind {
+            ExprKind::GetField { expr, field } => {
+                assert_eq!(field, "y");
+                assert_eq!(expr.span, Span::new(77, 80));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_match() {
+        let expr =
+            parse_str("match x with | (0, _) -> 1 | n, -2 when n > 0 -> 2; 3 | _ -> 4").unwrap();
+        assert_eq!(expr.span, Span::new(0, 62));
+        match expr.kind {
+            ExprKind::Match { arms, .. } => {
+                assert_eq!(arms.len(), 3);
+                assert_eq!(arms[0].pat.span, Span::new(16, 20));
+                match &arms[1].pat.kind {
+                    PatKind::Tuple(pats) => {
+                        assert_eq!(pats[0].kind, PatKind::Var("n".to_owned()));
+                        assert_eq!(pats[1].kind, PatKind::Int(-2));
+                    }
+                    other => panic!("{:?}", other),
+                }
+                assert!(arms[1].guard.is_some());
+                // Arm bodies are sequences
+                assert_eq!(arms[1].rhs.span, Span::new(49, 53));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_top_level() {
+        // Last expression is the body of the innermost `let`
+        let expr = parse_str("let x = 1\nlet rec f y = y;; f x").unwrap();
+        match expr.kind {
+            ExprKind::Let { bndr, body, .. } => {
+                assert_eq!(bndr, "x");
+                match body.kind {
+                    ExprKind::LetRec { defs, body } => {
+                        assert_eq!(defs[0].bndr, "f");
+                        assert!(matches!(body.kind, ExprKind::App { .. }));
+                    }
+                    other => panic!("{:?}", other),
+                }
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // Ends with `()` when the last item is a definition
+        let expr = parse_str("f 1;;\nlet () = g 2").unwrap();
+        assert_eq!(expr.span, Span::new(0, 18));
+        match expr.kind {
+            ExprKind::Let { bndr, rhs, body } => {
+                assert_eq!(bndr, "_");
+                assert!(matches!(rhs.kind, ExprKind::App { .. }));
+                match body.kind {
+                    ExprKind::Let { bndr, body, .. } => {
+                        assert_eq!(bndr, "_");
+                        assert_eq!(*body, *e(ExprKind::Unit, 18, 18));
+                    }
+                    other => panic!("{:?}", other),
+                }
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // Expressions after definitions need `;;`
+        let errs = parse_str("let x = 1\nif x then 1 else 2").unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].span, Span::new(10, 12));
+    }
+
+    #[test]
+    fn test_error_span() {
+        assert_eq!(
+            parse_str("f (let x = 1 x)"),
+            Err(vec![ParseErr {
+                span: Span::new(14, 15),
+                found: Some(Token::RParen),
+                expected: vec![
+                    Expected::Token(Token::Dot),
+                    Expected::Expr,
+                    Expected::Operator,
+                    Expected::Token(Token::Comma),
+                    Expected::Token(Token::Semicolon),
+                    Expected::Token(Token::In),
+                ],
+            }])
+        );
+        assert_eq!(
+            parse_str("f (1 + )"),
+            Err(vec![ParseErr {
+                span: Span::new(7, 8),
+                found: Some(Token::RParen),
+                expected: vec![Expected::Expr],
+            }])
+        );
+    }
+
+    #[test]
+    fn test_external() {
+        let expr =
+            parse_str("external pow : float -> float -> float = \"pow\"\n;; pow 2.0 3.0").unwrap();
+        match expr.kind {
+            ExprKind::External { bndr, ty, symbol, body } => {
+                assert_eq!(bndr, "pow");
+                assert_eq!(symbol, "pow");
+                assert_eq!(ty.span, Span::new(15, 38));
+                assert!(matches!(ty.kind, TypeExprKind::Fun { ref args, .. } if args.len() == 2));
+                assert!(matches!(body.kind, ExprKind::App { .. }));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        assert_eq!(
+            parse_str("external f : int -> int = f"),
+            Err(vec![ParseErr {
+                span: Span::new(26, 27),
+                found: Some(Token::Id("f".to_owned())),
+                expected: vec![Expected::String],
+            }])
+        );
+    }
+
+    #[test]
+    fn test_lists() {
+        // `::` is right associative, and binds weaker than `+`
+        let expr = parse_str("1 + 2 :: x :: []").unwrap();
+        match expr.kind {
+            ExprKind::Cons(hd, tl) => {
+                assert!(matches!(hd.kind, ExprKind::IntBinOp(_, IntBinOp::Add, _)));
+                assert!(matches!(
+                    tl.kind,
+                    ExprKind::Cons(_, box ParsedExpr { kind: ExprKind::Nil, .. })
+                ));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let expr = parse_str("[1; 2;]").unwrap();
+        assert_eq!(expr.span, Span::new(0, 7));
+        match expr.kind {
+            ExprKind::Cons(box ParsedExpr { kind: ExprKind::Int(1), .. }, tl) => {
+                assert_eq!(tl.span, Span::new(4, 5));
+                assert!(matches!(
+                    tl.kind,
+                    ExprKind::Cons(_, box ParsedExpr { kind: ExprKind::Nil, .. })
+                ));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let expr = parse_str("match l with [] -> 0 | [x, y] -> 1 | x :: _, y -> 2").unwrap();
+        match expr.kind {
+            ExprKind::Match { arms, .. } => {
+                assert_eq!(arms[0].pat.kind, PatKind::Nil);
+                assert!(matches!(
+                    &arms[1].pat.kind,
+                    PatKind::Cons(
+                        box Pat { kind: PatKind::Tuple(_), .. },
+                        box Pat { kind: PatKind::Nil, .. },
+                    )
+                ));
+                assert!(matches!(
+                    &arms[2].pat.kind,
+                    PatKind::Tuple(pats) if matches!(pats[0].kind, PatKind::Cons(_, _))
+                ));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let expr = parse_str("([] : int list list)").unwrap();
+        match expr.kind {
+            ExprKind::Annot { ty, .. } => {
+                assert!(matches!(
+                    ty.kind,
+                    TypeExprKind::List(box TypeExpr { kind: TypeExprKind::List(_), .. })
+                ));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_aborts() {
+        assert_eq!(
+            parse_str("failwith \"no\"").unwrap(),
+            *e(
+                ExprKind::Abort(
+                    AbortKind::Failwith,
+                    e(ExprKind::String(b"no".to_vec()), 9, 13)
+                ),
+                0,
+                13
+            )
+        );
+        assert_eq!(
+            parse_str("assert (x > 0)").unwrap().kind,
+            ExprKind::Abort(
+                AbortKind::Assert,
+                e(
+                    ExprKind::Cmp(
+                        e(ExprKind::Var("x".to_owned()), 8, 9),
+                        Cmp::GreaterThan,
+                        e(ExprKind::Int(0), 12, 13)
+                    ),
+                    8,
+                    13
+                )
+            )
+        );
+        assert!(matches!(
+            parse_str("exit 1").unwrap().kind,
+            ExprKind::Abort(AbortKind::Exit, _)
+        ));
+        // Not applied, or applied to more than one argument: a function application
+        assert!(matches!(
+            parse_str("f exit").unwrap().kind,
+            ExprKind::App { .. }
+        ));
+        assert!(matches!(
+            parse_str("failwith x y").unwrap().kind,
+            ExprKind::App { .. }
+        ));
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let expr =
+            parse_str("exception E of int;; try raise (E 1) with E n -> n | _ -> 0").unwrap();
+        assert_eq!(expr.span, Span::new(0, 59));
+        let (decl, body) = match expr.kind {
+            ExprKind::Exception { decl, body } => (decl, body),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!((decl.name.as_str(), decl.span), ("E", Span::new(10, 11)));
+        assert_eq!(decl.fields.len(), 1);
+        assert_eq!(body.span, Span::new(21, 59));
+        match body.kind {
+            ExprKind::Try { body, arms } => {
+                assert!(matches!(body.kind, ExprKind::Abort(AbortKind::Raise, _)));
+                assert_eq!(arms.len(), 2);
+                assert_eq!(arms[0].pat.span, Span::new(42, 45));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let tokens =
+            tokenize("let x = (1 + ) in\nif x then else 2;\nf (let y = 1 in 2) ; g (1 2").unwrap();
+        let (expr, errs) = parse(&tokens);
+        let err_spans: Vec<Span> = errs.iter().map(|err| err.span).collect();
+        assert_eq!(
+            err_spans,
+            vec![Span::new(13, 14), Span::new(28, 32), Span::new(63, 63)]
+        );
+        // Erroneous parts are replaced with `()`
+        match expr.kind {
+            ExprKind::Let { rhs, .. } => {
+                assert_eq!(rhs.kind, ExprKind::Unit);
+                assert_eq!(rhs.span, Span::new(9, 12));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
 }
@@ -19,6 +19,7 @@ unsafe impl GlobalAlloc for AllocCounter {
     }
 }
 
+#[allow(dead_code)]
 pub fn reset_allocated() {
     ALLOCATED.store(0, Ordering::SeqCst);
 }
@@ -0,0 +1,64 @@
+// Library functions implemented in MinCaml or declared with `external`. The definitions are added
+// to every program as `let`s and `external`s, with names qualified by the module name, e.g.
+// `List.length`. Functions are polymorphic, so the ones not used by the program are removed by
+// monomorphization. Definitions of the `Stdlib` module are not qualified, like in OCaml.
+
+use crate::ast::{ExprKind, ParsedExpr};
+use crate::lexer::tokenize;
+use crate::parser::parse;
+
+static STDLIB: &str = include_str!("prelude/stdlib.ml");
+static FLOAT: &str = include_str!("prelude/float.ml");
+static LIST: &str = include_str!("prelude/list.ml");
+
+/// Add the library definitions to a program
+pub fn add_prelude(pgm: ParsedExpr) -> ParsedExpr {
+    let pgm = add_module("List", LIST, pgm);
+    let pgm = add_module("Float", FLOAT, pgm);
+    add_module("Stdlib", STDLIB, pgm)
+}
+
+// Wrap the program with the definitions in the source of a module. The source should only have
+// `external`s and `let` items without `rec`, as recursive references to the definitions would not
+// be qualified. Recursive functions are implemented with local helpers.
+fn add_module(module: &str, src: &str, pgm: ParsedExpr) -> ParsedExpr {
+    let (defs, errs) = parse(&tokenize(src).unwrap());
+    assert!(errs.is_empty(), "{:?}", errs);
+    qualify(module, defs, pgm)
+}
+
+// The definitions get the span of the program, so that errors about the type of the whole program
+// are reported in the program
+fn qualify(module: &str, defs: ParsedExpr, pgm: ParsedExpr) -> ParsedExpr {
+    let qualified = |bndr: String| {
+        if module == "Stdlib" {
+            bndr
+        } else {
+            format!("{}.{}", module, bndr)
+        }
+    };
+    let span = pgm.span;
+    match defs.kind {
+        ExprKind::Let { bndr, rhs, body } => {
+            let body = qualify(module, *body, pgm);
+            ParsedExpr {
+                kind: ExprKind::Let { bndr: qualified(bndr), rhs, body: Box::new(body) },
+                span,
+            }
+        }
+        ExprKind::External { bndr, ty, symbol, body } => {
+            let body = qualify(module, *body, pgm);
+            ParsedExpr {
+                kind: ExprKind::External {
+                    bndr: qualified(bndr),
+                    ty,
+                    symbol,
+                    body: Box::new(body),
+                },
+                span,
+            }
+        }
+        ExprKind::Unit => pgm,
+        other => panic!("Unexpected definition in module {}: {:?}", module, other),
+    }
+}
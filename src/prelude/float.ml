@@ -0,0 +1,9 @@
+external tan : float -> float = "tan"
+external atan : float -> float = "atan"
+external exp : float -> float = "exp"
+external log : float -> float = "log"
+external floor : float -> float = "floor"
+external ceil : float -> float = "ceil"
+(* Rounds half away from zero *)
+external round : float -> float = "round"
+external trunc : float -> float = "trunc"
@@ -0,0 +1,11 @@
+let length = fun l ->
+  let rec go n l = match l with [] -> n | _ :: l -> go (n + 1) l in
+  go 0 l
+
+let rev = fun l ->
+  let rec go acc l = match l with [] -> acc | x :: l -> go (x :: acc) l in
+  go [] l
+
+let iter = fun f l ->
+  let rec go l = match l with [] -> () | x :: l -> f x; go l in
+  go l
@@ -0,0 +1,18 @@
+external tan : float -> float = "tan"
+external atan : float -> float = "atan"
+external exp : float -> float = "exp"
+external log : float -> float = "log"
+external floor : float -> float = "floor"
+external ceil : float -> float = "ceil"
+
+(* Functions of the original MinCaml library *)
+external fabs : float -> float = "fabs"
+external fneg : float -> float = "mc_fneg"
+external fsqr : float -> float = "mc_fsqr"
+external fhalf : float -> float = "mc_fhalf"
+external fless : float -> float -> bool = "mc_fless"
+external fequal : float -> float -> bool = "mc_fequal"
+external fispos : float -> bool = "mc_fispos"
+external fisneg : float -> bool = "mc_fisneg"
+external fiszero : float -> bool = "mc_fiszero"
+external xor : bool -> bool -> bool = "mc_xor"
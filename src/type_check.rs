@@ -1,11 +1,17 @@
-use fxhash::FxHashMap;
+use fxhash::{FxHashMap, FxHashSet};
 use std::rc::Rc;
 
-use crate::ast::Expr;
+use crate::ast::{
+    AbortKind, Arm, CtorDecl, Expr, ExprKind, FieldExpr, FunDef, Pat, PatKind, TypeDecl,
+    TypeDeclKind, TypeExpr, TypeExprKind,
+};
 use crate::ctx::{Ctx, VarId};
+use crate::diagnostics::Diagnostic;
+use crate::exhaustiveness::{check_match, MatchCheck, Witness};
 use crate::locals::Locals;
+use crate::span::Span;
 use crate::utils::take;
-use crate::var::Uniq;
+use crate::var::{CompilerPhase, Uniq};
 
 pub type TyVar = Uniq;
 
@@ -17,12 +23,119 @@ pub enum Type {
     Bool,
     Int,
     Float,
+    String,
+    Char,
     Fun { args: Vec<Type>, ret: Box<Type> },
     Tuple(Vec<Type>),
     Array(Box<Type>),
+    List(Box<Type>),
+    Ref(Box<Type>),
+    // Variant type declared with `type`, identified by its name
+    Variant(Rc<str>),
+    // Record type declared with `type`, identified by its name
+    Record(Rc<str>),
     Var(TyVar),
 }
 
+/// A variant type declared with `type`
+#[derive(Debug, PartialEq)]
+pub struct VariantDecl {
+    pub name: Rc<str>,
+    pub ctors: Vec<VariantCtor>,
+}
+
+/// Name of the variant type of exceptions. The type is declared without constructors, `exception`
+/// declarations add constructors to it.
+pub const EXN_TYPE: &str = "exn";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantCtor {
+    pub name: Rc<str>,
+    pub fields: Vec<Type>,
+}
+
+/// A record type declared with `type`. Records are represented as tuples of the fields, in the
+/// order of the declaration.
+#[derive(Debug, PartialEq)]
+pub struct RecordDecl {
+    pub name: Rc<str>,
+    pub fields: Vec<RecordField>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RecordField {
+    pub name: Rc<str>,
+    pub mutable: bool,
+    pub ty: Type,
+}
+
+/// Runtime representation of a constructor. Constructors without fields are immediate integers
+/// `0, 1, ...`. Constructors with fields are heap blocks `[tag, fields...]`, with tags `0, 1, ...`
+/// numbered separately from the constant constructors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CtorRep {
+    Const(i64),
+    Block(i64),
+}
+
+impl VariantDecl {
+    pub fn ctor_rep(&self, ctor_idx: usize) -> CtorRep {
+        let is_const = self.ctors[ctor_idx].fields.is_empty();
+        let n = self.ctors[..ctor_idx]
+            .iter()
+            .filter(|ctor| ctor.fields.is_empty() == is_const)
+            .count() as i64;
+        if is_const {
+            CtorRep::Const(n)
+        } else {
+            CtorRep::Block(n)
+        }
+    }
+
+    /// Number of constructors without fields
+    pub fn n_consts(&self) -> usize {
+        self.ctors
+            .iter()
+            .filter(|ctor| ctor.fields.is_empty())
+            .count()
+    }
+
+    /// Number of constructors with fields
+    pub fn n_blocks(&self) -> usize {
+        self.ctors.len() - self.n_consts()
+    }
+
+    /// Whether more constructors can be declared for the type. Matches on these types are never
+    /// exhaustive without a wildcard.
+    pub fn is_extensible(&self) -> bool {
+        &*self.name == EXN_TYPE
+    }
+}
+
+impl Type {
+    /// Type of the value returned by a function of this type when applied to `n_args` arguments.
+    /// For example `a -> b -> c` applied to one argument returns `b -> c`, regardless of the number
+    /// of arguments the function takes.
+    pub fn apply(&self, mut n_args: usize) -> Type {
+        let mut ty = self;
+        loop {
+            match ty {
+                Type::Fun { args, ret } => {
+                    if n_args < args.len() {
+                        return Type::Fun { args: args[n_args..].to_vec(), ret: ret.clone() };
+                    }
+                    n_args -= args.len();
+                    if n_args == 0 {
+                        return (**ret).clone();
+                    }
+                    ty = ret;
+                }
+                other => panic!("Non-function type in function position: {:?}", other),
+            }
+        }
+    }
+}
+
 /*
 impl Type {
     pub fn is_array(&self) -> bool {
@@ -36,58 +149,295 @@ impl Type {
 
 #[derive(Debug)]
 pub enum TypeErr {
-    /// Can't unify these two types
-    UnifyError(Type, Type),
+    /// Can't unify these two types. First type is the expected one, second is the type found.
+    UnifyError(Type, Type, Span),
     /// Occurs check failed
-    InfiniteType(Type, Type),
+    InfiniteType(Type, Type, Span),
     /// Unbound variable
-    UnboundVar(VarId),
+    UnboundVar(VarId, Span),
+    /// Unknown type name in an annotation
+    UnknownType(String, Span),
+    /// Unknown constructor
+    UnknownCtor(String, Span),
+    /// Constructor applied to the wrong number of arguments
+    CtorArity { ctor: String, expected: usize, found: usize, span: Span },
+    /// A type with the name is already declared
+    DuplicateType(String, Span),
+    /// A constructor with the name is already declared
+    DuplicateCtor(String, Span),
+    /// Unknown record field
+    UnknownField(String, Span),
+    /// A field is declared or initialized more than once
+    DuplicateField(String, Span),
+    /// Record expression doesn't initialize a field
+    MissingField(String, Span),
+    /// Update of a field not declared `mutable`
+    ImmutableField(String, Span),
+    /// Type of an `external` is not a function type
+    ExternalNotFunction(Span),
+    /// Comparison of values that would be compared physically: variants, records, lists and refs,
+    /// also in tuples and arrays
+    InvalidComparison(Type, Span),
 }
 
-type SubstEnv = FxHashMap<TyVar, Type>;
+impl TypeErr {
+    fn norm(self, substs: &SubstEnv) -> TypeErr {
+        match self {
+            TypeErr::UnifyError(ty1, ty2, span) => {
+                TypeErr::UnifyError(norm_ty(substs, ty1), norm_ty(substs, ty2), span)
+            }
+            TypeErr::InfiniteType(ty1, ty2, span) => {
+                TypeErr::InfiniteType(norm_ty(substs, ty1), norm_ty(substs, ty2), span)
+            }
+            TypeErr::InvalidComparison(ty, span) => {
+                TypeErr::InvalidComparison(norm_ty(substs, ty), span)
+            }
+            TypeErr::UnboundVar(_, _)
+            | TypeErr::UnknownType(_, _)
+            | TypeErr::UnknownCtor(_, _)
+            | TypeErr::CtorArity { .. }
+            | TypeErr::DuplicateType(_, _)
+            | TypeErr::DuplicateCtor(_, _)
+            | TypeErr::UnknownField(_, _)
+            | TypeErr::DuplicateField(_, _)
+            | TypeErr::MissingField(_, _)
+            | TypeErr::ImmutableField(_, _)
+            | TypeErr::ExternalNotFunction(_) => self,
+        }
+    }
+
+    pub fn to_diagnostic(&self, ctx: &Ctx) -> Diagnostic {
+        // Shared so that type variables get the same names in both types
+        let mut names = TyVarNames::default();
+        match self {
+            TypeErr::UnifyError(expected, found, span) => {
+                let mut label = String::new();
+                label.push_str("expected `");
+                expected.pp_with(&mut names, &mut label).unwrap();
+                label.push_str("`, found `");
+                found.pp_with(&mut names, &mut label).unwrap();
+                label.push('`');
+                Diagnostic::error("mismatched types", *span).with_label(label)
+            }
+            TypeErr::InfiniteType(ty1, ty2, span) => {
+                let mut msg = String::new();
+                msg.push_str("cannot construct infinite type `");
+                ty1.pp_with(&mut names, &mut msg).unwrap();
+                msg.push_str(" = ");
+                ty2.pp_with(&mut names, &mut msg).unwrap();
+                msg.push('`');
+                Diagnostic::error(msg, *span)
+            }
+            TypeErr::UnboundVar(var, span) => {
+                Diagnostic::error(format!("unbound variable `{}`", ctx.var_name(*var)), *span)
+                    .with_label("not found in this scope")
+            }
+            TypeErr::UnknownType(name, span) => {
+                Diagnostic::error(format!("unknown type `{}`", name), *span)
+                    .with_label("not found in this scope")
+            }
+            TypeErr::UnknownCtor(name, span) => {
+                Diagnostic::error(format!("unknown constructor `{}`", name), *span)
+                    .with_label("not found in this scope")
+            }
+            TypeErr::CtorArity { ctor, expected, found, span } => Diagnostic::error(
+                format!(
+                    "constructor `{}` expects {} argument{}",
+                    ctor,
+                    expected,
+                    if *expected == 1 { "" } else { "s" }
+                ),
+                *span,
+            )
+            .with_label(format!(
+                "found {} argument{}",
+                found,
+                if *found == 1 { "" } else { "s" }
+            )),
+            TypeErr::DuplicateType(name, span) => {
+                Diagnostic::error(format!("type `{}` is declared multiple times", name), *span)
+            }
+            TypeErr::DuplicateCtor(name, span) => Diagnostic::error(
+                format!("constructor `{}` is declared multiple times", name),
+                *span,
+            ),
+            TypeErr::UnknownField(name, span) => {
+                Diagnostic::error(format!("unknown record field `{}`", name), *span)
+                    .with_label("not found in this scope")
+            }
+            TypeErr::DuplicateField(name, span) => {
+                Diagnostic::error(format!("field `{}` is given multiple times", name), *span)
+            }
+            TypeErr::MissingField(name, span) => {
+                Diagnostic::error(format!("missing record field `{}`", name), *span)
+            }
+            TypeErr::ImmutableField(name, span) => {
+                Diagnostic::error(format!("field `{}` is not mutable", name), *span)
+                    .with_note("declare the field with `mutable` to allow updates")
+            }
+            TypeErr::ExternalNotFunction(span) => {
+                Diagnostic::error("external declaration is not a function", *span)
+                    .with_label("expected a function type")
+            }
+            TypeErr::InvalidComparison(ty, span) => {
+                let mut msg = String::new();
+                msg.push_str("cannot compare values of type `");
+                ty.pp_with(&mut names, &mut msg).unwrap();
+                msg.push('`');
+                Diagnostic::error(msg, *span)
+                    .with_note("variants, records, lists and refs can't be compared")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TypeWarning {
+    /// `match` doesn't handle the value
+    NonExhaustiveMatch { span: Span, missing: String, guarded: bool },
+    /// `match` arm pattern is already covered by earlier arms
+    UnusedArm(Span),
+}
+
+impl TypeWarning {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            TypeWarning::NonExhaustiveMatch { span, missing, guarded } => {
+                let diag = Diagnostic::warning("non-exhaustive match", *span)
+                    .with_label(format!("pattern `{}` not covered", missing));
+                if *guarded {
+                    diag.with_note("arms with `when` guards are not considered exhaustive")
+                } else {
+                    diag
+                }
+            }
+            TypeWarning::UnusedArm(span) => Diagnostic::warning("unused match arm", *span)
+                .with_label("this pattern is matched by earlier arms"),
+        }
+    }
+}
+
+pub type SubstEnv = FxHashMap<TyVar, Type>;
+
+/// A use of a polymorphic variable, with the types that the type variables of the variable are
+/// instantiated with. The types are in the same order as the type variables in
+/// `Ctx::generic_vars`.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub binder: VarId,
+    pub tys: Vec<Type>,
+}
 
 #[derive(Debug, Clone)]
 struct Binder {
     binder: VarId,
     ty: Type,
+    // Type variables of a polymorphic binder. Instantiated with fresh type variables in every use.
+    generics: Vec<TyVar>,
+}
+
+impl Binder {
+    fn new(binder: VarId, ty: Type) -> Binder {
+        Binder { binder, ty, generics: vec![] }
+    }
 }
 
 type Scope = Locals<Rc<str>, Binder>;
 
-pub fn type_check_pgm(ctx: &mut Ctx, expr: &mut Expr) -> Result<(), TypeErr> {
+/// Type check a program. On success returns the warnings.
+pub fn type_check_pgm(ctx: &mut Ctx, expr: &mut Expr) -> Result<Vec<TypeWarning>, TypeErr> {
     let mut global_scope: FxHashMap<Rc<str>, Binder> = Default::default();
 
     for (var_id, ty_id) in ctx.builtins() {
         let var = ctx.get_var(*var_id);
         let var_name = var.name();
         let ty = ctx.get_type(*ty_id);
-        global_scope.insert(var_name, Binder { binder: *var_id, ty: (&*ty).clone() });
+        global_scope.insert(var_name, Binder::new(*var_id, (*ty).clone()));
     }
 
     let mut scope: Scope = Locals::new(global_scope);
     let mut subst_env: SubstEnv = Default::default();
     let mut ty_env: TypeEnv = Default::default();
-    let ty = type_check(ctx, &mut ty_env, &mut subst_env, &mut scope, expr)?;
-    unify(&mut subst_env, &Type::Unit, &ty)?;
+    let mut warnings: Vec<TypeWarning> = vec![];
+    let ret = type_check(
+        ctx,
+        &mut ty_env,
+        &mut subst_env,
+        &mut scope,
+        &mut warnings,
+        expr,
+    )
+    .and_then(|ty| unify(&mut subst_env, &Type::Unit, &ty, expr.span));
+    if let Err(err) = ret {
+        return Err(err.norm(&subst_env));
+    }
+
+    for (ty, span) in ctx.take_comparisons() {
+        let ty = norm_ty(&subst_env, ty);
+        if compares_physically(&ty) {
+            return Err(TypeErr::InvalidComparison(ty, span));
+        }
+    }
+
+    // Type variables not constrained by the program, e.g. the type of `exit 1` in `exit 1; e`,
+    // are defaulted to `unit`. Values of these types are never used. Type variables of
+    // polymorphic binders are specialized by `mono`.
+    let generics: FxHashSet<TyVar> = ctx.all_generic_vars().collect();
+    let mut unconstrained: Vec<TyVar> = vec![];
+    for ty in ty_env.values() {
+        ty_vars(&subst_env, ty, &mut unconstrained);
+    }
+    for Instance { tys, .. } in ctx.instances_mut() {
+        for ty in tys.iter() {
+            ty_vars(&subst_env, ty, &mut unconstrained);
+        }
+    }
+    for ty_var in unconstrained {
+        if !generics.contains(&ty_var) {
+            subst_env.insert(ty_var, Type::Unit);
+        }
+    }
 
     for ty in ty_env.values_mut() {
         take(ty, |ty| norm_ty(&subst_env, ty));
     }
 
+    for Instance { tys, .. } in ctx.instances_mut() {
+        for ty in tys {
+            take(ty, |ty| norm_ty(&subst_env, ty));
+        }
+    }
+
     ctx.extend_type_env(ty_env.into_iter());
 
-    Ok(())
+    Ok(warnings)
 }
 
-fn norm_ty(substs: &SubstEnv, ty: Type) -> Type {
+// Values are compared by their representations, which would compare variants, records, lists and
+// refs physically, also when they're in tuples and arrays (which are compared physically, like in
+// the original MinCaml). Type variables are compared physically as well, see `mono`.
+fn compares_physically(ty: &Type) -> bool {
     match ty {
-        Type::Unit | Type::Bool | Type::Int | Type::Float => ty,
+        Type::Variant(_) | Type::Record(_) | Type::List(_) | Type::Ref(_) => true,
+        Type::Tuple(tys) => tys.iter().any(compares_physically),
+        Type::Array(ty) => compares_physically(ty),
+        _ => false,
+    }
+}
+
+/// Apply a substitution to a type
+pub fn norm_ty(substs: &SubstEnv, ty: Type) -> Type {
+    match ty {
+        Type::Unit | Type::Bool | Type::Int | Type::Float | Type::String | Type::Char => ty,
         Type::Fun { args, ret } => Type::Fun {
             args: args.into_iter().map(|ty| norm_ty(substs, ty)).collect(),
             ret: Box::new(norm_ty(substs, *ret)),
         },
         Type::Tuple(args) => Type::Tuple(args.into_iter().map(|ty| norm_ty(substs, ty)).collect()),
         Type::Array(ty) => Type::Array(Box::new(norm_ty(substs, *ty))),
+        Type::List(ty) => Type::List(Box::new(norm_ty(substs, *ty))),
+        Type::Ref(ty) => Type::Ref(Box::new(norm_ty(substs, *ty))),
+        Type::Variant(_) | Type::Record(_) => ty,
         Type::Var(_) => {
             let deref = deref_ty(substs, &ty).clone();
             if deref == ty {
@@ -119,157 +469,334 @@ fn deref_ty<'a>(subst: &'a SubstEnv, mut ty: &'a Type) -> &'a Type {
 
 fn occurs_check(subst: &SubstEnv, var: TyVar, ty: &Type) -> bool {
     match deref_ty(subst, ty) {
-        Type::Unit | Type::Bool | Type::Int | Type::Float => false,
+        Type::Unit
+        | Type::Bool
+        | Type::Int
+        | Type::Float
+        | Type::String
+        | Type::Char
+        | Type::Variant(_)
+        | Type::Record(_) => false,
         Type::Fun { args, ret } => {
             args.iter().any(|ty| occurs_check(subst, var, ty)) || occurs_check(subst, var, ret)
         }
         Type::Tuple(args) => args.iter().any(|ty| occurs_check(subst, var, ty)),
-        Type::Array(ty) => occurs_check(subst, var, ty),
+        Type::Array(ty) | Type::List(ty) | Type::Ref(ty) => occurs_check(subst, var, ty),
         Type::Var(var_) => var == *var_,
     }
 }
 
 fn type_check(
     ctx: &mut Ctx, ty_env: &mut TypeEnv, subst_env: &mut SubstEnv, scope: &mut Scope,
-    expr: &mut Expr,
+    warnings: &mut Vec<TypeWarning>, expr: &mut Expr,
 ) -> Result<Type, TypeErr> {
-    match expr {
-        Expr::Unit => Ok(Type::Unit),
-        Expr::Bool(_) => Ok(Type::Bool),
-        Expr::Int(_) => Ok(Type::Int),
-        Expr::Float(_) => Ok(Type::Float),
-
-        Expr::Not(e) => {
-            let e_ty = type_check(ctx, ty_env, subst_env, scope, e)?;
-            unify(subst_env, &Type::Bool, &e_ty)?;
+    let span = expr.span;
+    match &mut expr.kind {
+        ExprKind::Unit => Ok(Type::Unit),
+        ExprKind::Bool(_) => Ok(Type::Bool),
+        ExprKind::Int(_) => Ok(Type::Int),
+        ExprKind::Float(_) => Ok(Type::Float),
+        ExprKind::String(_) => Ok(Type::String),
+        ExprKind::Char(_) => Ok(Type::Char),
+
+        ExprKind::Not(e) => {
+            let e_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e)?;
+            unify(subst_env, &Type::Bool, &e_ty, e.span)?;
             Ok(Type::Bool)
         }
 
-        Expr::Neg(e) => {
-            let e_ty = type_check(ctx, ty_env, subst_env, scope, e)?;
-            unify(subst_env, &Type::Int, &e_ty)?;
+        ExprKind::Neg(e) => {
+            let e_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e)?;
+            unify(subst_env, &Type::Int, &e_ty, e.span)?;
             Ok(Type::Int)
         }
 
-        Expr::IntBinOp(e1, _, e2) => {
-            let e1_ty = type_check(ctx, ty_env, subst_env, scope, e1)?;
-            let e2_ty = type_check(ctx, ty_env, subst_env, scope, e2)?;
-            unify(subst_env, &Type::Int, &e1_ty)?;
-            unify(subst_env, &Type::Int, &e2_ty)?;
+        ExprKind::IntBinOp(e1, _, e2) => {
+            let e1_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e1)?;
+            let e2_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e2)?;
+            unify(subst_env, &Type::Int, &e1_ty, e1.span)?;
+            unify(subst_env, &Type::Int, &e2_ty, e2.span)?;
             Ok(Type::Int)
         }
 
-        Expr::FNeg(e) => {
-            let e_ty = type_check(ctx, ty_env, subst_env, scope, e)?;
-            unify(subst_env, &Type::Float, &e_ty)?;
+        ExprKind::FNeg(e) => {
+            let e_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e)?;
+            unify(subst_env, &Type::Float, &e_ty, e.span)?;
             Ok(Type::Float)
         }
 
-        Expr::FloatBinOp(e1, _, e2) => {
-            let e1_ty = type_check(ctx, ty_env, subst_env, scope, e1)?;
-            let e2_ty = type_check(ctx, ty_env, subst_env, scope, e2)?;
-            unify(subst_env, &Type::Float, &e1_ty)?;
-            unify(subst_env, &Type::Float, &e2_ty)?;
+        ExprKind::FloatBinOp(e1, _, e2) => {
+            let e1_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e1)?;
+            let e2_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e2)?;
+            unify(subst_env, &Type::Float, &e1_ty, e1.span)?;
+            unify(subst_env, &Type::Float, &e2_ty, e2.span)?;
             Ok(Type::Float)
         }
 
-        Expr::Cmp(e1, _, e2) => {
-            let e1_ty = type_check(ctx, ty_env, subst_env, scope, e1)?;
-            let e2_ty = type_check(ctx, ty_env, subst_env, scope, e2)?;
-            unify(subst_env, &e1_ty, &e2_ty)?;
+        ExprKind::Cmp(e1, _, e2) => {
+            let e1_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e1)?;
+            let e2_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e2)?;
+            unify(subst_env, &e1_ty, &e2_ty, e2.span)?;
+            ctx.add_comparison(e1_ty, expr.span);
             Ok(Type::Bool)
         }
 
-        Expr::If(e1, e2, e3) => {
-            let e1_ty = type_check(ctx, ty_env, subst_env, scope, e1)?;
-            let e2_ty = type_check(ctx, ty_env, subst_env, scope, e2)?;
-            let e3_ty = type_check(ctx, ty_env, subst_env, scope, e3)?;
-            unify(subst_env, &e1_ty, &Type::Bool)?;
-            unify(subst_env, &e2_ty, &e3_ty)?;
+        ExprKind::And(e1, e2) | ExprKind::Or(e1, e2) => {
+            let e1_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e1)?;
+            let e2_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e2)?;
+            unify(subst_env, &Type::Bool, &e1_ty, e1.span)?;
+            unify(subst_env, &Type::Bool, &e2_ty, e2.span)?;
+            Ok(Type::Bool)
+        }
+
+        ExprKind::If(e1, e2, e3) => {
+            let e1_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e1)?;
+            let e2_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e2)?;
+            let e3_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e3)?;
+            unify(subst_env, &Type::Bool, &e1_ty, e1.span)?;
+            unify(subst_env, &e2_ty, &e3_ty, e3.span)?;
             Ok(e2_ty)
         }
 
-        Expr::Let { bndr, ref mut rhs, body } => {
+        ExprKind::Let { bndr, ref mut rhs, body } => {
             let bndr_ty = Type::Var(ctx.fresh_tyvar());
             ty_env.insert(*bndr, bndr_ty.clone());
-            let rhs_ty = type_check(ctx, ty_env, subst_env, scope, rhs)?;
-            unify(subst_env, &bndr_ty, &rhs_ty)?;
+            let rhs_ty = type_check(ctx, ty_env, subst_env, scope, warnings, rhs)?;
+            unify(subst_env, &bndr_ty, &rhs_ty, rhs.span)?;
+            // Only functions are generalized. Other values may be mutable, e.g. `ref`s.
+            let generics = match rhs.kind {
+                ExprKind::Fun { .. } | ExprKind::Var(_) => {
+                    generalize(subst_env, scope, &[&bndr_ty])
+                }
+                _ => vec![],
+            };
+            if !generics.is_empty() {
+                ctx.set_generic_vars(*bndr, generics.clone());
+            }
             scope.new_scope();
-            scope.add(ctx.var_name(*bndr), Binder { binder: *bndr, ty: bndr_ty });
-            let ret = type_check(ctx, ty_env, subst_env, scope, body);
+            scope.add(
+                ctx.var_name(*bndr),
+                Binder { binder: *bndr, ty: bndr_ty, generics },
+            );
+            let ret = type_check(ctx, ty_env, subst_env, scope, warnings, body);
             scope.pop_scope();
             ret
         }
 
-        Expr::Var(ref mut var) => match scope.get(&ctx.var_name(*var)) {
-            Some(Binder { binder, ty }) => {
+        ExprKind::Var(ref mut var) => match scope.get(&ctx.var_name(*var)) {
+            Some(Binder { binder, ty, generics }) if generics.is_empty() => {
                 *var = *binder;
                 Ok(ty.clone())
             }
-            None => Err(TypeErr::UnboundVar(*var)),
+            Some(Binder { binder, ty, generics }) => {
+                // Each use of a polymorphic variable is a new variable, with the instantiated
+                // type. Uses are renamed to specializations of the binder after type checking.
+                let inst_tys: Vec<Type> = generics
+                    .iter()
+                    .map(|_| Type::Var(ctx.fresh_tyvar()))
+                    .collect();
+                let inst_substs: SubstEnv = generics
+                    .iter()
+                    .copied()
+                    .zip(inst_tys.iter().cloned())
+                    .collect();
+                let ty = norm_ty(&inst_substs, norm_ty(subst_env, ty.clone()));
+                let binder = *binder;
+                let instance = ctx.fresh_user_var(&ctx.var_name(binder));
+                ty_env.insert(instance, ty.clone());
+                ctx.add_instance(instance, Instance { binder, tys: inst_tys });
+                *var = instance;
+                Ok(ty)
+            }
+            None => Err(TypeErr::UnboundVar(*var, span)),
         },
 
-        Expr::LetRec { bndr, ref args, rhs, body } => {
-            // Type variables for the arguments
-            let mut arg_tys: Vec<Type> = Vec::with_capacity(args.len());
-            for arg in args {
-                let arg_ty = Type::Var(ctx.fresh_tyvar());
-                arg_tys.push(arg_ty.clone());
-                ty_env.insert(*arg, arg_ty);
+        ExprKind::LetRec { defs, body } => {
+            // Give types to the functions first, so that every function can call every other
+            // function in the group
+            let mut fun_tys: Vec<(Vec<Type>, Type)> = Vec::with_capacity(defs.len());
+            for FunDef { bndr, args, arg_tys: arg_annots, ret_ty, .. } in defs.iter() {
+                // Annotated types or type variables for the arguments
+                let mut arg_tys: Vec<Type> = Vec::with_capacity(args.len());
+                for (arg, arg_annot) in args.iter().zip(arg_annots) {
+                    let arg_ty = annot_or_fresh(ctx, arg_annot.as_ref())?;
+                    arg_tys.push(arg_ty.clone());
+                    ty_env.insert(*arg, arg_ty);
+                }
+
+                // Annotated type or type variable for the RHS
+                let rhs_ty = annot_or_fresh(ctx, ret_ty.as_ref())?;
+
+                let fun_ty = Type::Fun { args: arg_tys.clone(), ret: Box::new(rhs_ty.clone()) };
+                ty_env.insert(*bndr, fun_ty);
+                fun_tys.push((arg_tys, rhs_ty));
             }
 
-            // Type variable for the RHS
-            let rhs_ty = Type::Var(ctx.fresh_tyvar());
+            // RHSs and body will be type checked with the functions in scope
+            scope.new_scope(); // new scope for functions
+            for FunDef { bndr, .. } in defs.iter() {
+                let fun_ty = ty_env.get(bndr).unwrap().clone();
+                scope.add(ctx.var_name(*bndr), Binder::new(*bndr, fun_ty));
+            }
 
-            // We can now give type to the recursive function
-            let fun_ty = Type::Fun { args: arg_tys.clone(), ret: Box::new(rhs_ty.clone()) };
+            for (FunDef { args, rhs, .. }, (arg_tys, rhs_ty)) in defs.iter_mut().zip(fun_tys) {
+                scope.new_scope(); // new scope for args
+                for (binder, arg_ty) in args.iter().zip(arg_tys) {
+                    scope.add(ctx.var_name(*binder), Binder::new(*binder, arg_ty));
+                }
 
-            ty_env.insert(*bndr, fun_ty.clone());
+                // Type check RHS with functions and args in scope
+                let rhs_ty_ = type_check(ctx, ty_env, subst_env, scope, warnings, rhs)?;
+                unify(subst_env, &rhs_ty, &rhs_ty_, rhs.span)?;
+                scope.pop_scope();
+            }
+            scope.pop_scope();
 
-            // RHS and body will be type checked with `name` and args in scope
-            scope.new_scope(); // new scope for function
-            scope.add(ctx.var_name(*bndr), Binder { binder: *bndr, ty: fun_ty });
-            scope.new_scope(); // new scope for args
+            // Functions in the group are generalized together
+            let fun_tys: Vec<Type> = defs
+                .iter()
+                .map(|FunDef { bndr, .. }| ty_env.get(bndr).unwrap().clone())
+                .collect();
+            let generics = generalize(subst_env, scope, &fun_tys.iter().collect::<Vec<_>>());
 
-            for (binder, arg_ty) in args.iter().zip(arg_tys.iter()) {
+            scope.new_scope();
+            for (FunDef { bndr, .. }, fun_ty) in defs.iter().zip(fun_tys) {
+                if !generics.is_empty() {
+                    ctx.set_generic_vars(*bndr, generics.clone());
+                }
                 scope.add(
-                    ctx.var_name(*binder),
-                    Binder { binder: *binder, ty: arg_ty.clone() },
+                    ctx.var_name(*bndr),
+                    Binder { binder: *bndr, ty: fun_ty, generics: generics.clone() },
                 );
             }
 
-            // Type check RHS with fun and args in scope
-            let rhs_ty_ = type_check(ctx, ty_env, subst_env, scope, rhs)?;
-            unify(subst_env, &rhs_ty, &rhs_ty_)?;
-            // Type check body with just the fun in scope
-            scope.pop_scope();
-            let ret = type_check(ctx, ty_env, subst_env, scope, body);
+            // Type check body with just the functions in scope
+            let ret = type_check(ctx, ty_env, subst_env, scope, warnings, body);
             // Reset environment
             scope.pop_scope();
             ret
         }
 
-        Expr::App { fun, args } => {
-            let ret_ty = Type::Var(ctx.fresh_tyvar());
+        ExprKind::Fun { args, arg_tys: arg_annots, body } => {
             let mut arg_tys: Vec<Type> = Vec::with_capacity(args.len());
-            for arg in args {
-                arg_tys.push(type_check(ctx, ty_env, subst_env, scope, arg)?);
+            for arg_annot in arg_annots.iter() {
+                arg_tys.push(annot_or_fresh(ctx, arg_annot.as_ref())?);
+            }
+            scope.new_scope();
+            for (arg, arg_ty) in args.iter().zip(&arg_tys) {
+                ty_env.insert(*arg, arg_ty.clone());
+                scope.add(ctx.var_name(*arg), Binder::new(*arg, arg_ty.clone()));
+            }
+            let ret = type_check(ctx, ty_env, subst_env, scope, warnings, body);
+            scope.pop_scope();
+            Ok(Type::Fun { args: arg_tys, ret: Box::new(ret?) })
+        }
+
+        ExprKind::App { fun, args } => {
+            let fun_ty = type_check(ctx, ty_env, subst_env, scope, warnings, fun)?;
+            let mut arg_tys: Vec<Type> = Vec::with_capacity(args.len());
+            for arg in args.iter_mut() {
+                arg_tys.push(type_check(ctx, ty_env, subst_env, scope, warnings, arg)?);
+            }
+            // A function applied to fewer arguments than it takes is partially applied. When
+            // applied to more arguments, the return value is applied to the rest of the arguments.
+            let mut fun_ty = fun_ty;
+            let mut args_done = 0;
+            loop {
+                match deref_ty(subst_env, &fun_ty).clone() {
+                    // When the function type is known check arguments one by one, to report
+                    // errors at the argument
+                    Type::Fun { args: param_tys, ret } => {
+                        let n_args = param_tys.len().min(args.len() - args_done);
+                        for ((param_ty, arg_ty), arg) in param_tys
+                            .iter()
+                            .zip(&arg_tys[args_done..])
+                            .zip(&args[args_done..])
+                            .take(n_args)
+                        {
+                            unify(subst_env, param_ty, arg_ty, arg.span)?;
+                        }
+                        args_done += n_args;
+                        if n_args < param_tys.len() {
+                            return Ok(Type::Fun { args: param_tys[n_args..].to_vec(), ret });
+                        }
+                        if args_done == args.len() {
+                            return Ok(*ret);
+                        }
+                        fun_ty = *ret;
+                    }
+                    _ => {
+                        let ret_ty = Type::Var(ctx.fresh_tyvar());
+                        let fun_ty_ = Type::Fun {
+                            args: arg_tys[args_done..].to_vec(),
+                            ret: Box::new(ret_ty.clone()),
+                        };
+                        unify(subst_env, &fun_ty, &fun_ty_, span)?;
+                        return Ok(ret_ty);
+                    }
+                }
             }
-            let fun_ty = Type::Fun { args: arg_tys, ret: Box::new(ret_ty.clone()) };
-            let fun_ty_ = type_check(ctx, ty_env, subst_env, scope, fun)?;
-            unify(subst_env, &fun_ty, &fun_ty_)?;
+        }
+
+        ExprKind::Match { scrut, arms } => {
+            let scrut_ty = type_check(ctx, ty_env, subst_env, scope, warnings, scrut)?;
+            let ret_ty = Type::Var(ctx.fresh_tyvar());
+            let missing = type_check_arms(
+                ctx, ty_env, subst_env, scope, warnings, arms, &scrut_ty, &ret_ty,
+            )?;
+            if let Some(missing) = missing {
+                warnings.push(TypeWarning::NonExhaustiveMatch {
+                    span,
+                    missing: missing.to_string(),
+                    guarded: arms.iter().any(|arm| arm.guard.is_some()),
+                });
+            }
+
             Ok(ret_ty)
         }
 
-        Expr::Tuple(args) => {
+        // Exceptions not matched by the arms are raised again, so the arms don't need to be
+        // exhaustive
+        ExprKind::Try { body, arms } => {
+            let body_ty = type_check(ctx, ty_env, subst_env, scope, warnings, body)?;
+            let exn_ty = Type::Variant(EXN_TYPE.into());
+            type_check_arms(
+                ctx, ty_env, subst_env, scope, warnings, arms, &exn_ty, &body_ty,
+            )?;
+            Ok(body_ty)
+        }
+
+        ExprKind::Tuple(args) => {
             let mut arg_tys: Vec<Type> = Vec::with_capacity(args.len());
             for arg in args {
-                arg_tys.push(type_check(ctx, ty_env, subst_env, scope, arg)?);
+                arg_tys.push(type_check(ctx, ty_env, subst_env, scope, warnings, arg)?);
             }
             Ok(Type::Tuple(arg_tys))
         }
 
-        Expr::LetTuple { ref bndrs, rhs, body } => {
+        // `[]` doesn't determine its element type, and later passes get types of expressions
+        // from the variables in them, so `[]` is bound to a variable of the inferred type
+        ExprKind::Nil => {
+            let ty = Type::List(Box::new(Type::Var(ctx.fresh_tyvar())));
+            let nil_var = ctx.fresh_generated_var(CompilerPhase::TypeCheck);
+            ty_env.insert(nil_var, ty.clone());
+            expr.kind = ExprKind::Let {
+                bndr: nil_var,
+                rhs: Box::new(Expr { kind: ExprKind::Nil, span }),
+                body: Box::new(Expr { kind: ExprKind::Var(nil_var), span }),
+            };
+            Ok(ty)
+        }
+
+        ExprKind::Cons(hd, tl) => {
+            let hd_ty = type_check(ctx, ty_env, subst_env, scope, warnings, hd)?;
+            let list_ty = Type::List(Box::new(hd_ty));
+            let tl_ty = type_check(ctx, ty_env, subst_env, scope, warnings, tl)?;
+            unify(subst_env, &list_ty, &tl_ty, tl.span)?;
+            Ok(list_ty)
+        }
+
+        ExprKind::LetTuple { ref bndrs, rhs, body } => {
             let mut arg_tys: Vec<Type> = Vec::with_capacity(bndrs.len());
             for bndr in bndrs {
                 let bndr_ty = Type::Var(ctx.fresh_tyvar());
@@ -277,49 +804,548 @@ fn type_check(
                 arg_tys.push(bndr_ty);
             }
             let tuple_ty = Type::Tuple(arg_tys.clone());
-            let rhs_ty = type_check(ctx, ty_env, subst_env, scope, rhs)?;
-            unify(subst_env, &rhs_ty, &tuple_ty)?;
+            let rhs_ty = type_check(ctx, ty_env, subst_env, scope, warnings, rhs)?;
+            unify(subst_env, &tuple_ty, &rhs_ty, rhs.span)?;
             scope.new_scope();
-            for (bndr, bndr_type) in bndrs.iter().zip(arg_tys.into_iter()) {
-                scope.add(ctx.var_name(*bndr), Binder { binder: *bndr, ty: bndr_type });
+            for (bndr, bndr_type) in bndrs.iter().zip(arg_tys) {
+                scope.add(ctx.var_name(*bndr), Binder::new(*bndr, bndr_type));
             }
-            let ret = type_check(ctx, ty_env, subst_env, scope, body);
+            let ret = type_check(ctx, ty_env, subst_env, scope, warnings, body);
             scope.pop_scope();
             ret
         }
 
-        Expr::Array { len, elem } => {
-            let len_ty = type_check(ctx, ty_env, subst_env, scope, len)?;
-            unify(subst_env, &len_ty, &Type::Int)?;
-            let elem_ty = type_check(ctx, ty_env, subst_env, scope, elem)?;
+        ExprKind::Array { len, elem } => {
+            let len_ty = type_check(ctx, ty_env, subst_env, scope, warnings, len)?;
+            unify(subst_env, &Type::Int, &len_ty, len.span)?;
+            let elem_ty = type_check(ctx, ty_env, subst_env, scope, warnings, elem)?;
             Ok(Type::Array(Box::new(elem_ty)))
         }
 
-        Expr::Get(e1, e2) => {
+        ExprKind::Get(e1, e2) => {
             let array_elem_ty = Type::Var(ctx.fresh_tyvar());
             let array_ty = Type::Array(Box::new(array_elem_ty.clone()));
-            let e1_ty = type_check(ctx, ty_env, subst_env, scope, e1)?;
-            unify(subst_env, &e1_ty, &array_ty)?;
-            let e2_ty = type_check(ctx, ty_env, subst_env, scope, e2)?;
-            unify(subst_env, &e2_ty, &Type::Int)?;
+            let e1_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e1)?;
+            unify(subst_env, &array_ty, &e1_ty, e1.span)?;
+            let e2_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e2)?;
+            unify(subst_env, &Type::Int, &e2_ty, e2.span)?;
             Ok(array_elem_ty)
         }
 
-        Expr::Put(e1, e2, e3) => {
+        ExprKind::Put(e1, e2, e3) => {
             let array_elem_ty = Type::Var(ctx.fresh_tyvar());
             let array_ty = Type::Array(Box::new(array_elem_ty.clone()));
-            let e1_ty = type_check(ctx, ty_env, subst_env, scope, e1)?;
-            unify(subst_env, &e1_ty, &array_ty)?;
-            let e2_ty = type_check(ctx, ty_env, subst_env, scope, e2)?;
-            unify(subst_env, &e2_ty, &Type::Int)?;
-            let e3_ty = type_check(ctx, ty_env, subst_env, scope, e3)?;
-            unify(subst_env, &e3_ty, &array_elem_ty)?;
+            let e1_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e1)?;
+            unify(subst_env, &array_ty, &e1_ty, e1.span)?;
+            let e2_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e2)?;
+            unify(subst_env, &Type::Int, &e2_ty, e2.span)?;
+            let e3_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e3)?;
+            unify(subst_env, &array_elem_ty, &e3_ty, e3.span)?;
+            Ok(Type::Unit)
+        }
+
+        ExprKind::Annot { expr, ty } => {
+            let ty = annot_type(ctx, ty)?;
+            let expr_ty = type_check(ctx, ty_env, subst_env, scope, warnings, expr)?;
+            unify(subst_env, &ty, &expr_ty, expr.span)?;
+            Ok(ty)
+        }
+
+        ExprKind::Ref(e) => {
+            let e_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e)?;
+            Ok(Type::Ref(Box::new(e_ty)))
+        }
+
+        ExprKind::Deref(e) => {
+            let elem_ty = Type::Var(ctx.fresh_tyvar());
+            let e_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e)?;
+            unify(
+                subst_env,
+                &Type::Ref(Box::new(elem_ty.clone())),
+                &e_ty,
+                e.span,
+            )?;
+            Ok(elem_ty)
+        }
+
+        ExprKind::Abort(kind, arg) => {
+            let arg_expected_ty = match kind {
+                AbortKind::Failwith => Type::String,
+                AbortKind::Exit => Type::Int,
+                AbortKind::Assert => Type::Bool,
+                AbortKind::Raise => Type::Variant(EXN_TYPE.into()),
+            };
+            let arg_ty = type_check(ctx, ty_env, subst_env, scope, warnings, arg)?;
+            unify(subst_env, &arg_expected_ty, &arg_ty, arg.span)?;
+
+            // `assert e` evaluates to unit, but `assert false`, `failwith`, `exit` and `raise`
+            // don't return and can have any type. Like `[]`, these are bound to variables of the
+            // inferred type.
+            if *kind == AbortKind::Assert && !matches!(arg.kind, ExprKind::Bool(false)) {
+                return Ok(Type::Unit);
+            }
+            let ty = Type::Var(ctx.fresh_tyvar());
+            let abort_var = ctx.fresh_generated_var(CompilerPhase::TypeCheck);
+            ty_env.insert(abort_var, ty.clone());
+            let abort = std::mem::replace(&mut expr.kind, ExprKind::Unit);
+            expr.kind = ExprKind::Let {
+                bndr: abort_var,
+                rhs: Box::new(Expr { kind: abort, span }),
+                body: Box::new(Expr { kind: ExprKind::Var(abort_var), span }),
+            };
+            Ok(ty)
+        }
+
+        ExprKind::Assign(e1, e2) => {
+            let elem_ty = Type::Var(ctx.fresh_tyvar());
+            let e1_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e1)?;
+            unify(
+                subst_env,
+                &Type::Ref(Box::new(elem_ty.clone())),
+                &e1_ty,
+                e1.span,
+            )?;
+            let e2_ty = type_check(ctx, ty_env, subst_env, scope, warnings, e2)?;
+            unify(subst_env, &elem_ty, &e2_ty, e2.span)?;
+            Ok(Type::Unit)
+        }
+
+        ExprKind::For { var, start, end, body, .. } => {
+            let start_ty = type_check(ctx, ty_env, subst_env, scope, warnings, start)?;
+            unify(subst_env, &Type::Int, &start_ty, start.span)?;
+            let end_ty = type_check(ctx, ty_env, subst_env, scope, warnings, end)?;
+            unify(subst_env, &Type::Int, &end_ty, end.span)?;
+            ty_env.insert(*var, Type::Int);
+            scope.new_scope();
+            scope.add(ctx.var_name(*var), Binder::new(*var, Type::Int));
+            let body_ty = type_check(ctx, ty_env, subst_env, scope, warnings, body);
+            scope.pop_scope();
+            unify(subst_env, &Type::Unit, &body_ty?, body.span)?;
+            Ok(Type::Unit)
+        }
+
+        ExprKind::While { cond, body } => {
+            let cond_ty = type_check(ctx, ty_env, subst_env, scope, warnings, cond)?;
+            unify(subst_env, &Type::Bool, &cond_ty, cond.span)?;
+            let body_ty = type_check(ctx, ty_env, subst_env, scope, warnings, body)?;
+            unify(subst_env, &Type::Unit, &body_ty, body.span)?;
+            Ok(Type::Unit)
+        }
+
+        ExprKind::TypeDecl { decls, body } => {
+            declare_types(ctx, decls)?;
+            type_check(ctx, ty_env, subst_env, scope, warnings, body)
+        }
+
+        ExprKind::Exception { decl, body } => {
+            declare_exception(ctx, decl)?;
+            type_check(ctx, ty_env, subst_env, scope, warnings, body)
+        }
+
+        ExprKind::External { bndr, ty, symbol, body } => {
+            let external_ty = annot_type(ctx, ty)?;
+            if !matches!(external_ty, Type::Fun { .. }) {
+                return Err(TypeErr::ExternalNotFunction(ty.span));
+            }
+            // Externals are global like builtins, uses of the binder are not captured by closures
+            let name = ctx.var_name(*bndr);
+            *bndr = ctx.add_external(&name, symbol, external_ty.clone());
+            scope.new_scope();
+            scope.add(name, Binder::new(*bndr, external_ty));
+            let ret = type_check(ctx, ty_env, subst_env, scope, warnings, body);
+            scope.pop_scope();
+            ret
+        }
+
+        ExprKind::Ctor { ctor, args } => {
+            let (decl, ctor_idx) = resolve_ctor(ctx, ctor, span)?;
+            let field_tys = &decl.ctors[ctor_idx].fields;
+            if field_tys.len() != args.len() {
+                return Err(TypeErr::CtorArity {
+                    ctor: decl.ctors[ctor_idx].name.to_string(),
+                    expected: field_tys.len(),
+                    found: args.len(),
+                    span,
+                });
+            }
+            for (field_ty, arg) in field_tys.iter().zip(args.iter_mut()) {
+                let arg_ty = type_check(ctx, ty_env, subst_env, scope, warnings, arg)?;
+                unify(subst_env, field_ty, &arg_ty, arg.span)?;
+            }
+            Ok(Type::Variant(decl.name.clone()))
+        }
+
+        ExprKind::Record(fields) => {
+            let mut field_decls: Vec<(Rc<RecordDecl>, usize)> = Vec::with_capacity(fields.len());
+            for FieldExpr { field, span: field_span, .. } in fields.iter_mut() {
+                field_decls.push(resolve_field(ctx, field, *field_span)?);
+            }
+            // The type is found from the first field, other fields need to be of the same type
+            let decl = field_decls[0].0.clone();
+            let record_ty = Type::Record(decl.name.clone());
+            let mut field_idxs: Vec<usize> = Vec::with_capacity(fields.len());
+            for ((field_decl, field_idx), FieldExpr { span: field_span, .. }) in
+                field_decls.into_iter().zip(fields.iter())
+            {
+                if field_decl.name != decl.name {
+                    return Err(TypeErr::UnifyError(
+                        record_ty,
+                        Type::Record(field_decl.name.clone()),
+                        *field_span,
+                    ));
+                }
+                if field_idxs.contains(&field_idx) {
+                    return Err(TypeErr::DuplicateField(
+                        decl.fields[field_idx].name.to_string(),
+                        *field_span,
+                    ));
+                }
+                field_idxs.push(field_idx);
+            }
+            if let Some(missing) = (0..decl.fields.len()).find(|idx| !field_idxs.contains(idx)) {
+                return Err(TypeErr::MissingField(
+                    decl.fields[missing].name.to_string(),
+                    span,
+                ));
+            }
+            for (FieldExpr { expr, .. }, field_idx) in fields.iter_mut().zip(field_idxs) {
+                let expr_ty = type_check(ctx, ty_env, subst_env, scope, warnings, expr)?;
+                unify(subst_env, &decl.fields[field_idx].ty, &expr_ty, expr.span)?;
+            }
+            Ok(record_ty)
+        }
+
+        ExprKind::GetField { expr, field } => {
+            let (decl, field_idx) = resolve_field(ctx, field, span)?;
+            let expr_ty = type_check(ctx, ty_env, subst_env, scope, warnings, expr)?;
+            unify(
+                subst_env,
+                &Type::Record(decl.name.clone()),
+                &expr_ty,
+                expr.span,
+            )?;
+            Ok(decl.fields[field_idx].ty.clone())
+        }
+
+        ExprKind::SetField { expr, field, value } => {
+            let (decl, field_idx) = resolve_field(ctx, field, span)?;
+            if !decl.fields[field_idx].mutable {
+                return Err(TypeErr::ImmutableField(
+                    decl.fields[field_idx].name.to_string(),
+                    span,
+                ));
+            }
+            let expr_ty = type_check(ctx, ty_env, subst_env, scope, warnings, expr)?;
+            unify(
+                subst_env,
+                &Type::Record(decl.name.clone()),
+                &expr_ty,
+                expr.span,
+            )?;
+            let value_ty = type_check(ctx, ty_env, subst_env, scope, warnings, value)?;
+            unify(subst_env, &decl.fields[field_idx].ty, &value_ty, value.span)?;
             Ok(Type::Unit)
         }
     }
 }
 
-fn unify(subst_env: &mut SubstEnv, ty1: &Type, ty2: &Type) -> Result<(), TypeErr> {
+// Find the constructor with the given name in scope, and replace the name with the unique name of
+// the constructor for later passes
+fn resolve_ctor(
+    ctx: &Ctx, ctor: &mut String, span: Span,
+) -> Result<(Rc<VariantDecl>, usize), TypeErr> {
+    let unique_name = ctx
+        .resolve_ctor(ctor)
+        .ok_or_else(|| TypeErr::UnknownCtor(ctor.clone(), span))?;
+    *ctor = unique_name.to_string();
+    Ok(ctx.get_ctor(&unique_name).unwrap())
+}
+
+// Find the field with the given name in scope, and replace the name with the unique name of the
+// field for later passes
+fn resolve_field(
+    ctx: &Ctx, field: &mut String, span: Span,
+) -> Result<(Rc<RecordDecl>, usize), TypeErr> {
+    let unique_name = ctx
+        .resolve_field(field)
+        .ok_or_else(|| TypeErr::UnknownField(field.clone(), span))?;
+    *field = unique_name.to_string();
+    Ok(ctx.get_field(&unique_name).unwrap())
+}
+
+// Check arms of a `match` or `try` against the type of the scrutinee and the type of the
+// expression. Reports redundant arms, and returns a value not matched by the arms if there is one.
+#[allow(clippy::too_many_arguments)]
+fn type_check_arms(
+    ctx: &mut Ctx, ty_env: &mut TypeEnv, subst_env: &mut SubstEnv, scope: &mut Scope,
+    warnings: &mut Vec<TypeWarning>, arms: &mut [Arm<VarId>], scrut_ty: &Type, ret_ty: &Type,
+) -> Result<Option<Witness>, TypeErr> {
+    for Arm { pat, guard, rhs } in arms.iter_mut() {
+        // New scope for the pattern variables
+        scope.new_scope();
+        type_check_pat(ctx, ty_env, subst_env, scope, pat, scrut_ty)?;
+        if let Some(guard) = guard {
+            let guard_ty = type_check(ctx, ty_env, subst_env, scope, warnings, guard)?;
+            unify(subst_env, &Type::Bool, &guard_ty, guard.span)?;
+        }
+        let rhs_ty = type_check(ctx, ty_env, subst_env, scope, warnings, rhs)?;
+        unify(subst_env, ret_ty, &rhs_ty, rhs.span)?;
+        scope.pop_scope();
+    }
+
+    let arm_pats: Vec<(&Pat<VarId>, bool)> = arms
+        .iter()
+        .map(|arm| (&arm.pat, arm.guard.is_some()))
+        .collect();
+    let MatchCheck { redundant, missing } = check_match(ctx, &arm_pats);
+    for arm_idx in redundant {
+        warnings.push(TypeWarning::UnusedArm(arms[arm_idx].pat.span));
+    }
+    Ok(missing)
+}
+
+// Type variables in the types that are not in the types of the variables in scope
+fn generalize(subst_env: &SubstEnv, scope: &Scope, tys: &[&Type]) -> Vec<TyVar> {
+    let mut vars: Vec<TyVar> = vec![];
+    for ty in tys {
+        ty_vars(subst_env, ty, &mut vars);
+    }
+    if vars.is_empty() {
+        return vars;
+    }
+
+    let mut scope_vars: Vec<TyVar> = vec![];
+    for Binder { ty, generics, .. } in scope.values() {
+        let mut binder_vars: Vec<TyVar> = vec![];
+        ty_vars(subst_env, ty, &mut binder_vars);
+        // Type variables of polymorphic binders are not free
+        scope_vars.extend(
+            binder_vars
+                .into_iter()
+                .filter(|var| !generics.contains(var)),
+        );
+    }
+
+    vars.retain(|var| !scope_vars.contains(var));
+    vars
+}
+
+// Type variables in a type, without duplicates
+fn ty_vars(subst_env: &SubstEnv, ty: &Type, acc: &mut Vec<TyVar>) {
+    match deref_ty(subst_env, ty) {
+        Type::Unit
+        | Type::Bool
+        | Type::Int
+        | Type::Float
+        | Type::String
+        | Type::Char
+        | Type::Variant(_)
+        | Type::Record(_) => {}
+        Type::Fun { args, ret } => {
+            for arg in args {
+                ty_vars(subst_env, arg, acc);
+            }
+            ty_vars(subst_env, ret, acc);
+        }
+        Type::Tuple(args) => {
+            for arg in args {
+                ty_vars(subst_env, arg, acc);
+            }
+        }
+        Type::Array(ty) | Type::List(ty) | Type::Ref(ty) => ty_vars(subst_env, ty, acc),
+        Type::Var(var) => {
+            if !acc.contains(var) {
+                acc.push(*var);
+            }
+        }
+    }
+}
+
+/// Add the types declared in a `type` item to the context. Types in the same item can refer to
+/// each other.
+pub fn declare_types(ctx: &mut Ctx, decls: &[TypeDecl]) -> Result<(), TypeErr> {
+    for decl in decls {
+        if builtin_type(&decl.name).is_some()
+            || ctx.get_variant(&decl.name).is_some()
+            || ctx.get_record(&decl.name).is_some()
+        {
+            return Err(TypeErr::DuplicateType(decl.name.clone(), decl.span));
+        }
+        let name: Rc<str> = decl.name.as_str().into();
+        match &decl.kind {
+            TypeDeclKind::Variant(_) => ctx.add_variant(VariantDecl { name, ctors: vec![] }),
+            TypeDeclKind::Record(_) => ctx.add_record(RecordDecl { name, fields: vec![] }),
+        }
+    }
+
+    for decl in decls {
+        let name: Rc<str> = decl.name.as_str().into();
+        match &decl.kind {
+            TypeDeclKind::Variant(ctor_decls) => {
+                let mut ctors: Vec<VariantCtor> = Vec::with_capacity(ctor_decls.len());
+                // Constructors of earlier types with the same names are shadowed
+                for ctor in ctor_decls {
+                    if ctors.iter().any(|ctor_| *ctor_.name == *ctor.name) {
+                        return Err(TypeErr::DuplicateCtor(ctor.name.clone(), ctor.span));
+                    }
+                    let fields = ctor
+                        .fields
+                        .iter()
+                        .map(|field| annot_type(ctx, field))
+                        .collect::<Result<_, _>>()?;
+                    ctors.push(VariantCtor { name: ctor.name.as_str().into(), fields });
+                }
+                ctx.add_variant(VariantDecl { name, ctors });
+            }
+            TypeDeclKind::Record(field_decls) => {
+                let mut fields: Vec<RecordField> = Vec::with_capacity(field_decls.len());
+                // Fields of earlier types with the same names are shadowed
+                for field in field_decls {
+                    if fields.iter().any(|field_| *field_.name == *field.name) {
+                        return Err(TypeErr::DuplicateField(field.name.clone(), field.span));
+                    }
+                    fields.push(RecordField {
+                        name: field.name.as_str().into(),
+                        mutable: field.mutable,
+                        ty: annot_type(ctx, &field.ty)?,
+                    });
+                }
+                ctx.add_record(RecordDecl { name, fields });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Add the constructor declared in an `exception` item to the `exn` type. Like in type
+/// declarations, constructors with the same name are shadowed.
+pub fn declare_exception(ctx: &mut Ctx, decl: &CtorDecl) -> Result<(), TypeErr> {
+    let fields = decl
+        .fields
+        .iter()
+        .map(|field| annot_type(ctx, field))
+        .collect::<Result<_, _>>()?;
+    ctx.add_exn_ctor(VariantCtor { name: decl.name.as_str().into(), fields });
+    Ok(())
+}
+
+fn builtin_type(name: &str) -> Option<Type> {
+    match name {
+        "unit" => Some(Type::Unit),
+        "bool" => Some(Type::Bool),
+        "int" => Some(Type::Int),
+        "float" => Some(Type::Float),
+        "string" => Some(Type::String),
+        "char" => Some(Type::Char),
+        _ => None,
+    }
+}
+
+// Type of a type annotation
+fn annot_type(ctx: &Ctx, ty: &TypeExpr) -> Result<Type, TypeErr> {
+    match &ty.kind {
+        TypeExprKind::Named(name) => match builtin_type(name) {
+            Some(ty) => Ok(ty),
+            None => match (ctx.get_variant(name), ctx.get_record(name)) {
+                (Some(decl), _) => Ok(Type::Variant(decl.name.clone())),
+                (None, Some(decl)) => Ok(Type::Record(decl.name.clone())),
+                (None, None) => Err(TypeErr::UnknownType(name.clone(), ty.span)),
+            },
+        },
+        TypeExprKind::Array(elem) => Ok(Type::Array(Box::new(annot_type(ctx, elem)?))),
+        TypeExprKind::List(elem) => Ok(Type::List(Box::new(annot_type(ctx, elem)?))),
+        TypeExprKind::Ref(elem) => Ok(Type::Ref(Box::new(annot_type(ctx, elem)?))),
+        TypeExprKind::Tuple(tys) => Ok(Type::Tuple(
+            tys.iter()
+                .map(|ty| annot_type(ctx, ty))
+                .collect::<Result<_, _>>()?,
+        )),
+        TypeExprKind::Fun { args, ret } => Ok(Type::Fun {
+            args: args
+                .iter()
+                .map(|ty| annot_type(ctx, ty))
+                .collect::<Result<_, _>>()?,
+            ret: Box::new(annot_type(ctx, ret)?),
+        }),
+    }
+}
+
+// Type of an optional annotation, or a fresh type variable
+fn annot_or_fresh(ctx: &mut Ctx, ty: Option<&TypeExpr>) -> Result<Type, TypeErr> {
+    match ty {
+        Some(ty) => annot_type(ctx, ty),
+        None => Ok(Type::Var(ctx.fresh_tyvar())),
+    }
+}
+
+// Check a pattern against the type of the value it matches. Adds variables in the pattern to the
+// current scope.
+fn type_check_pat(
+    ctx: &mut Ctx, ty_env: &mut TypeEnv, subst_env: &mut SubstEnv, scope: &mut Scope,
+    pat: &mut Pat<VarId>, ty: &Type,
+) -> Result<(), TypeErr> {
+    let span = pat.span;
+    match &mut pat.kind {
+        PatKind::Wildcard => Ok(()),
+        PatKind::Var(var) => {
+            ty_env.insert(*var, ty.clone());
+            scope.add(ctx.var_name(*var), Binder::new(*var, ty.clone()));
+            Ok(())
+        }
+        PatKind::Unit => unify(subst_env, ty, &Type::Unit, span),
+        PatKind::Bool(_) => unify(subst_env, ty, &Type::Bool, span),
+        PatKind::Int(_) => unify(subst_env, ty, &Type::Int, span),
+        PatKind::Tuple(pats) => {
+            let pat_tys: Vec<Type> = pats.iter().map(|_| Type::Var(ctx.fresh_tyvar())).collect();
+            unify(subst_env, ty, &Type::Tuple(pat_tys.clone()), span)?;
+            for (pat, pat_ty) in pats.iter_mut().zip(pat_tys.iter()) {
+                type_check_pat(ctx, ty_env, subst_env, scope, pat, pat_ty)?;
+            }
+            Ok(())
+        }
+        PatKind::Nil => {
+            let elem_ty = Type::Var(ctx.fresh_tyvar());
+            unify(subst_env, ty, &Type::List(Box::new(elem_ty)), span)
+        }
+        PatKind::Cons(hd, tl) => {
+            let elem_ty = Type::Var(ctx.fresh_tyvar());
+            let list_ty = Type::List(Box::new(elem_ty.clone()));
+            unify(subst_env, ty, &list_ty, span)?;
+            type_check_pat(ctx, ty_env, subst_env, scope, hd, &elem_ty)?;
+            type_check_pat(ctx, ty_env, subst_env, scope, tl, &list_ty)
+        }
+        PatKind::Ctor { ctor, args } => {
+            let (decl, ctor_idx) = resolve_ctor(ctx, ctor, span)?;
+            let field_tys = &decl.ctors[ctor_idx].fields;
+            // `C _` matches all fields of a constructor with multiple fields
+            if field_tys.len() > 1 && args.len() == 1 && args[0].kind == PatKind::Wildcard {
+                let wildcard_span = args[0].span;
+                args.clear();
+                args.extend(
+                    field_tys
+                        .iter()
+                        .map(|_| Pat { kind: PatKind::Wildcard, span: wildcard_span }),
+                );
+            }
+            if field_tys.len() != args.len() {
+                return Err(TypeErr::CtorArity {
+                    ctor: decl.ctors[ctor_idx].name.to_string(),
+                    expected: field_tys.len(),
+                    found: args.len(),
+                    span,
+                });
+            }
+            unify(subst_env, ty, &Type::Variant(decl.name.clone()), span)?;
+            for (pat, field_ty) in args.iter_mut().zip(field_tys.iter()) {
+                type_check_pat(ctx, ty_env, subst_env, scope, pat, field_ty)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn unify(subst_env: &mut SubstEnv, ty1: &Type, ty2: &Type, span: Span) -> Result<(), TypeErr> {
     let ty1 = deref_ty(subst_env, ty1).clone();
     let ty2 = deref_ty(subst_env, ty2).clone();
 
@@ -330,22 +1356,31 @@ fn unify(subst_env: &mut SubstEnv, ty1: &Type, ty2: &Type) -> Result<(), TypeErr
         (Type::Unit, Type::Unit)
         | (Type::Bool, Type::Bool)
         | (Type::Int, Type::Int)
-        | (Type::Float, Type::Float) => Ok(()),
+        | (Type::Float, Type::Float)
+        | (Type::String, Type::String)
+        | (Type::Char, Type::Char) => Ok(()),
+        // Function types are curried: `a -> b -> c` is the same type as `a -> (b -> c)`, the
+        // number of arguments a function takes is not a part of its type
         (Type::Fun { args: args1, ret: ret1 }, Type::Fun { args: args2, ret: ret2 }) => {
-            if args1.len() != args2.len() {
-                return Err(TypeErr::UnifyError(ty1.clone(), ty2.clone()));
-            }
+            let n_args = args1.len().min(args2.len());
             for (arg1, arg2) in args1.iter().zip(args2.iter()) {
-                unify(subst_env, arg1, arg2)?;
+                unify(subst_env, arg1, arg2, span)?;
             }
-            unify(subst_env, &*ret1, &*ret2)
+            let rest = |args: &[Type], ret: &Type| {
+                if args.len() == n_args {
+                    ret.clone()
+                } else {
+                    Type::Fun { args: args[n_args..].to_vec(), ret: Box::new(ret.clone()) }
+                }
+            };
+            unify(subst_env, &rest(args1, ret1), &rest(args2, ret2), span)
         }
 
         (Type::Var(var1), Type::Var(var2)) if var1 == var2 => Ok(()),
 
         (Type::Var(var), ty) | (ty, Type::Var(var)) => {
             if occurs_check(subst_env, *var, ty) {
-                return Err(TypeErr::InfiniteType(ty1, ty2));
+                return Err(TypeErr::InfiniteType(ty1, ty2, span));
             }
             // println!("unify {:?} ~ {:?}", var, ty);
             subst_env.insert(*var, ty.clone());
@@ -354,52 +1389,575 @@ fn unify(subst_env: &mut SubstEnv, ty1: &Type, ty2: &Type) -> Result<(), TypeErr
 
         (Type::Tuple(args1), Type::Tuple(args2)) => {
             if args1.len() != args2.len() {
-                return Err(TypeErr::UnifyError(ty1.clone(), ty2.clone()));
+                return Err(TypeErr::UnifyError(ty1.clone(), ty2.clone(), span));
             }
             for (arg1, arg2) in args1.iter().zip(args2.iter()) {
-                unify(subst_env, arg1, arg2)?;
+                unify(subst_env, arg1, arg2, span)?;
             }
             Ok(())
         }
 
-        (Type::Array(ty1), Type::Array(ty2)) => unify(subst_env, ty1, ty2),
+        (Type::Array(ty1), Type::Array(ty2))
+        | (Type::List(ty1), Type::List(ty2))
+        | (Type::Ref(ty1), Type::Ref(ty2)) => unify(subst_env, ty1, ty2, span),
+
+        (Type::Variant(name1), Type::Variant(name2))
+        | (Type::Record(name1), Type::Record(name2))
+            if name1 == name2 =>
+        {
+            Ok(())
+        }
 
-        _ => Err(TypeErr::UnifyError(ty1.clone(), ty2.clone())),
+        _ => Err(TypeErr::UnifyError(ty1.clone(), ty2.clone(), span)),
     }
 }
 
 use std::fmt;
 
+/// Names of type variables in printed types: `'a`, `'b`, ... in the order they're printed. Types
+/// printed with the same `TyVarNames` agree on the names.
+#[derive(Debug, Default)]
+pub struct TyVarNames {
+    names: FxHashMap<TyVar, usize>,
+}
+
+impl TyVarNames {
+    fn pp(&mut self, var: TyVar, w: &mut dyn fmt::Write) -> fmt::Result {
+        let n_names = self.names.len();
+        let idx = *self.names.entry(var).or_insert(n_names);
+        write!(w, "'{}", (b'a' + (idx % 26) as u8) as char)?;
+        if idx >= 26 {
+            write!(w, "{}", idx / 26)?;
+        }
+        Ok(())
+    }
+}
+
+// Context of a type when printing, to decide where to add parens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Prec {
+    // Function return type, or top-level
+    Arrow,
+    // Function argument
+    ArrowArg,
+    // Tuple field, array or list element, or `ref` contents
+    Atom,
+}
+
 impl Type {
+    /// Print the type in OCaml syntax, e.g. `(int -> float) -> 'a array`
     pub fn pp(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        self.pp_with(&mut Default::default(), w)
+    }
+
+    pub fn pp_with(&self, names: &mut TyVarNames, w: &mut dyn fmt::Write) -> fmt::Result {
+        self.pp_prec(names, Prec::Arrow, w)
+    }
+
+    fn pp_prec(&self, names: &mut TyVarNames, prec: Prec, w: &mut dyn fmt::Write) -> fmt::Result {
         use Type::*;
         match self {
-            Unit => w.write_str("()"),
+            Unit => w.write_str("unit"),
             Bool => w.write_str("bool"),
             Int => w.write_str("int"),
             Float => w.write_str("float"),
+            String => w.write_str("string"),
+            Char => w.write_str("char"),
             Fun { args, ret } => {
+                if prec > Prec::Arrow {
+                    w.write_str("(")?;
+                }
                 for arg in args {
-                    arg.pp(w)?;
+                    arg.pp_prec(names, Prec::ArrowArg, w)?;
                     w.write_str(" -> ")?;
                 }
-                ret.pp(w)
+                ret.pp_prec(names, Prec::Arrow, w)?;
+                if prec > Prec::Arrow {
+                    w.write_str(")")?;
+                }
+                Ok(())
             }
             Tuple(args) => {
                 assert!(!args.is_empty());
-                args[0].pp(w)?;
+                if prec > Prec::ArrowArg {
+                    w.write_str("(")?;
+                }
+                args[0].pp_prec(names, Prec::Atom, w)?;
                 for arg in &args[1..] {
                     w.write_str(" * ")?;
-                    arg.pp(w)?;
+                    arg.pp_prec(names, Prec::Atom, w)?;
+                }
+                if prec > Prec::ArrowArg {
+                    w.write_str(")")?;
                 }
                 Ok(())
             }
             Array(ty) => {
-                w.write_str("[")?;
-                ty.pp(w)?;
-                w.write_str("]")
+                ty.pp_prec(names, Prec::Atom, w)?;
+                w.write_str(" array")
+            }
+            List(ty) => {
+                ty.pp_prec(names, Prec::Atom, w)?;
+                w.write_str(" list")
+            }
+            Ref(ty) => {
+                ty.pp_prec(names, Prec::Atom, w)?;
+                w.write_str(" ref")
+            }
+            Variant(name) | Record(name) => w.write_str(name),
+            Var(var) => names.pp(*var, w),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+
+    fn check(pgm: &str) -> Result<(), TypeErr> {
+        let (expr, errs) = parse(&tokenize(pgm).unwrap());
+        assert!(errs.is_empty(), "{:?}", errs);
+        let mut ctx: Ctx = Default::default();
+        let mut expr = expr.intern(&mut ctx);
+        type_check_pgm(&mut ctx, &mut expr).map(|_| ())
+    }
+
+    fn pp(ty: &Type) -> String {
+        let mut s = String::new();
+        ty.pp(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn pp_types() {
+        let fun = |args: Vec<Type>, ret: Type| Type::Fun { args, ret: Box::new(ret) };
+        let array = |ty: Type| Type::Array(Box::new(ty));
+        let var = |i: u32| Type::Var(Uniq(std::num::NonZeroU32::new(i).unwrap()));
+
+        assert_eq!(pp(&fun(vec![Type::Int], Type::Float)), "int -> float");
+        assert_eq!(pp(&array(var(42))), "'a array");
+        assert_eq!(
+            pp(&fun(
+                vec![fun(vec![var(2)], var(1)), array(var(2))],
+                array(var(1))
+            )),
+            "('a -> 'b) -> 'a array -> 'b array"
+        );
+        assert_eq!(
+            pp(&fun(
+                vec![Type::Tuple(vec![Type::Int, Type::Unit])],
+                Type::Bool
+            )),
+            "int * unit -> bool"
+        );
+        assert_eq!(
+            pp(&array(Type::Tuple(vec![
+                Type::Int,
+                Type::Tuple(vec![Type::Bool, Type::Float])
+            ]))),
+            "(int * (bool * float)) array"
+        );
+    }
+
+    #[test]
+    fn annotations() {
+        assert!(check(
+            "let rec f (x : int) : float = float_of_int x in print_int (truncate (f 1))"
+        )
+        .is_ok());
+
+        // Without the annotation the error would be reported at the use of `f`, after the
+        // argument type is inferred from the body
+        match check("let rec f (x : float) = x + 1 in print_int (f 1.0)") {
+            Err(TypeErr::UnifyError(Type::Int, Type::Float, span)) => {
+                assert_eq!(span, Span::new(24, 25))
+            }
+            other => panic!("{:?}", other),
+        }
+
+        match check("let x = (1 : float) in ()") {
+            Err(TypeErr::UnifyError(Type::Float, Type::Int, span)) => {
+                assert_eq!(span, Span::new(9, 10))
+            }
+            other => panic!("{:?}", other),
+        }
+
+        match check("let (x : list) = 1 in ()") {
+            Err(TypeErr::UnknownType(name, span)) => {
+                assert_eq!((name.as_str(), span), ("list", Span::new(9, 13)))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn let_unit() {
+        assert!(check("let () = print_int 1\nlet () = () in ()").is_ok());
+        match check("let () = 1") {
+            Err(TypeErr::UnifyError(Type::Unit, Type::Int, span)) => {
+                assert_eq!(span, Span::new(9, 10))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn variants() {
+        assert!(check(
+            "type t = A | B of int * t\n\
+             let rec f (x : t) = match x with A -> 0 | B (n, x) -> n + f x\n\
+             ;; print_int (f (B (1, A)))"
+        )
+        .is_ok());
+
+        match check("type t = A | B of int * int;; match A with B _ -> () | A -> ()") {
+            Ok(()) => {}
+            other => panic!("{:?}", other),
+        }
+
+        match check("type t = A of int;; let x = A (1, 2) in ()") {
+            Err(TypeErr::CtorArity { ctor, expected: 1, found: 2, span }) => {
+                assert_eq!((ctor.as_str(), span), ("A", Span::new(28, 35)))
+            }
+            other => panic!("{:?}", other),
+        }
+
+        match check("type t = A of float;; let x = A 1 in ()") {
+            Err(TypeErr::UnifyError(Type::Float, Type::Int, span)) => {
+                assert_eq!(span, Span::new(32, 33))
+            }
+            other => panic!("{:?}", other),
+        }
+
+        assert!(matches!(
+            check("let x = B in ()"),
+            Err(TypeErr::UnknownCtor(_, _))
+        ));
+        assert!(matches!(
+            check("type t = A | A"),
+            Err(TypeErr::DuplicateCtor(_, _))
+        ));
+        // Constructors of a later type shadow the constructors of earlier types
+        assert!(check("type t = A\ntype u = A;; let x = (A : u) in ()").is_ok());
+        assert!(matches!(
+            check("type t = A\ntype u = A;; let x = (A : t) in ()"),
+            Err(TypeErr::UnifyError(_, _, _))
+        ));
+        assert!(matches!(
+            check("type t = A\ntype t = B"),
+            Err(TypeErr::DuplicateType(_, _))
+        ));
+    }
+
+    #[test]
+    fn records() {
+        let ty = "type p = { x : int; mutable y : float }\n;; ";
+        assert!(check(&format!(
+            "{}let r = {{ y = 1.0; x = 2 }} in r.y <- r.y +. float_of_int r.x",
+            ty
+        ))
+        .is_ok());
+
+        match check(&format!("{}let r = {{ x = 1; y = 2 }} in ()", ty)) {
+            Err(TypeErr::UnifyError(Type::Float, Type::Int, span)) => {
+                assert_eq!(span, Span::new(64, 65))
+            }
+            other => panic!("{:?}", other),
+        }
+
+        assert!(matches!(
+            check(&format!("{}let r = {{ x = 1 }} in ()", ty)),
+            Err(TypeErr::MissingField(field, _)) if field == "y"
+        ));
+        assert!(matches!(
+            check(&format!("{}let r = {{ x = 1; y = 1.0; x = 2 }} in ()", ty)),
+            Err(TypeErr::DuplicateField(_, _))
+        ));
+        assert!(matches!(
+            check("type p = { x : int; x : int };; ()"),
+            Err(TypeErr::DuplicateField(_, _))
+        ));
+        // Fields of a later type shadow the fields of earlier types
+        assert!(check(&format!(
+            "{}type q = {{ x : float; mutable z : float }};; \
+             let r = {{ x = 1.0; z = 2.0 }} in r.z <- r.x",
+            ty
+        ))
+        .is_ok());
+        assert!(matches!(
+            check(&format!("{}let r = {{ x = 1; y = 1.0 }} in r.x <- 2", ty)),
+            Err(TypeErr::ImmutableField(field, _)) if field == "x"
+        ));
+        assert!(matches!(
+            check(&format!("{}let r = {{ x = 1; y = 1.0 }} in r.z", ty)),
+            Err(TypeErr::UnknownField(_, _))
+        ));
+    }
+
+    #[test]
+    fn polymorphism() {
+        assert!(check("let rec id x = x in let (a, b) = (id 1, id 1.0) in ()").is_ok());
+        assert!(check("let id = fun x -> x in let (a, b) = (id 1, id true) in ()").is_ok());
+        assert!(check(
+            "let rec compose f g = fun x -> g (f x) in
+             let h = compose float_of_int truncate in
+             let k = compose (fun b -> if b then 1.0 else 0.0) (fun x -> x +. 1.0) in
+             print_int (h 1)"
+        )
+        .is_ok());
+
+        // Functions are not polymorphic in their own definitions
+        assert!(matches!(
+            check("let rec f x = let (a, b) = (f 1, f 1.0) in x in ()"),
+            Err(TypeErr::UnifyError(_, _, _))
+        ));
+
+        // Type variables of arguments in scope are not generalized
+        assert!(matches!(
+            check("let rec f x = let rec g y = x in let (a, b) = (g 1 + 1, g 1 +. 1.0) in a in ()"),
+            Err(TypeErr::UnifyError(_, _, _))
+        ));
+
+        // Only functions are generalized
+        assert!(matches!(
+            check("let r = ref (fun x -> x) in r := (fun x -> x + 1); !r 1.0"),
+            Err(TypeErr::UnifyError(_, _, _))
+        ));
+    }
+
+    #[test]
+    fn externals() {
+        assert!(check(
+            "external floor : float -> float = \"floor\"
+             ;; print_int (truncate (floor 1.5))"
+        )
+        .is_ok());
+        match check("external x : int = \"x\"") {
+            Err(TypeErr::ExternalNotFunction(span)) => assert_eq!(span, Span::new(13, 16)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn partial_application() {
+        assert!(check("let rec add x y = x + y in let inc = add 1 in print_int (inc 2)").is_ok());
+        assert!(check(
+            "let rec compose f g = fun x -> g (f x) in
+             let rec inc x = x + 1 in
+             print_int (compose inc inc 1)"
+        )
+        .is_ok());
+
+        // Arguments are checked against the parameters of the returned function
+        match check("let rec f x = fun y -> x + y in f 1 2.0") {
+            Err(TypeErr::UnifyError(Type::Int, Type::Float, span)) => {
+                assert_eq!(span, Span::new(36, 39))
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // Too many arguments
+        assert!(matches!(
+            check("let rec f x = x + 1 in f 1 2"),
+            Err(TypeErr::UnifyError(_, _, _))
+        ));
+
+        // A partially applied function is not the type the function returns
+        assert!(matches!(
+            check("let rec add x y = x + y in print_int (add 1)"),
+            Err(TypeErr::UnifyError(_, _, _))
+        ));
+
+        // Function types are curried, the number of arguments functions take doesn't matter
+        assert!(
+            check("let rec add x y = x + y let rec ap f = (f 1) 2 in print_int (ap add)").is_ok()
+        );
+        assert!(check(
+            "let rec fold f acc l = match l with [] -> acc | x :: l -> fold f (f acc x) l in
+             print_int (fold (fun a -> fun x -> a + x) 0 [1; 2; 3])"
+        )
+        .is_ok());
+        assert!(check("print_int ((fun f -> f 1 2) (fun x -> fun y -> x + y))").is_ok());
+        assert!(check("let (f : int -> int -> int) = fun x -> fun y -> x + y in ()").is_ok());
+        match check("let rec ap f = (f 1) 2 in ap (fun x y -> x +. y)") {
+            Err(TypeErr::UnifyError(Type::Int, Type::Float, _)) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn lists() {
+        assert!(check(
+            "let rec sum l = match l with [] -> 0 | x :: xs -> x + sum xs in
+             print_int (sum [1; 2; 3])"
+        )
+        .is_ok());
+        // `[]` is polymorphic in every use
+        assert!(check("let (a, b) = (1 :: [], 1.0 :: []) in ()").is_ok());
+        assert!(check("let l = ([] : int list) in ()").is_ok());
+
+        match check("let l = [1; 2.0] in ()") {
+            Err(TypeErr::UnifyError(Type::Int, Type::Float, span)) => {
+                assert_eq!(span, Span::new(12, 15))
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            check("match [1] with [x] -> x +. 1.0 | _ -> 0.0"),
+            Err(TypeErr::UnifyError(_, _, _))
+        ));
+
+        assert_eq!(
+            pp(&Type::List(Box::new(Type::Tuple(vec![
+                Type::Int,
+                Type::Float
+            ])))),
+            "(int * float) list"
+        );
+    }
+
+    #[test]
+    fn aborts() {
+        // `failwith`, `exit` and `assert false` can have any type, `assert e` is unit
+        assert!(check(
+            "let x = if true then 1 else failwith \"x\" in
+             let y = if true then 1.0 else assert false in
+             let (a, b) = (exit 1, exit 2) in
+             assert (x > 0); print_float (y +. a +. float_of_int b)"
+        )
+        .is_ok());
+
+        match check("let x = if true then 1 else assert true in ()") {
+            Err(TypeErr::UnifyError(Type::Int, Type::Unit, _)) => {}
+            other => panic!("{:?}", other),
+        }
+        match check("failwith 1") {
+            Err(TypeErr::UnifyError(Type::String, Type::Int, span)) => {
+                assert_eq!(span, Span::new(9, 10))
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            check("exit 1.0"),
+            Err(TypeErr::UnifyError(_, _, _))
+        ));
+        assert!(matches!(
+            check("assert 1"),
+            Err(TypeErr::UnifyError(_, _, _))
+        ));
+    }
+
+    #[test]
+    fn exceptions() {
+        // `raise` can have any type, handlers have the type of the body
+        assert!(check(
+            "exception E of int;; exception F;;
+             let x = try 1 + raise (E 1) with E n -> n | F -> 0 in
+             let y = try 1.0 with _ -> raise F in
+             let f = fun (e : exn) -> raise e in
+             print_float (f (E x) +. y)"
+        )
+        .is_ok());
+
+        match check("exception E;; try 1 with E -> 1.0") {
+            Err(TypeErr::UnifyError(Type::Int, Type::Float, _)) => {}
+            other => panic!("{:?}", other),
+        }
+        match check("raise 1") {
+            Err(TypeErr::UnifyError(Type::Variant(exn), Type::Int, _)) => {
+                assert_eq!(&*exn, EXN_TYPE)
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(check("exception E;; exception E of int;; raise (E 1)").is_ok());
+        assert!(matches!(
+            check("exception E;; exception E of int;; raise E"),
+            Err(TypeErr::CtorArity { .. })
+        ));
+        assert!(check("type t = A;; exception A;; let x = (A : exn) in ()").is_ok());
+        assert!(matches!(
+            check("exception E of t;; ()"),
+            Err(TypeErr::UnknownType(_, _))
+        ));
+    }
+
+    #[test]
+    fn and_or() {
+        assert!(check("let b = 1 < 2 && not (2 < 1) || false in ()").is_ok());
+
+        match check("let b = true && 1 in ()") {
+            Err(TypeErr::UnifyError(Type::Bool, Type::Int, span)) => {
+                assert_eq!(span, Span::new(16, 17))
+            }
+            other => panic!("{:?}", other),
+        }
+        // Not a desugared `&&`, error is reported at the `else` branch
+        match check("let b = if true then print_int 1 else false in ()") {
+            Err(TypeErr::UnifyError(Type::Unit, Type::Bool, span)) => {
+                assert_eq!(span, Span::new(38, 43))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn unconstrained_type_vars() {
+        let (expr, errs) = parse(&tokenize("let x = [] in let y = exit 1 in ()").unwrap());
+        assert!(errs.is_empty(), "{:?}", errs);
+        let mut ctx: Ctx = Default::default();
+        let mut expr = expr.intern(&mut ctx);
+        type_check_pgm(&mut ctx, &mut expr).unwrap();
+        let (x, y) = match &expr.kind {
+            ExprKind::Let { bndr: x, body, .. } => match &body.kind {
+                ExprKind::Let { bndr: y, .. } => (*x, *y),
+                other => panic!("{:?}", other),
+            },
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(*ctx.var_type(x), Type::List(Box::new(Type::Unit)));
+        assert_eq!(*ctx.var_type(y), Type::Unit);
+    }
+
+    #[test]
+    fn comparisons() {
+        assert!(check("let b = (1, \"a\") = (2, \"b\") && 1.0 < 2.0 in ()").is_ok());
+        // Comparisons on type variables are allowed, even when instantiated with variants
+        assert!(check("let rec eq x y = x = y in let b = eq [1] [1] in ()").is_ok());
+
+        match check("type t = A of float;; let b = A 1.0 = A 1.0 in ()") {
+            Err(TypeErr::InvalidComparison(Type::Variant(name), span)) => {
+                assert_eq!((&*name, span), ("t", Span::new(30, 43)))
             }
-            Var(var) => write!(w, "{}", var),
+            other => panic!("{:?}", other),
         }
+        assert!(matches!(
+            check("type p = { x : float };; let b = { x = 1.0 } <> { x = 1.0 } in ()"),
+            Err(TypeErr::InvalidComparison(Type::Record(_), _))
+        ));
+        assert!(matches!(
+            check("let b = [1] < [2] in ()"),
+            Err(TypeErr::InvalidComparison(Type::List(_), _))
+        ));
+        assert!(matches!(
+            check("type t = A | B of int;; let b = (A, 1) = (B 1, 1) in ()"),
+            Err(TypeErr::InvalidComparison(Type::Tuple(_), _))
+        ));
+        // Unlike OCaml, which compares the contents
+        assert!(matches!(
+            check("let b = ref 1 = ref 1 in ()"),
+            Err(TypeErr::InvalidComparison(Type::Ref(_), _))
+        ));
+        assert!(matches!(
+            check("let a = Array.make 1 [] in let b = a = a in ()"),
+            Err(TypeErr::InvalidComparison(Type::Array(_), _))
+        ));
+        // Type of the operands is not known at the comparison
+        assert!(matches!(
+            check("let b = (fun x -> x = x) [1] in ()"),
+            Err(TypeErr::InvalidComparison(Type::List(_), _))
+        ));
     }
 }
@@ -136,13 +136,12 @@ impl UserVar {
 #[derive(Debug, Clone)]
 pub struct GeneratedVar {
     name: Rc<str>,
-    phase: CompilerPhase,
     uniq: Uniq,
 }
 
 impl GeneratedVar {
     fn new(phase: CompilerPhase, uniq: Uniq) -> GeneratedVar {
-        GeneratedVar { name: format!("#{}_{}", phase.display_str(), uniq).into(), phase, uniq }
+        GeneratedVar { name: format!("#{}_{}", phase.display_str(), uniq).into(), uniq }
     }
 
     fn name(&self) -> Rc<str> {
@@ -179,6 +178,7 @@ impl GeneratedVar {
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum CompilerPhase {
     Parser,
+    TypeCheck,
     ANormal,
     ClosureConvert,
 }
@@ -188,6 +188,7 @@ impl CompilerPhase {
         use CompilerPhase::*;
         match self {
             Parser => "p",
+            TypeCheck => "tc",
             ANormal => "an",
             ClosureConvert => "cc",
         }